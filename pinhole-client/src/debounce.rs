@@ -0,0 +1,86 @@
+use std::collections::HashMap;
+
+/// Debounced actions waiting to be sent, by the field whose changes they
+/// follow. Each change starts a timer, and only the latest one for a field
+/// sends its action when it runs out.
+#[derive(Default)]
+pub struct Debouncer {
+    pending: HashMap<String, u64>,
+
+    /// Never reused, so a timer started before a `cancel` can't match one
+    /// started after it
+    last_generation: u64,
+}
+
+impl Debouncer {
+    /// Records a change to the field `id`, returning the generation to give
+    /// the timer started for it.
+    pub fn changed(&mut self, id: &str) -> u64 {
+        self.last_generation += 1;
+        self.pending.insert(id.to_string(), self.last_generation);
+        self.last_generation
+    }
+
+    /// Whether the timer for `id` that ran out is the latest one, in which
+    /// case its action should be sent now.
+    pub fn due(&mut self, id: &str, generation: u64) -> bool {
+        if self.pending.get(id) == Some(&generation) {
+            self.pending.remove(id);
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Forgets every pending action, e.g. because the page the fields were
+    /// on has been left.
+    pub fn cancel(&mut self) {
+        self.pending.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn a_burst_of_changes_sends_one_action() {
+        let mut debouncer = Debouncer::default();
+        let timers: Vec<u64> = (0..5).map(|_| debouncer.changed("search")).collect();
+
+        let due: Vec<bool> = timers.iter().map(|timer| debouncer.due("search", *timer)).collect();
+        assert_eq!(due, [false, false, false, false, true]);
+    }
+
+    #[test]
+    fn an_action_is_only_sent_once() {
+        let mut debouncer = Debouncer::default();
+        let timer = debouncer.changed("search");
+
+        assert!(debouncer.due("search", timer));
+        assert!(!debouncer.due("search", timer));
+    }
+
+    #[test]
+    fn fields_are_debounced_separately() {
+        let mut debouncer = Debouncer::default();
+        let search = debouncer.changed("search");
+        let name = debouncer.changed("name");
+
+        assert!(debouncer.due("name", name));
+        assert!(debouncer.due("search", search));
+    }
+
+    #[test]
+    fn cancelled_actions_are_never_sent() {
+        let mut debouncer = Debouncer::default();
+        let before = debouncer.changed("search");
+        debouncer.cancel();
+        assert!(!debouncer.due("search", before));
+
+        // A change on the next page doesn't bring the old timer back
+        let after = debouncer.changed("search");
+        assert!(!debouncer.due("search", before));
+        assert!(debouncer.due("search", after));
+    }
+}
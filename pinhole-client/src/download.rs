@@ -0,0 +1,140 @@
+use std::{
+    fs::OpenOptions,
+    io::{ErrorKind, Write},
+    path::{Path, PathBuf},
+};
+
+type Result<T> = std::result::Result<T, Box<dyn std::error::Error + Send + Sync>>;
+
+/// Saves a file sent by the server into the user's downloads folder and
+/// returns where it ended up. An existing file is never overwritten; a
+/// numbered name is picked instead. This blocks, so run it with
+/// `task::spawn_blocking`.
+pub fn save(filename: &str, bytes: &[u8]) -> Result<PathBuf> {
+    save_in(&downloads_dir(), filename, bytes)
+}
+
+fn save_in(directory: &Path, filename: &str, bytes: &[u8]) -> Result<PathBuf> {
+    validate_filename(filename)?;
+
+    let path = Path::new(filename);
+    let stem = path
+        .file_stem()
+        .and_then(|s| s.to_str())
+        .unwrap_or(filename);
+    let extension = path.extension().and_then(|s| s.to_str());
+
+    for attempt in 0.. {
+        let name = match (attempt, extension) {
+            (0, _) => filename.to_string(),
+            (n, Some(extension)) => format!("{} ({}).{}", stem, n, extension),
+            (n, None) => format!("{} ({})", stem, n),
+        };
+        let target = directory.join(name);
+
+        match OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(&target)
+        {
+            Ok(mut file) => {
+                file.write_all(bytes)?;
+                return Ok(target);
+            }
+            Err(err) if err.kind() == ErrorKind::AlreadyExists => continue,
+            Err(err) => return Err(err.into()),
+        }
+    }
+
+    unreachable!()
+}
+
+/// The server picks the name, so only accept a plain file name that can't
+/// point outside the downloads folder.
+fn validate_filename(filename: &str) -> Result<()> {
+    let valid = !filename.is_empty()
+        && !filename.starts_with('.')
+        && !filename.contains(['/', '\\', ':'])
+        && !filename.chars().any(char::is_control);
+
+    if valid {
+        Ok(())
+    } else {
+        Err(format!("Refusing to save download with unsafe name {:?}", filename).into())
+    }
+}
+
+fn downloads_dir() -> PathBuf {
+    if let Some(dir) = std::env::var_os("XDG_DOWNLOAD_DIR") {
+        return PathBuf::from(dir);
+    }
+
+    let home = std::env::var_os("HOME").or_else(|| std::env::var_os("USERPROFILE"));
+    match home {
+        Some(home) if Path::new(&home).join("Downloads").is_dir() => {
+            Path::new(&home).join("Downloads")
+        }
+        _ => PathBuf::from("."),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    /// An empty directory of its own for each test
+    fn scratch_dir(name: &str) -> PathBuf {
+        let directory = std::env::temp_dir().join(format!("pinhole-download-{}-{}", name, std::process::id()));
+        fs::remove_dir_all(&directory).ok();
+        fs::create_dir_all(&directory).unwrap();
+        directory
+    }
+
+    #[test]
+    fn plain_file_names_are_valid() {
+        for filename in ["report.csv", "receipt 2024.pdf", "notes", "a..b.txt"] {
+            assert!(validate_filename(filename).is_ok(), "{:?}", filename);
+        }
+    }
+
+    #[test]
+    fn names_that_could_escape_the_folder_are_rejected() {
+        for filename in ["", "..", ".bashrc", "a/b", "..\\b", "C:x", "/etc/passwd", "bell\u{7}.txt", "line\nbreak"] {
+            assert!(validate_filename(filename).is_err(), "{:?}", filename);
+        }
+    }
+
+    #[test]
+    fn a_small_download_is_saved_as_sent() {
+        let directory = scratch_dir("small");
+
+        let path = save_in(&directory, "report.csv", b"a,b\n1,2\n").unwrap();
+        assert_eq!(path, directory.join("report.csv"));
+        assert_eq!(fs::read(&path).unwrap(), b"a,b\n1,2\n");
+
+        fs::remove_dir_all(&directory).unwrap();
+    }
+
+    #[test]
+    fn existing_files_get_a_numbered_name_instead() {
+        let directory = scratch_dir("collision");
+
+        let first = save_in(&directory, "report.csv", b"first").unwrap();
+        let second = save_in(&directory, "report.csv", b"second").unwrap();
+        let third = save_in(&directory, "report.csv", b"third").unwrap();
+        assert_eq!(second, directory.join("report (1).csv"));
+        assert_eq!(third, directory.join("report (2).csv"));
+
+        // Nothing is overwritten
+        assert_eq!(fs::read(&first).unwrap(), b"first");
+        assert_eq!(fs::read(&second).unwrap(), b"second");
+
+        let untyped = save_in(&directory, "notes", b"").unwrap();
+        let renamed = save_in(&directory, "notes", b"").unwrap();
+        assert_eq!(untyped, directory.join("notes"));
+        assert_eq!(renamed, directory.join("notes (1)"));
+
+        fs::remove_dir_all(&directory).unwrap();
+    }
+}
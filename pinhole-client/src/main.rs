@@ -1,21 +1,39 @@
 #![recursion_limit = "1024"]
+mod debounce;
+mod download;
+mod focus;
 mod network;
+mod render_cache;
 mod stylesheet;
+mod toasts;
 mod ui_node;
 
 use async_std::task;
 use kv_log_macro as log;
 
 use iced::{
-    Application, Command,
-    widget::Container, Length, Settings, Subscription, Theme, alignment::{Horizontal, Vertical},
+    Application, Command, Event,
+    clipboard, event, keyboard, subscription, theme, time, window,
+    widget::{scrollable, text_input, Column, Container, Text}, Length, Settings, Subscription, Theme, alignment::{Horizontal, Vertical},
 };
 
-use network::{NetworkSession, NetworkSessionEvent, NetworkSessionSubscription};
-use pinhole_protocol::{action::Action, node::TextProps, storage::StateMap, storage::StateValue};
-use std::{collections::HashMap, sync::Arc};
+use debounce::Debouncer;
+use network::{
+    NetworkSession, NetworkSessionEvent, NetworkSessionOptions, NetworkSessionSubscription,
+};
+use pinhole_protocol::{
+    action::Action,
+    layout::Position,
+    messages::Directive,
+    network::WireFormat,
+    node::{TextProps, ToastSeverity},
+    storage::StateMap,
+    storage::StateValue,
+};
+use std::{sync::Arc, time::Duration};
 use stylesheet::Stylesheet;
-use ui_node::UiNode;
+use toasts::Toasts;
+use ui_node::{is_number_text_key, is_validation_error_key, number_text_key, validation_error_key, UiNode};
 
 #[derive(Clone, Default)]
 pub enum ButtonState {
@@ -31,26 +49,147 @@ pub enum CheckboxState {
     Unchecked
 }
 
+#[derive(Clone, Default)]
+pub struct SpinnerState {
+    pub frame: usize,
+}
+
 #[derive(Clone, Default)]
 pub struct TextInputState {
 //    text: String
+    /// Set when the input is inside a form, to submit it on Enter
+    pub on_submit: Option<Action>,
 }
 
+const WINDOW_SIZE: (u32, u32) = (600, 400);
+
+/// Server to connect to when neither `--server` nor `PINHOLE_SERVER` is set
+const DEFAULT_SERVER_ADDRESS: &str = "127.0.0.1:8080";
+
+/// How long the window must stay the same size before the server is told
+const VIEWPORT_DEBOUNCE: Duration = Duration::from_millis(250);
+
+/// Time between spinner animation frames
+const SPINNER_INTERVAL: Duration = Duration::from_millis(150);
+
 fn main() -> iced::Result {
     femme::with_level(::log::LevelFilter::Info);
 
     log::info!("📌 Pinhole starting up...");
 
+    let flags = match Flags::from_args() {
+        Ok(flags) => flags,
+        Err(message) => {
+            eprintln!("{}", message);
+            std::process::exit(2);
+        }
+    };
+
     Pinhole::run(Settings {
         window: iced::window::Settings {
-            size: (600, 400),
+            size: WINDOW_SIZE,
             ..Default::default()
         },
         default_text_size: 14.,
-        ..Default::default()
+        ..Settings::with_flags(flags)
     })
 }
 
+#[derive(Debug, Default)]
+pub struct Flags {
+    /// With `--frame-width <px>`, the document is drawn in a frame this wide
+    /// centred in the window, e.g. to preview a phone-sized layout.
+    frame_width: Option<u32>,
+
+    /// `host:port` of the server, from `--server <address>` or else the
+    /// `PINHOLE_SERVER` environment variable.
+    server_address: Option<String>,
+
+    /// With `--login-path <path>`, the page to load when the server says
+    /// the user must sign in.
+    login_path: Option<String>,
+
+    /// With `--json`, messages are exchanged as JSON instead of CBOR if the
+    /// server supports it, to make them readable when debugging.
+    json: bool,
+}
+
+impl Flags {
+    fn from_args() -> Result<Flags, String> {
+        let mut flags = Flags {
+            server_address: std::env::var("PINHOLE_SERVER").ok(),
+            ..Flags::default()
+        };
+        let mut args = std::env::args().skip(1);
+
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--server" => match args.next() {
+                    Some(address) => flags.server_address = Some(address),
+                    None => return Err("--server expects an address as host:port".to_string()),
+                },
+                "--login-path" => match args.next() {
+                    Some(path) => flags.login_path = Some(path),
+                    None => return Err("--login-path expects a path such as /login".to_string()),
+                },
+                "--json" => flags.json = true,
+                "--frame-width" => match args.next().map(|width| width.parse()) {
+                    Some(Ok(width)) => flags.frame_width = Some(width),
+                    _ => return Err("--frame-width expects a width in pixels".to_string()),
+                },
+                _ => log::warn!("Ignoring unknown argument {:?}", arg),
+            }
+        }
+
+        if let Some(address) = &flags.server_address {
+            validate_address(address)?;
+        }
+
+        Ok(flags)
+    }
+}
+
+/// Checks that `address` has the form `host:port`, so a typo fails straight
+/// away instead of being retried forever.
+fn validate_address(address: &str) -> Result<(), String> {
+    let invalid = |reason: &str| {
+        Err(format!(
+            "Invalid server address {:?}: {}. Expected host:port, e.g. {}",
+            address, reason, DEFAULT_SERVER_ADDRESS
+        ))
+    };
+
+    let Some((host, port)) = address.rsplit_once(':') else {
+        return invalid("no port");
+    };
+
+    // IPv6 addresses are bracketed, e.g. [::1]:8080, as otherwise the port
+    // can't be told apart from the rest of the address
+    let (host, bracketed) = match host.strip_prefix('[').and_then(|host| host.strip_suffix(']')) {
+        Some(host) => (host, true),
+        None => (host, false),
+    };
+
+    if host.is_empty() || host.contains(char::is_whitespace) || (!bracketed && host.contains(':')) {
+        invalid("bad host name")
+    } else if port.parse::<u16>().map_or(true, |port| port == 0) {
+        invalid("the port must be a number from 1 to 65535")
+    } else {
+        Ok(())
+    }
+}
+
+/// The user's preferred locale from the environment, as a language tag
+/// such as `en-US`.
+fn system_locale() -> Option<String> {
+    ["LC_ALL", "LC_MESSAGES", "LANG"]
+        .iter()
+        .filter_map(|name| std::env::var(name).ok())
+        .find(|value| !value.is_empty())
+        .map(|value| value.split(['.', '@']).next().unwrap_or_default().replace('_', "-"))
+        .filter(|locale| !locale.is_empty() && locale != "C" && locale != "POSIX")
+}
+
 #[derive(Debug, Clone)]
 pub enum PinholeMessage {
     StartNavigation(String),
@@ -62,44 +201,151 @@ pub enum PinholeMessage {
         value: StateValue,
         action: Option<Action>,
     },
+    /// Like `FormValueChanged`, but the action waits until there have been
+    /// no further changes to the field for `after`
+    FormValueChangedDebounced {
+        id: String,
+        value: StateValue,
+        action: Action,
+        after: Duration,
+    },
+    DebouncedActionDue {
+        id: String,
+        generation: u64,
+        action: Action,
+    },
+    /// Turns what's been typed into number inputs into their values
+    CommitNumberInputs,
+    TabPressed {
+        backwards: bool,
+    },
+    MoveFocus {
+        from: Option<iced_native::widget::Id>,
+        backwards: bool,
+    },
+    WindowResized {
+        width: u32,
+        height: u32,
+    },
+    ViewportSettled(u64),
+    ToastExpired(u64),
+    ReloadDue(u64),
+    SpinnerTick,
 }
 
 struct Pinhole {
     network_session: Arc<NetworkSession>,
     document: UiNode,
     context: UiContext,
+    viewport: (u32, u32),
+    viewport_generation: u64,
+    frame_width: Option<u32>,
+    toasts: Toasts,
+    disconnected: bool,
+
+    /// How long until the next attempt to connect, while the server can't
+    /// be reached
+    reconnecting_in: Option<Duration>,
+
+    error: Option<String>,
+
+    /// Bumped whenever a scheduled reload should no longer happen
+    reload_generation: u64,
+
+    /// So only the last of a burst of debounced actions is sent
+    debouncer: Debouncer,
+}
+
+impl Pinhole {
+    /// Tells the server how much room the document has, which is the frame
+    /// rather than the window when one is set.
+    fn report_viewport(&self) {
+        let (width, height) = self.viewport;
+        let width = self.frame_width.map_or(width, |frame| frame.min(width));
+
+        if let Err(err) = self.network_session.viewport(width, height) {
+            log::error!("Failed to report viewport: {:?}", err);
+        }
+    }
+
+    /// Sends `action`, after committing what's been typed into number inputs
+    /// so it goes with it.
+    fn perform(&mut self, action: &Action) {
+        self.commit_number_inputs();
+        self.send_action(action);
+    }
+
+    /// Turns what's been typed into number inputs into their values, and
+    /// fires their `on_change` actions.
+    fn commit_number_inputs(&mut self) {
+        for action in self.document.commit_number_inputs(&mut self.context.state_map) {
+            self.send_action(&action);
+        }
+    }
+
+    fn send_action(&self, action: &Action) {
+        if let Err(err) = task::block_on(
+            self.network_session
+                .action(action, &self.context.state_map),
+        ) {
+            log::error!("Failed to perform action: {:?}", err);
+        }
+    }
 }
 
 #[derive(Clone)]
 struct UiContext {
-    button_state: HashMap<String, ButtonState>,
-    text_input_state: HashMap<String, TextInputState>,
     state_map: StateMap,
 }
 
 impl Application for Pinhole {
     type Executor = iced::executor::Default;
     type Message = PinholeMessage;
-    type Flags = ();
+    type Flags = Flags;
     type Theme = Theme;
 
-    fn new(_flags: Self::Flags) -> (Self, iced::Command<Self::Message>) {
-        let address = "127.0.0.1:8080".to_string();
-        let network_session = NetworkSession::new(address);
+    fn new(flags: Self::Flags) -> (Self, iced::Command<Self::Message>) {
+        let address = flags
+            .server_address
+            .clone()
+            .unwrap_or_else(|| DEFAULT_SERVER_ADDRESS.to_string());
+        let options = NetworkSessionOptions {
+            unauthorized_path: flags.login_path.clone(),
+            wire_format: if flags.json { WireFormat::Json } else { WireFormat::Cbor },
+            ..NetworkSessionOptions::default()
+        };
+        let network_session = NetworkSession::new(address, options);
         let document = UiNode::Text(TextProps {
             text: "Loading...".to_string(),
+            align: Position::Start,
         });
 
-        (
-            Pinhole {
-                network_session: Arc::new(network_session),
-                document,
-                context: UiContext {
-                    state_map: StateMap::new(),
-                    button_state: HashMap::new(),
-                    text_input_state: HashMap::new(),
-                },
+        let pinhole = Pinhole {
+            network_session: Arc::new(network_session),
+            document,
+            context: UiContext {
+                state_map: StateMap::new(),
             },
+            viewport: WINDOW_SIZE,
+            viewport_generation: 0,
+            frame_width: flags.frame_width,
+            toasts: Toasts::default(),
+            disconnected: false,
+            reconnecting_in: None,
+            error: None,
+            reload_generation: 0,
+            debouncer: Debouncer::default(),
+        };
+        pinhole.report_viewport();
+
+        if let Some(locale) = system_locale() {
+            if let Err(err) = pinhole.network_session.locale(&locale) {
+                log::error!("Failed to report locale: {:?}", err);
+            }
+        }
+
+        (
+            pinhole,
             Command::perform(async { "/".to_string() }, PinholeMessage::StartNavigation),
         )
     }
@@ -109,17 +355,48 @@ impl Application for Pinhole {
     }
 
     fn subscription(&self) -> Subscription<Self::Message> {
-        Subscription::from_recipe(NetworkSessionSubscription::new(
-            self.network_session.clone(),
-        ))
-        .map(PinholeMessage::NetworkSessionEvent)
+        let mut subscriptions = vec![
+            Subscription::from_recipe(NetworkSessionSubscription::new(
+                self.network_session.clone(),
+            ))
+            .map(PinholeMessage::NetworkSessionEvent),
+            subscription::events_with(|event, status| match (event, status) {
+                (
+                    Event::Keyboard(keyboard::Event::KeyPressed {
+                        key_code: keyboard::KeyCode::Tab,
+                        modifiers,
+                    }),
+                    event::Status::Ignored,
+                ) => Some(PinholeMessage::TabPressed {
+                    backwards: modifiers.shift(),
+                }),
+                (Event::Window(window::Event::Resized { width, height }), _) => {
+                    Some(PinholeMessage::WindowResized { width, height })
+                }
+                _ => None,
+            }),
+        ];
+
+        // Only tick while there's something to animate
+        if self.document.has_spinner() {
+            subscriptions.push(time::every(SPINNER_INTERVAL).map(|_| PinholeMessage::SpinnerTick));
+        }
+
+        Subscription::batch(subscriptions)
     }
 
     fn update(&mut self, message: Self::Message) -> iced::Command<Self::Message> {
         let mut command = Command::none();
         match message {
             PinholeMessage::StartNavigation(path) => {
-                self.network_session.load(&path);
+                self.reload_generation += 1;
+                self.debouncer.cancel();
+                self.context
+                    .state_map
+                    .retain(|key, _| !is_validation_error_key(key) && !is_number_text_key(key));
+                if let Err(err) = self.network_session.load(&path) {
+                    log::error!("Failed to start navigation: {:?}", err);
+                }
                 command = Command::perform(async {}, |_| PinholeMessage::LoadStarted)
             }
             PinholeMessage::LoadStarted => {
@@ -129,23 +406,188 @@ impl Application for Pinhole {
                 NetworkSessionEvent::DocumentUpdated(document) => {
                     log::info!("Document updated", { document: format!("{:?}", document) });
                     self.document = document.0.into();
+                    self.document.apply_form_values(&mut self.context.state_map);
+
+                    let mut commands = vec![self.toasts.update(self.document.toasts())];
+                    if let Some(id) = self.document.autofocus() {
+                        commands.push(text_input::focus(text_input::Id::new(id)));
+                    }
+                    command = Command::batch(commands);
+                    self.disconnected = false;
+                    self.reconnecting_in = None;
+                    self.error = None;
+                }
+                NetworkSessionEvent::DocumentUnchanged => {
+                    self.disconnected = false;
+                    self.reconnecting_in = None;
+                    self.error = None;
+                }
+                NetworkSessionEvent::Patch { id, node } => match self.document.slot_mut(&id) {
+                    Some(slot) => {
+                        *slot = node.into();
+                        slot.apply_form_values(&mut self.context.state_map);
+                        command = self.toasts.update(self.document.toasts());
+                    }
+                    // The document has moved on since the patch was sent
+                    None => log::debug!("Ignoring patch for missing slot", { id: id }),
+                },
+                NetworkSessionEvent::Data(fields) => {
+                    for (key, value) in fields {
+                        match self.context.state_map.get_mut(&key) {
+                            Some(existing) => existing.merge(value),
+                            None => {
+                                self.context.state_map.insert(key, value);
+                            }
+                        }
+                    }
+                }
+                NetworkSessionEvent::ValidationErrors(errors) => {
+                    let state_map = &mut self.context.state_map;
+                    state_map.retain(|key, _| !is_validation_error_key(key));
+
+                    for (id, message) in errors {
+                        state_map.insert(validation_error_key(&id), StateValue::String(message));
+                    }
+                }
+                NetworkSessionEvent::Notify { severity, text } => {
+                    command = self.toasts.notify(severity, text);
+                }
+                NetworkSessionEvent::Disconnected => {
+                    log::warn!("Disconnected from server");
+                    self.disconnected = true;
+                }
+                NetworkSessionEvent::Reconnecting(delay) => {
+                    self.disconnected = true;
+                    self.reconnecting_in = Some(delay);
+                }
+                NetworkSessionEvent::Error(message) => {
+                    self.error = Some(message);
+                }
+                NetworkSessionEvent::Directive(directive) => match directive {
+                    Directive::CopyToClipboard(text) => command = clipboard::write(text),
+                    Directive::FocusInput(id) => command = text_input::focus(text_input::Id::new(id)),
+                    Directive::ScrollTo(id) => {
+                        let scrolls = self.document.scroll_to(&id);
+                        if scrolls.is_empty() {
+                            log::warn!("Nothing to scroll into view", { id: id });
+                        }
+
+                        command = Command::batch(
+                            scrolls
+                                .into_iter()
+                                .map(|(id, offset)| scrollable::snap_to(id, offset)),
+                        );
+                    }
+                    Directive::Unknown => {}
+                },
+                NetworkSessionEvent::ReloadScheduled(after) => {
+                    self.reload_generation += 1;
+
+                    let generation = self.reload_generation;
+                    command = Command::perform(task::sleep(after), move |_| {
+                        PinholeMessage::ReloadDue(generation)
+                    });
                 }
             },
             PinholeMessage::PerformAction(action) => {
-                task::block_on(
-                    self.network_session
-                        .action(&action, &self.context.state_map),
-                );
+                self.perform(&action);
             }
             PinholeMessage::FormValueChanged { id, value, action } => {
                 log::info!("Form value changed", { id: id, value: value, action: action });
+                // Whatever was wrong with the old value may be fixed now, and
+                // a number set by its step buttons replaces any typed text
+                self.context.state_map.remove(&validation_error_key(&id));
+                self.context.state_map.remove(&number_text_key(&id));
                 self.context.state_map.insert(id, value);
 
                 if let Some(action) = action {
-                    task::block_on(
-                        self.network_session
-                            .action(&action, &self.context.state_map),
-                    );
+                    self.perform(&action);
+                }
+            }
+            PinholeMessage::FormValueChangedDebounced {
+                id,
+                value,
+                action,
+                after,
+            } => {
+                self.context.state_map.remove(&validation_error_key(&id));
+                self.context.state_map.insert(id.clone(), value);
+
+                let generation = self.debouncer.changed(&id);
+                command = Command::perform(task::sleep(after), move |_| {
+                    PinholeMessage::DebouncedActionDue {
+                        id,
+                        generation,
+                        action,
+                    }
+                });
+            }
+            PinholeMessage::DebouncedActionDue {
+                id,
+                generation,
+                action,
+            } => {
+                // Unless the field changed again since, and a later one will
+                // fire, or the page has been left
+                if self.debouncer.due(&id, generation) {
+                    self.perform(&action);
+                }
+            }
+            PinholeMessage::CommitNumberInputs => {
+                self.commit_number_inputs();
+            }
+            PinholeMessage::TabPressed { backwards } => {
+                // Leaving a number input commits it
+                self.commit_number_inputs();
+                command = Command::widget(focus::find_focused())
+                    .map(move |from| PinholeMessage::MoveFocus { from, backwards });
+            }
+            PinholeMessage::MoveFocus { from, backwards } => {
+                let order = self.document.focus_order();
+                let current = from.and_then(|from| {
+                    order.iter().position(|id| {
+                        iced_native::widget::Id::from(text_input::Id::new(id.clone())) == from
+                    })
+                });
+
+                let next = match (current, backwards) {
+                    (None, false) => order.first(),
+                    (None, true) => order.last(),
+                    (Some(index), false) => order.get((index + 1) % order.len()),
+                    (Some(index), true) => order.get((index + order.len() - 1) % order.len()),
+                };
+
+                if let Some(id) = next {
+                    command = text_input::focus(text_input::Id::new(id.clone()));
+                }
+            }
+            PinholeMessage::WindowResized { width, height } => {
+                self.viewport = (width, height);
+                self.viewport_generation += 1;
+
+                let generation = self.viewport_generation;
+                command = Command::perform(task::sleep(VIEWPORT_DEBOUNCE), move |_| {
+                    PinholeMessage::ViewportSettled(generation)
+                });
+            }
+            PinholeMessage::ReloadDue(generation) => {
+                // Superseded by a newer schedule or by navigating away
+                if generation == self.reload_generation {
+                    if let Err(err) = self.network_session.reload() {
+                        log::error!("Failed to reload: {:?}", err);
+                    }
+                }
+            }
+            PinholeMessage::SpinnerTick => {
+                self.document.advance_spinners();
+            }
+            PinholeMessage::ToastExpired(serial) => {
+                self.toasts.dismiss(serial);
+            }
+            PinholeMessage::ViewportSettled(generation) => {
+                // A later resize is still pending, so let that one report
+                if generation == self.viewport_generation {
+                    self.report_viewport();
                 }
             }
         }
@@ -153,13 +595,87 @@ impl Application for Pinhole {
         command
     }
 
-    fn view(&self) -> iced::Element<Self::Message> {
+    fn view(&self) -> iced::Element<'_, Self::Message> {
         let stylesheet = Stylesheet;
-        Container::new(self.document.view(&stylesheet, &self.context.state_map))
-            .width(Length::Fill)
+        let document = Container::new(self.document.view(&stylesheet, &self.context.state_map))
+            .width(match self.frame_width {
+                Some(width) => Length::Fixed(width as f32),
+                None => Length::Fill,
+            })
             .height(Length::Fill)
             .align_x(Horizontal::Center)
-            .align_y(Vertical::Center)
-            .into()
+            .align_y(Vertical::Center);
+
+        let frame = Container::new(document)
+            .width(Length::Fill)
+            .height(Length::Fill)
+            .center_x();
+
+        let mut children = Vec::new();
+        if self.disconnected {
+            let text = match self.reconnecting_in {
+                Some(delay) => format!("Disconnected — retrying in {}s...", delay.as_secs_f32().ceil()),
+                None => "Disconnected — retrying...".to_string(),
+            };
+            children.push(banner(&stylesheet, &text, ToastSeverity::Warning));
+        }
+        if let Some(error) = &self.error {
+            children.push(banner(&stylesheet, error, ToastSeverity::Error));
+        }
+        children.push(self.toasts.view(&stylesheet));
+        children.push(frame.into());
+
+        Column::with_children(children).into()
+    }
+}
+
+fn banner<'a>(
+    stylesheet: &Stylesheet,
+    text: &str,
+    severity: ToastSeverity,
+) -> iced::Element<'a, PinholeMessage> {
+    Container::new(Text::new(text.to_string()))
+        .width(Length::Fill)
+        .padding(8)
+        .style(theme::Container::Custom(Box::new(
+            stylesheet.toast_style(severity),
+        )))
+        .into()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn validate_address_accepts_host_and_port() {
+        assert_eq!(validate_address("localhost:8080"), Ok(()));
+        assert_eq!(validate_address("127.0.0.1:1"), Ok(()));
+        assert_eq!(validate_address("example.com:65535"), Ok(()));
+        assert_eq!(validate_address("[::1]:8080"), Ok(()));
+        assert_eq!(validate_address(DEFAULT_SERVER_ADDRESS), Ok(()));
+    }
+
+    #[test]
+    fn validate_address_rejects_a_missing_port() {
+        let err = validate_address("localhost").unwrap_err();
+        assert!(err.contains("no port"), "{}", err);
+        assert!(err.contains(DEFAULT_SERVER_ADDRESS), "{}", err);
+    }
+
+    #[test]
+    fn validate_address_rejects_bad_hosts() {
+        for address in [":8080", "[]:8080", "local host:8080", " :8080", "::1:8080", "localhost:80:8080"] {
+            let err = validate_address(address).unwrap_err();
+            assert!(err.contains("bad host name"), "{}: {}", address, err);
+        }
+    }
+
+    #[test]
+    fn validate_address_rejects_bad_ports() {
+        for address in ["localhost:", "localhost:0", "localhost:65536", "localhost:http", "localhost:-1"] {
+            let err = validate_address(address).unwrap_err();
+            assert!(err.contains("port must be a number"), "{}: {}", address, err);
+        }
     }
 }
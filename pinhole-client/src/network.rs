@@ -1,5 +1,5 @@
 use async_std::{
-    net::TcpStream,
+    net::{Shutdown, TcpStream},
     channel::{self, Receiver, Sender},
     task,
 };
@@ -7,26 +7,43 @@ use futures::{select, stream::BoxStream, FutureExt};
 
 use kv_log_macro as log;
 
+use crate::{download, render_cache::RenderCache};
+
 use pinhole_protocol::{
     action::Action,
     document::Document,
-    messages::{ClientToServerMessage, ServerToClientMessage},
-    network::{receive_response, send_request},
+    layout::Layout,
+    messages::{ClientToServerMessage, Directive, ErrorCode, RenderMeta, ServerToClientMessage},
+    node::{Node, ToastSeverity},
+    network::{receive_response, send_request, supported_capabilities, Compression, NetworkLimits, DEFAULT_MAX_DOCUMENT_DEPTH, WireFormat, DEFLATE, JSON, PING},
     storage::StateMap,
+    storage::StateValue,
     storage::StorageScope,
 };
-use std::{collections::HashMap, sync::Arc, time::Duration};
+use std::{collections::{HashMap, VecDeque}, sync::Arc, time::{Duration, Instant}};
 
 type Result<T> = std::result::Result<T, Box<dyn std::error::Error + Send + Sync>>;
 
+/// How long a prefetched document is kept when the server didn't say
+const PREFETCH_MAX_AGE: Duration = Duration::from_secs(30);
+
+/// How long the connection may sit idle before the server is pinged
+const PING_INTERVAL: Duration = Duration::from_secs(15);
+
+/// How long to wait for a `Pong` before giving up on the connection
+const PONG_TIMEOUT: Duration = Duration::from_secs(10);
+
 #[derive(Debug)]
 pub enum NetworkSessionCommand {
     Action { action: Action, state_map: StateMap },
     Load { path: String },
+    Viewport { width: u32, height: u32 },
+    Locale { locale: String },
+    Reload,
 }
 
 impl ::log::kv::ToValue for NetworkSessionCommand {
-    fn to_value(&self) -> ::log::kv::Value {
+    fn to_value(&self) -> ::log::kv::Value<'_> {
         ::log::kv::Value::from_debug(self)
     }
 }
@@ -34,29 +51,150 @@ impl ::log::kv::ToValue for NetworkSessionCommand {
 #[derive(Debug, Clone)]
 pub enum NetworkSessionEvent {
     DocumentUpdated(Document),
+
+    /// The server has nothing newer than the document on screen.
+    DocumentUnchanged,
+
+    /// The server closed the connection. The session keeps trying to
+    /// reconnect, and reloads the current path once it does.
+    Disconnected,
+
+    /// Something went wrong that the user should know about.
+    Error(String),
+
+    /// The server wants the current path loaded again after this long.
+    ReloadScheduled(Duration),
+
+    /// The server filled in the slot `id` of the current document.
+    Patch { id: String, node: Node },
+
+    /// The server wants the app to carry out a client-side effect.
+    Directive(Directive),
+
+    /// Values an action answered with, to merge into the state map.
+    Data(HashMap<String, StateValue>),
+
+    /// Messages to show beside form fields, keyed by field id.
+    ValidationErrors(HashMap<String, String>),
+
+    /// A brief message to show above the document.
+    Notify { severity: ToastSeverity, text: String },
+
+    /// Connecting to the server failed, and will be tried again after this
+    /// long.
+    Reconnecting(Duration),
+}
+
+#[derive(Clone, Debug)]
+pub struct NetworkSessionOptions {
+    /// Disable Nagle's algorithm, which keeps small action messages from
+    /// being held back.
+    pub nodelay: bool,
+
+    /// How many redirects in a row the session follows before giving up and
+    /// reporting a redirect loop.
+    pub max_redirects: usize,
+
+    /// Bounds on the messages the server may send. Each container in a
+    /// document adds a few levels of nesting.
+    pub limits: NetworkLimits,
+
+    /// How long to wait between attempts to connect while the server can't
+    /// be reached.
+    pub reconnect: ReconnectPolicy,
+
+    /// Where to go when the server says the user must sign in, e.g.
+    /// `/login`. Without one the server's message is shown instead.
+    pub unauthorized_path: Option<String>,
+
+    /// How to ask the server to encode messages. JSON is easier to read in
+    /// a packet capture; CBOR is used until the server agrees to it.
+    pub wire_format: WireFormat,
+
+    /// Largest file the server may send in parts, in bytes. Files sent in one
+    /// message are already bounded by `limits`.
+    pub max_download_size: usize,
+}
+
+impl Default for NetworkSessionOptions {
+    fn default() -> Self {
+        NetworkSessionOptions {
+            nodelay: true,
+            max_redirects: 10,
+            limits: NetworkLimits {
+                max_depth: DEFAULT_MAX_DOCUMENT_DEPTH,
+                ..NetworkLimits::default()
+            },
+            reconnect: ReconnectPolicy::default(),
+            unauthorized_path: None,
+            wire_format: WireFormat::Cbor,
+            max_download_size: 256 * 1024 * 1024,
+        }
+    }
+}
+
+/// Exponential backoff between attempts to connect, so a server that's down
+/// isn't hammered by every client at once.
+#[derive(Clone, Debug)]
+pub struct ReconnectPolicy {
+    /// The wait after the first failed attempt
+    pub initial_delay: Duration,
+
+    /// The longest wait, however many attempts have failed
+    pub max_delay: Duration,
+
+    /// How many times longer each wait is than the one before
+    pub multiplier: f64,
+
+    /// Up to this fraction of each wait, between 0 and 1, is randomly taken
+    /// off, so clients that lost the server together don't retry together
+    pub jitter: f64,
+}
+
+impl Default for ReconnectPolicy {
+    fn default() -> Self {
+        ReconnectPolicy {
+            initial_delay: Duration::from_millis(500),
+            max_delay: Duration::from_secs(30),
+            multiplier: 2.,
+            jitter: 0.2,
+        }
+    }
+}
+
+impl ReconnectPolicy {
+    /// The wait after `failures` failed attempts in a row, before jitter.
+    pub fn delay(&self, failures: u32) -> Duration {
+        let exponent = i32::try_from(failures.saturating_sub(1)).unwrap_or(i32::MAX);
+        let factor = self.multiplier.powi(exponent);
+
+        Duration::try_from_secs_f64(self.initial_delay.as_secs_f64() * factor)
+            .unwrap_or(self.max_delay)
+            .min(self.max_delay)
+    }
+
+    /// `delay` with jitter applied.
+    fn jittered_delay(&self, failures: u32) -> Duration {
+        let jitter = self.jitter.clamp(0., 1.) * fastrand::f64();
+        self.delay(failures).mul_f64(1. - jitter)
+    }
 }
 
 #[derive(Clone)]
 pub struct NetworkSession {
-    address: String,
     command_sender: Sender<NetworkSessionCommand>,
     event_receiver: Receiver<NetworkSessionEvent>,
 }
 
 impl NetworkSession {
-    pub fn new(address: String) -> NetworkSession {
+    /// Connects to `address` in the background.
+    pub fn new(address: String, options: NetworkSessionOptions) -> NetworkSession {
         let (command_sender, command_receiver) = channel::bounded::<NetworkSessionCommand>(10);
         let (event_sender, event_receiver) = channel::bounded::<NetworkSessionEvent>(10);
 
-        let address = address.clone();
-        task::spawn(session_loop(
-            address.clone(),
-            command_receiver,
-            event_sender,
-        ));
+        task::spawn(session_loop(address, options, command_receiver, event_sender));
 
         NetworkSession {
-            address,
             command_sender,
             event_receiver,
         }
@@ -83,6 +221,31 @@ impl NetworkSession {
 
         Ok(())
     }
+
+    pub fn viewport(&self, width: u32, height: u32) -> Result<()> {
+        task::block_on(
+            self.command_sender
+                .send(NetworkSessionCommand::Viewport { width, height }))?;
+
+        Ok(())
+    }
+
+    /// Loads the current path again, if there is one.
+    pub fn reload(&self) -> Result<()> {
+        task::block_on(self.command_sender.send(NetworkSessionCommand::Reload))?;
+
+        Ok(())
+    }
+
+    pub fn locale(&self, locale: &str) -> Result<()> {
+        let locale = locale.to_string();
+
+        task::block_on(
+            self.command_sender
+                .send(NetworkSessionCommand::Locale { locale }))?;
+
+        Ok(())
+    }
 }
 
 #[derive(Clone)]
@@ -115,40 +278,308 @@ where
     }
 }
 
+fn store(session_storage: &mut HashMap<String, String>, scope: StorageScope, key: String, value: String) {
+    match scope {
+        StorageScope::Session => { session_storage.insert(key, value); },
+
+        // Only session storage is kept so far
+        _ => log::warn!("Ignoring a value for storage the client doesn't keep", { scope: format!("{:?}", scope), key: key }),
+    }
+}
+
+/// Whether following a redirect to `path` would go round in circles: it
+/// comes back to a path in `chain`, the paths visited since the last render,
+/// or makes more than `max_redirects` in a row.
+fn is_redirect_loop(chain: &[String], path: &str, max_redirects: usize) -> bool {
+    chain.iter().any(|visited| visited == path) || chain.len() > max_redirects
+}
+
+/// The version of the document on screen, if it's the one for `path`.
+fn known_version(shown: &Option<(String, u64)>, path: &str) -> Option<u64> {
+    shown
+        .as_ref()
+        .filter(|(shown_path, _)| shown_path == path)
+        .map(|(_, version)| *version)
+}
+
+/// The last request the server may still be working on. The server handles
+/// requests one at a time, so a slow route holds up the answer to a ping
+/// sent behind it, and the connection shouldn't be given up on meanwhile.
+#[derive(Debug, Default)]
+struct Outstanding {
+    request: Option<u64>,
+
+    /// `request` as it was when each unanswered ping was sent
+    pings: VecDeque<Option<u64>>,
+}
+
+impl Outstanding {
+    fn request_sent(&mut self, request_id: Option<u64>) {
+        self.request = request_id;
+    }
+
+    fn ping_sent(&mut self) {
+        self.pings.push_back(self.request);
+    }
+
+    /// An answer to the request with `request_id`, or to whichever request
+    /// is being handled if it doesn't say.
+    fn answered(&mut self, request_id: Option<u64>) {
+        if request_id.is_none() || request_id == self.request {
+            self.request = None;
+        }
+    }
+
+    /// Pongs come back in order, once everything sent before their ping has
+    /// been handled.
+    fn pong_received(&mut self) {
+        if let Some(request) = self.pings.pop_front() {
+            if request == self.request {
+                self.request = None;
+            }
+        }
+    }
+
+    fn busy(&self) -> bool {
+        self.request.is_some()
+    }
+}
+
+/// Turns the server asking for a signed-in user into a redirect to the
+/// sign-in page, if there is one. Signing in is then handled like any other
+/// redirect, so a sign-in page that itself needs a user is caught as a loop.
+fn sign_in_redirect(message: ServerToClientMessage, unauthorized_path: Option<&str>) -> ServerToClientMessage {
+    match (message, unauthorized_path) {
+        (ServerToClientMessage::Error { code: ErrorCode::Unauthorized, request_id, .. }, Some(path)) => {
+            log::info!("Server asked the user to sign in", { path: path });
+            ServerToClientMessage::RedirectTo { path: path.to_string(), request_id }
+        }
+        (message, _) => message,
+    }
+}
+
+/// The parts of a document the server is sending piece by piece.
+#[derive(Debug, Default)]
+struct StreamedDocument(Option<(Layout, Vec<Node>, Option<RenderMeta>)>);
+
+impl StreamedDocument {
+    /// Keeps a part of a streamed document, giving back the whole document
+    /// as a `Render` once its last part arrives, since it's handled like any
+    /// other then. Other messages are given back as they are.
+    fn receive(&mut self, message: ServerToClientMessage) -> Option<ServerToClientMessage> {
+        match message {
+            ServerToClientMessage::RenderBegin { layout, meta } => {
+                self.0 = Some((layout, Vec::new(), meta));
+                None
+            }
+            ServerToClientMessage::RenderAppend { node } => {
+                match &mut self.0 {
+                    Some((_, children, _)) => children.push(node),
+                    None => log::warn!("Ignoring part of a document that never began"),
+                }
+                None
+            }
+            ServerToClientMessage::RenderEnd => match self.0.take() {
+                Some((layout, children, meta)) => {
+                    let children = children.into_iter().map(Node::boxed).collect();
+                    let document = Document(Node::Container { layout, children });
+                    Some(ServerToClientMessage::Render { document, meta, request_id: None })
+                }
+                None => {
+                    log::warn!("Ignoring the end of a document that never began");
+                    None
+                }
+            },
+            message => Some(message),
+        }
+    }
+}
+
+/// Saves a file the server sent without holding up the session, and tells
+/// the user where it went, since the server chose to send it.
+async fn save_download(filename: String, mime: String, bytes: Vec<u8>, event_sender: &Sender<NetworkSessionEvent>) -> Result<()> {
+    let name = filename.clone();
+    let event = match task::spawn_blocking(move || download::save(&name, &bytes)).await {
+        Ok(path) => {
+            log::info!("Saved download", { path: format!("{:?}", path), mime: mime });
+            NetworkSessionEvent::Notify { severity: ToastSeverity::Info, text: format!("Downloaded {}", path.display()) }
+        }
+        Err(err) => {
+            log::error!("Failed to save download: {:?}", err);
+            NetworkSessionEvent::Error(format!("Couldn't save {}: {}", filename, err))
+        }
+    };
+
+    event_sender.send(event).await?;
+    Ok(())
+}
+
+/// Reads responses as they arrive and passes them on, until the connection
+/// closes or fails.
+async fn read_responses(
+    mut stream: TcpStream,
+    limits: NetworkLimits,
+    responses: Sender<Result<ServerToClientMessage>>,
+) {
+    while let Some(response) = receive_response(&mut stream, &limits).await.transpose() {
+        // After an error the stream may not be at the start of a message
+        let failed = response.is_err();
+        if responses.send(response.map(|(response, _size)| response)).await.is_err() || failed {
+            break;
+        }
+    }
+}
+
 async fn session_loop(
     address: String,
+    options: NetworkSessionOptions,
     command_receiver: Receiver<NetworkSessionCommand>,
     event_sender: Sender<NetworkSessionEvent>,
 ) -> Result<()> {
     let mut current_path: Option<String> = None;
     let mut session_storage = HashMap::new();
+    let mut viewport: Option<(u32, u32)> = None;
+    let mut locale: Option<String> = None;
+    let mut render_cache = RenderCache::default();
+
+    // Paths visited since the last render, to catch redirect loops
+    let mut redirect_chain: Vec<String> = Vec::new();
+
+    // The path and version of the document on screen, so the server needn't
+    // send it again if it hasn't changed
+    let mut shown: Option<(String, u64)> = None;
+
+    // Sent with each request and echoed in the answer, to match them up in
+    // the logs
+    let mut request_ids = 1u64..;
+
+    async fn connect(
+        address: &String,
+        options: &NetworkSessionOptions,
+        event_sender: &Sender<NetworkSessionEvent>,
+    ) -> Result<TcpStream> {
+        let mut failures = 0;
 
-    async fn connect(address: &String) -> Result<TcpStream> {
         loop {
             log::debug!("Trying to connect to {}", address);
             match TcpStream::connect(&address).await {
                 Ok(stream) => {
+                    // Only a latency tweak, so no reason to give up on the connection
+                    if let Err(err) = stream.set_nodelay(options.nodelay) {
+                        log::warn!("Failed to set TCP_NODELAY: {:?}", err);
+                    }
+
                     return Ok(stream);
                 }
                 Err(err) => {
-                    log::warn!("Error trying to connect (will retry in 1s): {:?}", err);
-                    task::sleep(Duration::from_millis(1000)).await;
+                    failures += 1;
+                    let delay = options.reconnect.jittered_delay(failures);
+                    log::warn!("Error trying to connect (will retry in {:?}): {:?}", delay, err);
+
+                    event_sender.send(NetworkSessionEvent::Reconnecting(delay)).await?;
+                    task::sleep(delay).await;
                 }
             }
         }
     }
 
+    // Connections in a row that dropped before delivering a document, so a
+    // server that keeps dropping them is backed off from like one that's down
+    let mut dropped = 0;
+
     'main: loop {
-        let mut stream: TcpStream = connect(&address).await?;
+        if dropped > 0 {
+            let delay = options.reconnect.jittered_delay(dropped);
+            log::info!("Reconnecting in {:?}", delay);
+            event_sender.send(NetworkSessionEvent::Reconnecting(delay)).await?;
+            task::sleep(delay).await;
+        }
+
+        let mut stream: TcpStream = connect(&address, &options, &event_sender).await?;
 
         log::info!("Connected to server");
 
-        if let Some(path) = current_path.clone() {
-            let storage = session_storage.clone();
-            send_request(&mut stream, ClientToServerMessage::Load { path, storage }).await?;
+        // Everything is sent uncompressed and as CBOR until the server says
+        // otherwise
+        let mut compression = Compression::None;
+        let mut format = WireFormat::Cbor;
+
+        // Older servers don't answer pings, so only ping those that say they do
+        let mut ping = false;
+        let mut last_heard = Instant::now();
+        let mut awaiting_pong = false;
+        let mut outstanding = Outstanding::default();
+
+        let mut streamed = StreamedDocument::default();
+
+        // The name, type and contents so far of a file sent in parts
+        let mut downloading: Option<(String, String, Vec<u8>)> = None;
+
+        let mut capabilities = supported_capabilities();
+        if options.wire_format != WireFormat::Json {
+            capabilities.retain(|capability| capability != JSON);
+        }
+
+        let greeting: Result<()> = async {
+            send_request(&mut stream, ClientToServerMessage::Capabilities { capabilities }, compression, format).await?;
+
+            if let Some((width, height)) = viewport {
+                send_request(&mut stream, ClientToServerMessage::Viewport { width, height }, compression, format).await?;
+            }
+
+            if let Some(locale) = locale.clone() {
+                send_request(&mut stream, ClientToServerMessage::Locale { locale }, compression, format).await?;
+            }
+
+            if let Some(path) = current_path.clone() {
+                let storage = session_storage.clone();
+                let version = known_version(&shown, &path);
+                let request_id = request_ids.next();
+                outstanding.request_sent(request_id);
+                send_request(&mut stream, ClientToServerMessage::Load { path, storage, version, request_id }, compression, format).await?;
+            }
+
+            Ok(())
+        }.await;
+
+        if let Err(err) = greeting {
+            log::warn!("Failed to send to server, reconnecting: {}", err);
+            dropped += 1;
+            event_sender.send(NetworkSessionEvent::Disconnected).await?;
+            continue 'main;
         }
 
+        // Responses are read on a task of their own, so one that's partly
+        // arrived isn't lost when a command or the ping timer is ready first
+        let (response_sender, responses) = channel::unbounded();
+        task::spawn(read_responses(stream.clone(), options.limits, response_sender));
+
         'connection: loop {
+            // Sends a request, or drops the connection if it can't be sent
+            macro_rules! send {
+                ($request:expr) => {{
+                    let request = $request;
+                    outstanding.request_sent(request.request_id().or(outstanding.request));
+                    if let Err(err) = send_request(&mut stream, request, compression, format).await {
+                        log::warn!("Failed to send to server, reconnecting: {}", err);
+                        break 'connection;
+                    }
+                }};
+            }
+
+            // While the server is busy with a request the pong can't come
+            // until it's done, however long the route takes
+            let timeout = if awaiting_pong { PONG_TIMEOUT } else { PING_INTERVAL };
+            let ping_due = last_heard + timeout;
+            let waiting = awaiting_pong && outstanding.busy();
+            let ping_timer = async {
+                if ping && !waiting {
+                    task::sleep(ping_due.saturating_duration_since(Instant::now())).await
+                } else {
+                    futures::future::pending().await
+                }
+            };
+
             select! {
               command = command_receiver.recv().fuse() => {
                 if let Ok(command) = command {
@@ -156,12 +587,51 @@ async fn session_loop(
                   match command {
                     NetworkSessionCommand::Action { action, state_map } => {
                       let path = current_path.clone().expect("Can't fire actions without a path set");
-                      send_request(&mut stream, ClientToServerMessage::Action { path, action, state_map }).await?;
+
+                      // The action may change what any page renders
+                      render_cache.clear();
+                      let storage = session_storage.clone();
+                      send!(ClientToServerMessage::Action { path, action, state_map, storage, request_id: request_ids.next() });
                     },
                     NetworkSessionCommand::Load { path } => {
+                      // Loading the page that's showing means the user wants it fresh
+                      let reload = current_path.as_ref() == Some(&path);
                       current_path = Some(path.clone());
+
+                      if let Some(document) = render_cache.get(&path).filter(|_| !reload) {
+                        log::debug!("Rendering from cache", { path: path });
+                        redirect_chain.clear();
+                        shown = None;
+                        event_sender.send(NetworkSessionEvent::DocumentUpdated(document)).await?;
+                        continue;
+                      }
+
+                      redirect_chain = vec![path.clone()];
                       let storage = session_storage.clone();
-                      send_request(&mut stream, ClientToServerMessage::Load { path, storage }).await?;
+                      let version = known_version(&shown, &path);
+                      send!(ClientToServerMessage::Load { path, storage, version, request_id: request_ids.next() });
+                    }
+                    NetworkSessionCommand::Reload => {
+                      if let Some(path) = current_path.clone() {
+                        let storage = session_storage.clone();
+                        let version = known_version(&shown, &path);
+                        send!(ClientToServerMessage::Load { path, storage, version, request_id: request_ids.next() });
+                      }
+                    }
+                    NetworkSessionCommand::Locale { locale: new_locale } => {
+                      locale = Some(new_locale.clone());
+                      send!(ClientToServerMessage::Locale { locale: new_locale });
+                    }
+                    NetworkSessionCommand::Viewport { width, height } => {
+                      viewport = Some((width, height));
+                      send!(ClientToServerMessage::Viewport { width, height });
+
+                      // Re-render so the route can adapt its layout to the new size
+                      if let Some(path) = current_path.clone() {
+                        let storage = session_storage.clone();
+                        let version = known_version(&shown, &path);
+                        send!(ClientToServerMessage::Load { path, storage, version, request_id: request_ids.next() });
+                      }
                     }
                   }
                 } else {
@@ -169,33 +639,380 @@ async fn session_loop(
                 }
               },
 
-              message = receive_response(&mut stream).fuse() => {
-                if let Some(message) = message? {
+              _ = ping_timer.fuse() => {
+                if awaiting_pong {
+                  log::warn!("No answer to ping, reconnecting");
+                  break 'connection;
+                }
+
+                awaiting_pong = true;
+                last_heard = Instant::now();
+                outstanding.ping_sent();
+                send!(ClientToServerMessage::Ping);
+              },
+
+              response = responses.recv().fuse() => {
+                let message = match response {
+                  Ok(Ok(message)) => message,
+                  Ok(Err(err)) => {
+                    log::warn!("Failed to receive from server, reconnecting: {}", err);
+                    break 'connection;
+                  }
+                  Err(_) => {
+                    log::info!("Server closed the connection");
+                    break 'connection;
+                  }
+                };
+
+                last_heard = Instant::now();
+                awaiting_pong = false;
                 log::info!("Received message from server", {message: message});
-                  match message {
-                    ServerToClientMessage::Render { document } => {
-                      event_sender.send(NetworkSessionEvent::DocumentUpdated(document)).await;
-                    },
-                    ServerToClientMessage::RedirectTo { path } => {
-                      current_path = Some(path.clone());
-                      let storage = session_storage.clone();
-                      send_request(&mut stream, ClientToServerMessage::Load { path, storage }).await?;
+
+                let Some(message) = streamed.receive(message) else { continue };
+
+                let message = sign_in_redirect(message, options.unauthorized_path.as_deref());
+
+                match &message {
+                  ServerToClientMessage::Render { request_id, .. }
+                  | ServerToClientMessage::RedirectTo { request_id, .. }
+                  | ServerToClientMessage::Error { request_id, .. } => outstanding.answered(*request_id),
+                  ServerToClientMessage::NotModified
+                  | ServerToClientMessage::ActionData { .. }
+                  | ServerToClientMessage::Prefetched { .. } => outstanding.answered(None),
+                  ServerToClientMessage::Pong => outstanding.pong_received(),
+                  _ => {}
+                }
+
+                match message {
+                  ServerToClientMessage::Render { document, meta, .. } => {
+                    dropped = 0;
+                    redirect_chain.clear();
+                    let version = meta.as_ref().and_then(|meta| meta.version);
+
+                    if let Some(meta) = meta {
+                      log::debug!("Render metadata", { meta: format!("{:?}", meta) });
+
+                      if meta.canonical_path.is_some() {
+                        current_path = meta.canonical_path;
+                      }
+
+                      if let (Some(path), Some(max_age_ms)) = (current_path.clone(), meta.max_age_ms) {
+                        render_cache.insert(path, document.clone(), Duration::from_millis(max_age_ms));
+                      }
+                    }
+
+                    shown = current_path.clone().zip(version);
+                    event_sender.send(NetworkSessionEvent::DocumentUpdated(document)).await?;
+                  },
+                  ServerToClientMessage::NotModified => {
+                    dropped = 0;
+                    redirect_chain.clear();
+                    event_sender.send(NetworkSessionEvent::DocumentUnchanged).await?;
+                  }
+                  ServerToClientMessage::RenderBegin { .. }
+                  | ServerToClientMessage::RenderAppend { .. }
+                  | ServerToClientMessage::RenderEnd => unreachable!("handled above"),
+                  ServerToClientMessage::RedirectTo { path, .. } => {
+                    if is_redirect_loop(&redirect_chain, &path, options.max_redirects) {
+                      log::error!("Redirect loop detected", { path: path, chain: format!("{:?}", redirect_chain) });
+                      redirect_chain.clear();
+                      event_sender.send(NetworkSessionEvent::Error(format!("Too many redirects while loading {}", path))).await?;
+                      continue;
+                    }
+
+                    redirect_chain.push(path.clone());
+                    current_path = Some(path.clone());
+                    let storage = session_storage.clone();
+                    let version = known_version(&shown, &path);
+                    send!(ClientToServerMessage::Load { path, storage, version, request_id: request_ids.next() });
+                  }
+                  ServerToClientMessage::Store { scope, key, value, ack } => {
+                    store(&mut session_storage, scope, key, value);
+
+                    // Cached documents were rendered from the old storage
+                    render_cache.clear();
+
+                    if let Some(id) = ack {
+                      send!(ClientToServerMessage::StoreAck { id });
+                    }
+                  }
+                  ServerToClientMessage::StoreBatch { items } => {
+                    for (scope, key, value) in items {
+                      store(&mut session_storage, scope, key, value.into_storage_string());
+                    }
+
+                    render_cache.clear();
+                  }
+                  ServerToClientMessage::ClearStorage { scope } => {
+                    match scope {
+                      StorageScope::Session => session_storage.clear(),
+
+                      // Only session storage is kept so far, so the
+                      // others are already empty
+                      _ => log::debug!("Nothing to clear", { scope: format!("{:?}", scope) }),
                     }
-                    ServerToClientMessage::Store { scope, key, value } => {
-                      match scope {
-                        StorageScope::Session => { session_storage.insert(key, value); },
-                        _ => todo!("scope {:?}", scope)
+
+                    render_cache.clear();
+                  }
+                  ServerToClientMessage::ScheduleReload { after_ms } => {
+                    event_sender.send(NetworkSessionEvent::ReloadScheduled(Duration::from_millis(after_ms))).await?;
+                  }
+                  ServerToClientMessage::Download { filename, mime, bytes } => {
+                    save_download(filename, mime, bytes, &event_sender).await?;
+                  }
+                  ServerToClientMessage::DownloadBegin { filename, mime } => {
+                    downloading = Some((filename, mime, Vec::new()));
+                  }
+                  ServerToClientMessage::DownloadChunk { bytes } => {
+                    match &mut downloading {
+                      Some((filename, _, contents)) if contents.len() + bytes.len() > options.max_download_size => {
+                        log::error!("Download too large, dropping it", { filename: filename.as_str(), max: options.max_download_size });
+                        let message = format!("Couldn't save {}: it's larger than {} bytes", filename, options.max_download_size);
+                        downloading = None;
+                        event_sender.send(NetworkSessionEvent::Error(message)).await?;
                       }
+                      Some((_, _, contents)) => contents.extend(bytes),
+                      None => log::debug!("Ignoring part of a download that never began, or was dropped"),
                     }
                   }
-                } else {
-                  log::info!("Received null response, terminating connection");
-                  break 'connection;
+                  ServerToClientMessage::DownloadEnd => {
+                    match downloading.take() {
+                      Some((filename, mime, bytes)) => save_download(filename, mime, bytes, &event_sender).await?,
+                      None => log::debug!("Ignoring the end of a download that never began, or was dropped"),
+                    }
+                  }
+                  ServerToClientMessage::Error { code, message, request_id } => {
+                    // Whatever was being loaded has ended here
+                    redirect_chain.clear();
+
+                    log::error!("Server error", { code: format!("{:?}", code), message: message, request_id: format!("{:?}", request_id) });
+
+                    // Worth telling the user, since they can slow down or
+                    // sign in as someone else
+                    if matches!(code, ErrorCode::TooManyRequests | ErrorCode::Unauthorized | ErrorCode::Forbidden) {
+                      event_sender.send(NetworkSessionEvent::Error(message)).await?;
+                    }
+                  }
+                  ServerToClientMessage::Prefetch { paths } => {
+                    for path in paths {
+                      if Some(&path) == current_path.as_ref() || render_cache.get(&path).is_some() {
+                        continue;
+                      }
+
+                      let storage = session_storage.clone();
+                      send!(ClientToServerMessage::Prefetch { path, storage, request_id: request_ids.next() });
+                    }
+                  }
+                  ServerToClientMessage::Prefetched { path, document, meta } => {
+                    let max_age = meta
+                      .and_then(|meta| meta.max_age_ms)
+                      .map_or(PREFETCH_MAX_AGE, Duration::from_millis);
+
+                    log::debug!("Prefetched", { path: path });
+                    render_cache.insert(path, document, max_age);
+                  }
+                  ServerToClientMessage::ActionData { value: StateValue::Object(fields) } => {
+                    event_sender.send(NetworkSessionEvent::Data(fields)).await?;
+                  }
+                  ServerToClientMessage::ActionData { .. } => {
+                    log::debug!("Ignoring action data that isn't an object");
+                  }
+                  ServerToClientMessage::ValidationErrors { errors } => {
+                    event_sender.send(NetworkSessionEvent::ValidationErrors(errors)).await?;
+                  }
+                  ServerToClientMessage::Notify { severity, text } => {
+                    event_sender.send(NetworkSessionEvent::Notify { severity, text }).await?;
+                  }
+                  ServerToClientMessage::Patch { id, node } => {
+                    event_sender.send(NetworkSessionEvent::Patch { id, node }).await?;
+                  }
+                  ServerToClientMessage::ClientDirective { kind: Directive::Unknown } => {
+                    log::debug!("Ignoring unknown directive from server");
+                  }
+                  ServerToClientMessage::ClientDirective { kind } => {
+                    event_sender.send(NetworkSessionEvent::Directive(kind)).await?;
+                  }
+                  ServerToClientMessage::Capabilities { capabilities } => {
+                    log::debug!("Server capabilities", { capabilities: format!("{:?}", capabilities) });
+                    if capabilities.iter().any(|capability| capability == DEFLATE) {
+                      compression = Compression::Deflate;
+                    }
+                    if capabilities.iter().any(|capability| capability == JSON) {
+                      format = WireFormat::Json;
+                    }
+                    ping = capabilities.iter().any(|capability| capability == PING);
+                  }
+                  ServerToClientMessage::Pong => {}
+                  ServerToClientMessage::Unknown => {
+                    log::debug!("Ignoring unknown message from server");
+                  }
                 }
               }
             }
         }
+
+        // Stops the reader too, if the connection was given up on
+        stream.shutdown(Shutdown::Both).ok();
+        dropped += 1;
+        event_sender.send(NetworkSessionEvent::Disconnected).await?;
     }
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn policy() -> ReconnectPolicy {
+        ReconnectPolicy {
+            initial_delay: Duration::from_millis(500),
+            max_delay: Duration::from_secs(10),
+            multiplier: 2.,
+            jitter: 0.5,
+        }
+    }
+
+    #[test]
+    fn delay_doubles_after_each_failure() {
+        let policy = policy();
+        let delays: Vec<u64> = (1..=5).map(|failures| policy.delay(failures).as_millis() as u64).collect();
+
+        assert_eq!(delays, [500, 1000, 2000, 4000, 8000]);
+    }
+
+    #[test]
+    fn delay_before_any_failure_is_the_initial_delay() {
+        assert_eq!(policy().delay(0), Duration::from_millis(500));
+    }
+
+    #[test]
+    fn delay_is_capped_at_the_maximum() {
+        let policy = policy();
+        assert_eq!(policy.delay(6), Duration::from_secs(10));
+        assert_eq!(policy.delay(1000), Duration::from_secs(10));
+        assert_eq!(policy.delay(u32::MAX), Duration::from_secs(10));
+    }
+
+    #[test]
+    fn jitter_only_shortens_the_delay() {
+        let policy = policy();
+        for _ in 0..100 {
+            let delay = policy.jittered_delay(3);
+            assert!(delay <= Duration::from_secs(2), "{:?}", delay);
+            assert!(delay >= Duration::from_secs(1), "{:?}", delay);
+        }
+
+        let steady = ReconnectPolicy { jitter: 0., ..policy };
+        assert_eq!(steady.jittered_delay(3), Duration::from_secs(2));
+    }
+
+    #[test]
+    fn a_request_is_outstanding_until_answered() {
+        let mut outstanding = Outstanding::default();
+        assert!(!outstanding.busy());
+
+        outstanding.request_sent(Some(1));
+        assert!(outstanding.busy());
+
+        // An answer to an earlier request leaves the later one outstanding
+        outstanding.answered(Some(0));
+        assert!(outstanding.busy());
+
+        outstanding.answered(Some(1));
+        assert!(!outstanding.busy());
+    }
+
+    #[test]
+    fn a_pong_answers_every_request_sent_before_its_ping() {
+        let mut outstanding = Outstanding::default();
+        outstanding.request_sent(Some(1));
+        outstanding.ping_sent();
+        outstanding.request_sent(Some(2));
+        outstanding.ping_sent();
+
+        // The first pong only shows the first request was handled
+        outstanding.pong_received();
+        assert!(outstanding.busy());
+
+        outstanding.pong_received();
+        assert!(!outstanding.busy());
+    }
+
+    fn chain(paths: &[&str]) -> Vec<String> {
+        paths.iter().map(|path| path.to_string()).collect()
+    }
+
+    #[test]
+    fn redirecting_back_to_a_visited_path_is_a_loop() {
+        // Loading /a redirects to /b, which redirects back to /a
+        assert!(!is_redirect_loop(&chain(&["/a"]), "/b", 10));
+        assert!(is_redirect_loop(&chain(&["/a", "/b"]), "/a", 10));
+        assert!(is_redirect_loop(&chain(&["/a", "/b"]), "/b", 10));
+    }
+
+    #[test]
+    fn redirects_are_followed_up_to_the_maximum() {
+        let paths: Vec<String> = (0..=3).map(|n| format!("/{}", n)).collect();
+
+        // Three redirects from the page first loaded are allowed, a fourth isn't
+        assert!(!is_redirect_loop(&paths[..3], "/next", 3));
+        assert!(is_redirect_loop(&paths[..4], "/next", 3));
+    }
+
+    #[test]
+    fn redirecting_after_the_chain_is_cleared_is_not_a_loop() {
+        // E.g. an action that redirects to the page it was on
+        assert!(!is_redirect_loop(&[], "/a", 10));
+    }
+
+    #[test]
+    fn streamed_documents_are_rendered_once_whole() {
+        let mut streamed = StreamedDocument::default();
+        let meta = RenderMeta { version: Some(3), ..RenderMeta::default() };
+
+        assert!(streamed.receive(ServerToClientMessage::RenderBegin { layout: Layout::default(), meta: Some(meta) }).is_none());
+        for text in ["one", "two", "three"] {
+            assert!(streamed.receive(ServerToClientMessage::RenderAppend { node: Node::text(text) }).is_none());
+        }
+
+        let render = streamed.receive(ServerToClientMessage::RenderEnd);
+        let Some(ServerToClientMessage::Render { document: Document(Node::Container { children, .. }), meta, request_id: None }) = render else { panic!("Expected a render, got {:?}", render) };
+        assert_eq!(children, vec![Node::text("one").boxed(), Node::text("two").boxed(), Node::text("three").boxed()]);
+        assert_eq!(meta.and_then(|meta| meta.version), Some(3));
+
+        // Each document starts afresh
+        streamed.receive(ServerToClientMessage::RenderBegin { layout: Layout::default(), meta: None });
+        let render = streamed.receive(ServerToClientMessage::RenderEnd);
+        assert!(matches!(render, Some(ServerToClientMessage::Render { document: Document(Node::Container { ref children, .. }), .. }) if children.is_empty()), "{:?}", render);
+    }
+
+    #[test]
+    fn streamed_parts_without_a_beginning_are_ignored() {
+        let mut streamed = StreamedDocument::default();
+
+        assert!(streamed.receive(ServerToClientMessage::RenderAppend { node: Node::text("stray") }).is_none());
+        assert!(streamed.receive(ServerToClientMessage::RenderEnd).is_none());
+        assert!(matches!(streamed.receive(ServerToClientMessage::Pong), Some(ServerToClientMessage::Pong)));
+    }
+
+    fn unauthorized() -> ServerToClientMessage {
+        ServerToClientMessage::Error { code: ErrorCode::Unauthorized, message: "Please sign in".to_string(), request_id: Some(4) }
+    }
+
+    #[test]
+    fn unauthorized_errors_redirect_to_the_sign_in_page() {
+        let redirect = sign_in_redirect(unauthorized(), Some("/login"));
+        assert!(matches!(&redirect, ServerToClientMessage::RedirectTo { path, request_id: Some(4) } if path == "/login"), "{:?}", redirect);
+    }
+
+    #[test]
+    fn other_messages_are_left_alone_by_the_sign_in_redirect() {
+        let error = sign_in_redirect(unauthorized(), None);
+        assert!(matches!(error, ServerToClientMessage::Error { code: ErrorCode::Unauthorized, .. }), "{:?}", error);
+
+        let forbidden = ServerToClientMessage::Error { code: ErrorCode::Forbidden, message: "No".to_string(), request_id: None };
+        let forbidden = sign_in_redirect(forbidden, Some("/login"));
+        assert!(matches!(forbidden, ServerToClientMessage::Error { code: ErrorCode::Forbidden, .. }), "{:?}", forbidden);
+    }
+}
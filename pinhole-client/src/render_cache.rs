@@ -0,0 +1,36 @@
+use std::{
+    collections::HashMap,
+    time::{Duration, Instant},
+};
+
+use pinhole_protocol::document::Document;
+
+/// Documents the server said could be reused for a while, by path.
+#[derive(Default)]
+pub struct RenderCache {
+    entries: HashMap<String, (Document, Instant)>,
+}
+
+impl RenderCache {
+    pub fn insert(&mut self, path: String, document: Document, max_age: Duration) {
+        self.entries.insert(path, (document, Instant::now() + max_age));
+    }
+
+    /// The cached document for `path`, if it hasn't expired yet.
+    pub fn get(&mut self, path: &str) -> Option<Document> {
+        match self.entries.get(path) {
+            Some((document, expires)) if Instant::now() < *expires => Some(document.clone()),
+            Some(_) => {
+                self.entries.remove(path);
+                None
+            }
+            None => None,
+        }
+    }
+
+    /// Forgets everything, e.g. because the state the documents were
+    /// rendered from has changed.
+    pub fn clear(&mut self) {
+        self.entries.clear();
+    }
+}
@@ -1,13 +1,22 @@
+mod avatar;
 mod button;
+mod button_group;
 mod checkbox;
 mod container;
+mod link;
+mod spinner;
 mod text_input;
+mod toast;
+mod tooltip;
 
 use iced::Color;
+use pinhole_protocol::node::ToastSeverity;
 
 use self::{
-    button::ButtonStylesheet, checkbox::{CheckboxStylesheet}, container::ContainerStylesheet,
-    text_input::TextInputStylesheet,
+    avatar::AvatarStylesheet, button::ButtonStylesheet, button_group::ButtonGroupStylesheet,
+    checkbox::{CheckboxStylesheet, DisabledCheckboxStylesheet}, container::ContainerStylesheet, link::LinkStylesheet,
+    spinner::SpinnerStylesheet, text_input::TextInputStylesheet, toast::ToastStylesheet,
+    tooltip::TooltipStylesheet,
 };
 
 #[derive(Default)]
@@ -22,21 +31,56 @@ impl iced::application::StyleSheet for Stylesheet {
 }
 
 impl Stylesheet {
+    pub fn avatar_style(&self, name: &str, size: f32) -> AvatarStylesheet {
+        AvatarStylesheet {
+            name: name.to_string(),
+            size,
+        }
+    }
+
     pub fn button_style(&self) -> ButtonStylesheet {
         ButtonStylesheet
     }
 
+    pub fn button_group_style(&self, selected: bool) -> ButtonGroupStylesheet {
+        ButtonGroupStylesheet { selected }
+    }
+
     pub fn checkbox_style(&self) -> CheckboxStylesheet {
         CheckboxStylesheet
     }
 
+    pub fn disabled_checkbox_style(&self) -> DisabledCheckboxStylesheet {
+        DisabledCheckboxStylesheet
+    }
+
     pub fn container_style(&self) -> ContainerStylesheet {
         ContainerStylesheet
     }
 
+    pub fn validation_error_colour(&self) -> Color {
+        Color::from_rgb(0.75, 0.2, 0.2)
+    }
+
+    pub fn link_style(&self) -> LinkStylesheet {
+        LinkStylesheet
+    }
+
+    pub fn spinner_style(&self, lit: bool) -> SpinnerStylesheet {
+        SpinnerStylesheet { lit }
+    }
+
     pub fn text_input_style(&self) -> TextInputStylesheet {
         TextInputStylesheet
     }
+
+    pub fn tooltip_style(&self) -> TooltipStylesheet {
+        TooltipStylesheet
+    }
+
+    pub fn toast_style(&self, severity: ToastSeverity) -> ToastStylesheet {
+        ToastStylesheet { severity }
+    }
 }
 
 impl From<&Stylesheet> for Box<dyn iced::widget::button::StyleSheet<Style = ()>> {
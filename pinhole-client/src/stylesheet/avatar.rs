@@ -0,0 +1,36 @@
+use iced::{widget::container, Background, Color, Theme};
+
+/// Background colours for avatars, picked by hashing the user's name so a
+/// given user always gets the same one.
+const PALETTE: [Color; 6] = [
+    Color::from_rgb(0.85, 0.33, 0.31),
+    Color::from_rgb(0.95, 0.61, 0.07),
+    Color::from_rgb(0.36, 0.72, 0.36),
+    Color::from_rgb(0.2, 0.6, 0.86),
+    Color::from_rgb(0.56, 0.35, 0.8),
+    Color::from_rgb(0.4, 0.4, 0.45),
+];
+
+pub struct AvatarStylesheet {
+    pub name: String,
+    pub size: f32,
+}
+
+impl container::StyleSheet for AvatarStylesheet {
+    type Style = Theme;
+
+    fn appearance(&self, _style: &Self::Style) -> container::Appearance {
+        let hash = self
+            .name
+            .bytes()
+            .fold(0usize, |hash, byte| hash.wrapping_mul(31).wrapping_add(byte as usize));
+
+        container::Appearance {
+            text_color: Some(Color::WHITE),
+            background: Some(Background::Color(PALETTE[hash % PALETTE.len()])),
+            border_radius: self.size / 2.,
+            border_width: 0.,
+            border_color: Color::TRANSPARENT,
+        }
+    }
+}
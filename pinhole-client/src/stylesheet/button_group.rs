@@ -0,0 +1,39 @@
+use iced::{widget::button, Background, Color, Theme};
+
+pub struct ButtonGroupStylesheet {
+    pub selected: bool,
+}
+
+impl button::StyleSheet for ButtonGroupStylesheet {
+    type Style = Theme;
+
+    fn active(&self, _style: &Self::Style) -> button::Appearance {
+        let (background, text_color) = if self.selected {
+            (Color::from_rgb(0.2, 0.4, 0.8), Color::WHITE)
+        } else {
+            (Color::WHITE, Color::BLACK)
+        };
+
+        button::Appearance {
+            background: Some(Background::Color(background)),
+            text_color,
+            border_color: Color::from_rgba(0., 0., 0., 0.3),
+            border_radius: 0.,
+            border_width: 1.,
+            ..Default::default()
+        }
+    }
+
+    fn hovered(&self, style: &Self::Style) -> button::Appearance {
+        let active = self.active(style);
+
+        if self.selected {
+            active
+        } else {
+            button::Appearance {
+                background: Some(Background::Color(Color::from_rgb(0.93, 0.93, 0.93))),
+                ..active
+            }
+        }
+    }
+}
@@ -1,4 +1,4 @@
-use iced::{Background, Color, widget::checkbox};
+use iced::{Background, Color, Theme, widget::checkbox};
 
 pub struct CheckboxStylesheet;
 impl checkbox::StyleSheet for CheckboxStylesheet {
@@ -22,3 +22,24 @@ impl checkbox::StyleSheet for CheckboxStylesheet {
         }
     }
 }
+
+/// Greys out a checkbox that can't be changed.
+pub struct DisabledCheckboxStylesheet;
+impl checkbox::StyleSheet for DisabledCheckboxStylesheet {
+    type Style = Theme;
+
+    fn active(&self, _style: &Self::Style, _is_checked: bool) -> checkbox::Appearance {
+        checkbox::Appearance {
+            background: Background::Color(Color::from_rgb(0.95, 0.95, 0.95)),
+            icon_color: Color::from_rgb(0.6, 0.6, 0.6),
+            text_color: Some(Color::from_rgb(0.6, 0.6, 0.6)),
+            border_radius: 5.,
+            border_width: 1.,
+            border_color: Color::from_rgb(0.8, 0.8, 0.8),
+        }
+    }
+
+    fn hovered(&self, style: &Self::Style, is_checked: bool) -> checkbox::Appearance {
+        self.active(style, is_checked)
+    }
+}
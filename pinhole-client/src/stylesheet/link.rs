@@ -0,0 +1,23 @@
+use iced::{widget::button, Color, Theme};
+
+pub struct LinkStylesheet;
+
+impl button::StyleSheet for LinkStylesheet {
+    type Style = Theme;
+
+    fn active(&self, _style: &Self::Style) -> button::Appearance {
+        button::Appearance {
+            background: None,
+            text_color: Color::from_rgb(0.2, 0.4, 0.8),
+            border_width: 0.,
+            ..Default::default()
+        }
+    }
+
+    fn hovered(&self, style: &Self::Style) -> button::Appearance {
+        button::Appearance {
+            text_color: Color::from_rgb(0.1, 0.25, 0.6),
+            ..self.active(style)
+        }
+    }
+}
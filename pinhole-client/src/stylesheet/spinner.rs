@@ -0,0 +1,19 @@
+use iced::{widget::container, Background, Color, Theme};
+
+pub struct SpinnerStylesheet {
+    pub lit: bool,
+}
+
+impl container::StyleSheet for SpinnerStylesheet {
+    type Style = Theme;
+
+    fn appearance(&self, _style: &Self::Style) -> container::Appearance {
+        let alpha = if self.lit { 0.7 } else { 0.2 };
+
+        container::Appearance {
+            background: Some(Background::Color(Color::from_rgba(0., 0., 0., alpha))),
+            border_radius: f32::MAX,
+            ..Default::default()
+        }
+    }
+}
@@ -0,0 +1,27 @@
+use iced::{widget::container, Background, Color, Theme};
+use pinhole_protocol::node::ToastSeverity;
+
+pub struct ToastStylesheet {
+    pub severity: ToastSeverity,
+}
+
+impl container::StyleSheet for ToastStylesheet {
+    type Style = Theme;
+
+    fn appearance(&self, _style: &Self::Style) -> container::Appearance {
+        let background = match self.severity {
+            ToastSeverity::Info => Color::from_rgb(0.2, 0.4, 0.7),
+            ToastSeverity::Success => Color::from_rgb(0.2, 0.6, 0.3),
+            ToastSeverity::Warning => Color::from_rgb(0.85, 0.6, 0.1),
+            ToastSeverity::Error => Color::from_rgb(0.75, 0.2, 0.2),
+        };
+
+        container::Appearance {
+            text_color: Some(Color::WHITE),
+            background: Some(Background::Color(background)),
+            border_radius: 3.,
+            border_width: 0.,
+            border_color: Color::TRANSPARENT,
+        }
+    }
+}
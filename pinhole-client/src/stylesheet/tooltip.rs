@@ -0,0 +1,16 @@
+use iced::{widget::container, Background, Color, Theme};
+
+pub struct TooltipStylesheet;
+impl container::StyleSheet for TooltipStylesheet {
+    type Style = Theme;
+
+    fn appearance(&self, _style: &Self::Style) -> container::Appearance {
+        container::Appearance {
+            text_color: Some(Color::WHITE),
+            background: Some(Background::Color(Color::from_rgba(0., 0., 0., 0.8))),
+            border_radius: 3.,
+            border_width: 0.,
+            border_color: Color::TRANSPARENT,
+        }
+    }
+}
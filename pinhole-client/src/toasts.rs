@@ -0,0 +1,91 @@
+use std::{collections::HashSet, time::Duration};
+
+use async_std::task;
+use iced::{
+    theme,
+    widget::{Column, Container, Text},
+    Command, Length,
+};
+use pinhole_protocol::node::{ToastProps, ToastSeverity};
+
+use crate::{stylesheet::Stylesheet, PinholeMessage};
+
+/// How long a notification from the server stays on screen
+const NOTIFICATION_DURATION: Duration = Duration::from_secs(4);
+
+/// Toasts currently on screen, each tagged with a serial number so that an
+/// expiry timer only ever dismisses the toast it was started for.
+#[derive(Default)]
+pub struct Toasts {
+    active: Vec<(u64, ToastProps)>,
+    rendered_ids: HashSet<String>,
+    next_serial: u64,
+}
+
+impl Toasts {
+    /// Shows the toasts from a newly rendered document. Toasts whose id was
+    /// also in the previous document are left alone, so re-rendering doesn't
+    /// restart their timers or bring back ones that already expired.
+    pub fn update(&mut self, toasts: Vec<ToastProps>) -> Command<PinholeMessage> {
+        let mut commands = Vec::new();
+        let mut rendered_ids = HashSet::new();
+
+        for toast in toasts {
+            rendered_ids.insert(toast.id.clone());
+
+            if self.rendered_ids.contains(&toast.id) {
+                continue;
+            }
+
+            commands.push(self.show(toast));
+        }
+
+        self.rendered_ids = rendered_ids;
+        Command::batch(commands)
+    }
+
+    /// Shows a toast that isn't part of any document, so later renders leave
+    /// it alone.
+    pub fn notify(&mut self, severity: ToastSeverity, text: String) -> Command<PinholeMessage> {
+        self.show(ToastProps {
+            id: String::new(),
+            text,
+            duration_ms: NOTIFICATION_DURATION.as_millis() as u64,
+            severity,
+        })
+    }
+
+    fn show(&mut self, toast: ToastProps) -> Command<PinholeMessage> {
+        let serial = self.next_serial;
+        self.next_serial += 1;
+
+        let duration = Duration::from_millis(toast.duration_ms);
+        self.active.push((serial, toast));
+
+        Command::perform(task::sleep(duration), move |_| {
+            PinholeMessage::ToastExpired(serial)
+        })
+    }
+
+    pub fn dismiss(&mut self, serial: u64) {
+        self.active.retain(|(active, _)| *active != serial);
+    }
+
+    pub fn view(&self, stylesheet: &Stylesheet) -> iced::Element<'_, PinholeMessage> {
+        let toasts = self
+            .active
+            .iter()
+            .map(|(_, toast)| {
+                Container::new(Text::new(toast.text.clone()))
+                    .width(Length::Fill)
+                    .padding(8)
+                    .style(theme::Container::Custom(Box::new(
+                        stylesheet.toast_style(toast.severity),
+                    )))
+                    .into()
+            })
+            .collect();
+
+        Column::with_children(toasts).spacing(4).padding(4).into()
+    }
+}
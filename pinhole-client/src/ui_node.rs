@@ -1,21 +1,58 @@
-use iced::{widget::{Space, Text, Button, Checkbox, Container, Column, TextInput, Row}, Length, alignment::{Horizontal, Vertical}, Alignment
+use iced::{widget::{checkbox, scrollable, text_input, tooltip, Space, Text, Button, Checkbox, Container, Column, PickList, ProgressBar, Radio, Rule, Scrollable, TextInput, Row, Tooltip}, Length, alignment::{Horizontal, Vertical}, Alignment, theme
 };
 
-use crate::{stylesheet::Stylesheet, PinholeMessage, ButtonState, TextInputState};
+use crate::{stylesheet::Stylesheet, PinholeMessage, ButtonState, SpinnerState, TextInputState};
+use std::time::Duration;
 use pinhole_protocol::{
-    layout::{Layout, Position, Size},
-    node::{ButtonProps, CheckboxProps, InputProps, Node, TextProps},
+    action::Action,
+    layout::{Layout, Padding, Position, Size},
+    node::{
+        AvatarProps, ButtonGroupProps, ButtonProps, CheckboxProps, DividerProps, FormProps, IconProps, InputProps, LinkProps,
+        Colour, Node, NumberInputProps, ProgressProps, RadioProps, RichTextProps, Span, SelectProps, SlotProps, SpinnerProps, TextAreaProps, TextProps, ToastProps,
+        TooltipPosition, TooltipProps,
+    },
     storage::StateMap,
     storage::StateValue,
 };
 
 pub enum UiNode {
     Empty,
-    Container(Layout, Vec<Box<UiNode>>),
+    /// The id is set for scrollable containers, for scrolling them from a
+    /// directive
+    Container(Layout, Vec<UiNode>, Option<scrollable::Id>),
     Text(TextProps),
     Button(ButtonProps, ButtonState),
     Checkbox(CheckboxProps),
     Input(InputProps, TextInputState),
+    Tooltip(String, TooltipPosition, Box<UiNode>),
+    Toast(ToastProps),
+    Form(Vec<UiNode>),
+    Avatar(AvatarProps),
+    Spinner(SpinnerProps, SpinnerState),
+    ButtonGroup(ButtonGroupProps),
+    Slot(String, Box<UiNode>),
+    Select(SelectProps),
+    Radio(RadioProps),
+    TextArea(TextAreaProps),
+    Link(LinkProps),
+    NumberInput(NumberInputProps),
+    Divider(DividerProps),
+    Icon(IconProps),
+    Progress(f32),
+    RichText(RichTextProps),
+}
+
+/// An entry in a select, shown by its label.
+#[derive(Clone, Debug, PartialEq, Eq)]
+struct SelectOption {
+    value: String,
+    label: String,
+}
+
+impl std::fmt::Display for SelectOption {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.label)
+    }
 }
 
 impl From<Node> for UiNode {
@@ -25,85 +62,801 @@ impl From<Node> for UiNode {
             Node::Container { layout, children } => {
                 let mut nodes = Vec::new();
                 for node in children {
-                    nodes.push(Box::new(UiNode::from(*node)));
+                    nodes.push(UiNode::from(*node));
                 }
-                Self::Container(layout, nodes)
+                let scroll_id = layout.scrollable.then(scrollable::Id::unique);
+                Self::Container(layout, nodes, scroll_id)
             }
             Node::Text(props) => UiNode::Text(props),
             Node::Button(props) => UiNode::Button(props, ButtonState::default()),
             Node::Checkbox(props) => UiNode::Checkbox(props),
             Node::Input(props) => UiNode::Input(props, TextInputState::default()),
+            Node::Tooltip(TooltipProps {
+                text,
+                position,
+                child,
+            }) => UiNode::Tooltip(text, position, Box::new(UiNode::from(*child))),
+            Node::Toast(props) => UiNode::Toast(props),
+            Node::Avatar(props) => UiNode::Avatar(props),
+            Node::Spinner(props) => UiNode::Spinner(props, SpinnerState::default()),
+            Node::ButtonGroup(props) => UiNode::ButtonGroup(props),
+            Node::Select(props) => UiNode::Select(props),
+            Node::Radio(props) => UiNode::Radio(props),
+            Node::TextArea(props) => UiNode::TextArea(props),
+            Node::Link(props) => UiNode::Link(props),
+            Node::NumberInput(props) => UiNode::NumberInput(props),
+            Node::Divider(props) => UiNode::Divider(props),
+            Node::Icon(props) => UiNode::Icon(props),
+            Node::RichText(props) => UiNode::RichText(props),
+            Node::Progress(ProgressProps { value: Some(value) }) => UiNode::Progress(value.clamp(0., 1.) as f32),
+            Node::Progress(ProgressProps { value: None }) => {
+                UiNode::Spinner(SpinnerProps { size: PROGRESS_SPINNER_SIZE }, SpinnerState::default())
+            }
+            Node::Slot(SlotProps { id, child }) => UiNode::Slot(id, Box::new(UiNode::from(*child))),
+            Node::Form(FormProps {
+                on_submit,
+                children,
+            }) => {
+                let mut nodes: Vec<UiNode> =
+                    children.into_iter().map(|node| UiNode::from(*node)).collect();
+
+                let mut fields = Vec::new();
+                for node in &nodes {
+                    node.collect_fields(&mut fields);
+                }
+
+                let on_submit = with_keys(on_submit, &fields);
+                for node in &mut nodes {
+                    node.attach_form(&on_submit, &fields);
+                }
+
+                UiNode::Form(nodes)
+            }
+        }
+    }
+}
+
+/// State map keys holding validation errors are the field id with this prefix
+const VALIDATION_ERROR_PREFIX: &str = "__error_";
+
+/// The state map key for the validation error of the field `id`.
+pub fn validation_error_key(id: &str) -> String {
+    format!("{}{}", VALIDATION_ERROR_PREFIX, id)
+}
+
+pub fn is_validation_error_key(key: &str) -> bool {
+    key.starts_with(VALIDATION_ERROR_PREFIX)
+}
+
+/// State map keys holding what's been typed into number inputs are the field
+/// id with this prefix
+const NUMBER_TEXT_PREFIX: &str = "__text_";
+
+/// The state map key for text typed into the number input `id` that hasn't
+/// been turned into its value yet.
+pub fn number_text_key(id: &str) -> String {
+    format!("{}{}", NUMBER_TEXT_PREFIX, id)
+}
+
+pub fn is_number_text_key(key: &str) -> bool {
+    key.starts_with(NUMBER_TEXT_PREFIX)
+}
+
+/// Limits `value` to the bounds that are set.
+fn clamp(value: f64, min: Option<f64>, max: Option<f64>) -> f64 {
+    let value = min.map_or(value, |min| value.max(min));
+    max.map_or(value, |max| value.min(max))
+}
+
+/// The number in `text`, if it's a finite one.
+fn parse_number(text: &str) -> Option<f64> {
+    text.trim().parse::<f64>().ok().filter(|number| number.is_finite())
+}
+
+/// The value of the number input `id`, or `value` if it hasn't changed.
+fn number_value(state_map: &StateMap, id: &str, value: f64) -> f64 {
+    match state_map.get(id) {
+        Some(StateValue::Number(number)) => *number,
+        _ => value,
+    }
+}
+
+/// A container's length along one axis. Iced has no percentages, so a
+/// percentage becomes that many portions of 100, and the second length is
+/// for a spacer beside the container taking up the rest.
+fn to_length(size: Size) -> (Length, Option<Length>) {
+    match size {
+        Size::Auto => (Length::Shrink, None),
+        Size::Fixed(size) => (Length::Fixed(size), None),
+        Size::Fill => (Length::Fill, None),
+        Size::Percent(percent) => {
+            let portion = percent.clamp(0., 100.).round() as u16;
+            (Length::FillPortion(portion), Some(Length::FillPortion(100 - portion)))
         }
     }
 }
 
+/// Size icons are drawn at when the server doesn't give one, matching iced's
+/// default text size
+const DEFAULT_ICON_SIZE: u16 = 20;
+
+/// The font and character to draw each built-in icon with. Iced bundles an
+/// icon font with only a few glyphs, so the rest come from its text font.
+fn icon_glyph(name: &str) -> Option<(iced::Font, char)> {
+    type Renderer = iced::Renderer;
+    let icons = <Renderer as iced_native::text::Renderer>::ICON_FONT;
+    let text = iced::Font::Default;
+
+    match name {
+        "check" => Some((icons, <Renderer as iced_native::text::Renderer>::CHECKMARK_ICON)),
+        "chevron-down" => Some((icons, <Renderer as iced_native::text::Renderer>::ARROW_DOWN_ICON)),
+        "close" => Some((text, '\u{00D7}')),
+        "plus" => Some((text, '+')),
+        "minus" => Some((text, '\u{2212}')),
+        "more" => Some((text, '\u{2026}')),
+        "dot" => Some((text, '\u{2022}')),
+        _ => None,
+    }
+}
+
+/// Width of the spinner shown for progress of unknown amount
+const PROGRESS_SPINNER_SIZE: f32 = 36.;
+
+/// Height of a progress bar, in pixels
+const PROGRESS_BAR_HEIGHT: f32 = 8.;
+
+/// Height of a line of text in a text area, in pixels
+const TEXT_AREA_LINE_HEIGHT: f32 = 20.;
+
+fn to_horizontal(position: Position) -> Horizontal {
+    match position {
+        Position::Centre => Horizontal::Center,
+        Position::Start => Horizontal::Left,
+        Position::End => Horizontal::Right,
+    }
+}
+
+fn to_padding(padding: Padding) -> iced::Padding {
+    iced::Padding {
+        top: padding.top,
+        right: padding.right,
+        bottom: padding.bottom,
+        left: padding.left,
+    }
+}
+
+/// Adds `keys` to the action's keys, skipping any it already has.
+fn with_keys(mut action: Action, keys: &[String]) -> Action {
+    for key in keys {
+        if !action.keys.contains(key) {
+            action.keys.push(key.clone());
+        }
+    }
+    action
+}
+
 impl UiNode {
+    /// The content of the slot `id`, for replacing it with a patch.
+    pub fn slot_mut(&mut self, id: &str) -> Option<&mut UiNode> {
+        match self {
+            UiNode::Slot(slot_id, child) => {
+                if slot_id == id {
+                    Some(child)
+                } else {
+                    child.slot_mut(id)
+                }
+            }
+
+            UiNode::Container(_, children, _) | UiNode::Form(children) => {
+                children.iter_mut().find_map(|child| child.slot_mut(id))
+            }
+
+            UiNode::Tooltip(_, _, child) => child.slot_mut(id),
+
+            _ => None,
+        }
+    }
+
+    /// How to scroll each scrollable container around the form field or slot
+    /// `id` to bring it into view, outermost first. Iced doesn't say where
+    /// children are, so the offsets assume they're all about the same height.
+    pub fn scroll_to(&self, id: &str) -> Vec<(scrollable::Id, scrollable::RelativeOffset)> {
+        self.scroll_path(id).unwrap_or_default()
+    }
+
+    /// Like `scroll_to`, or `None` if `id` isn't in this subtree.
+    fn scroll_path(&self, id: &str) -> Option<Vec<(scrollable::Id, scrollable::RelativeOffset)>> {
+        if self.field_id() == Some(id) {
+            return Some(Vec::new());
+        }
+
+        match self {
+            UiNode::Slot(slot_id, _) if slot_id == id => Some(Vec::new()),
+
+            UiNode::Container(_, children, scroll_id) => {
+                children.iter().enumerate().find_map(|(index, child)| {
+                    let mut path = child.scroll_path(id)?;
+
+                    if let Some(scroll_id) = scroll_id {
+                        let y = match children.len() {
+                            1 => 0.,
+                            count => index as f32 / (count - 1) as f32,
+                        };
+                        path.insert(0, (scroll_id.clone(), scrollable::RelativeOffset { x: 0., y }));
+                    }
+
+                    Some(path)
+                })
+            }
+
+            UiNode::Form(children) => children.iter().find_map(|child| child.scroll_path(id)),
+
+            UiNode::Tooltip(_, _, child) | UiNode::Slot(_, child) => child.scroll_path(id),
+
+            _ => None,
+        }
+    }
+
+    /// Writes any values the server explicitly set on form fields into the
+    /// state map. Fields without an explicit value keep what the user typed,
+    /// so re-rendering a document doesn't clobber unsubmitted input.
+    pub fn apply_form_values(&self, state_map: &mut StateMap) {
+        match self {
+            UiNode::Container(_, children, _) | UiNode::Form(children) => {
+                for child in children {
+                    child.apply_form_values(state_map);
+                }
+            }
+
+            UiNode::Tooltip(_, _, child) | UiNode::Slot(_, child) => child.apply_form_values(state_map),
+
+            UiNode::Input(
+                InputProps {
+                    id,
+                    value: Some(value),
+                    ..
+                },
+                _,
+            )
+            | UiNode::TextArea(TextAreaProps {
+                id,
+                value: Some(value),
+                ..
+            }) => {
+                state_map.insert(id.clone(), StateValue::String(value.clone()));
+            }
+
+            UiNode::ButtonGroup(ButtonGroupProps {
+                id,
+                selected: Some(selected),
+                ..
+            })
+            | UiNode::Select(SelectProps {
+                id,
+                selected: Some(selected),
+                ..
+            })
+            | UiNode::Radio(RadioProps {
+                id,
+                selected: Some(selected),
+                ..
+            }) => {
+                state_map.insert(id.clone(), StateValue::String(selected.clone()));
+            }
+
+            UiNode::Checkbox(CheckboxProps {
+                id,
+                indeterminate: true,
+                ..
+            }) => {
+                state_map.insert(id.clone(), StateValue::Null);
+            }
+
+            // Every change is reported through `on_change`, so the value
+            // rendered is the server's word on it. Text still being typed
+            // is left alone.
+            UiNode::NumberInput(NumberInputProps { id, value, .. }) => {
+                state_map.insert(id.clone(), StateValue::Number(*value));
+            }
+
+            _ => {}
+        }
+    }
+
+    /// Turns text typed into number inputs into their values, clamped to
+    /// their bounds, returning the `on_change` actions of those whose value
+    /// changed. Text that isn't a number is dropped, leaving the value as it
+    /// was.
+    pub fn commit_number_inputs(&self, state_map: &mut StateMap) -> Vec<Action> {
+        let mut actions = Vec::new();
+        self.collect_number_commits(state_map, &mut actions);
+        actions
+    }
+
+    fn collect_number_commits(&self, state_map: &mut StateMap, actions: &mut Vec<Action>) {
+        match self {
+            UiNode::Container(_, children, _) | UiNode::Form(children) => {
+                for child in children {
+                    child.collect_number_commits(state_map, actions);
+                }
+            }
+
+            UiNode::Tooltip(_, _, child) | UiNode::Slot(_, child) => child.collect_number_commits(state_map, actions),
+
+            UiNode::NumberInput(NumberInputProps { id, value, min, max, on_change, .. }) => {
+                let Some(StateValue::String(text)) = state_map.remove(&number_text_key(id)) else {
+                    return;
+                };
+
+                let current = number_value(state_map, id, *value);
+                if let Some(number) = parse_number(&text).map(|number| clamp(number, *min, *max)) {
+                    if number != current {
+                        state_map.insert(id.clone(), StateValue::Number(number));
+                        actions.push(on_change.clone());
+                    }
+                }
+            }
+
+            _ => {}
+        }
+    }
+
+    /// Ids of the inputs, text areas, number inputs, checkboxes, button
+    /// groups, selects and radio groups in this subtree, not counting those
+    /// inside nested forms.
+    fn collect_fields(&self, fields: &mut Vec<String>) {
+        match self {
+            UiNode::Container(_, children, _) => {
+                for child in children {
+                    child.collect_fields(fields);
+                }
+            }
+
+            UiNode::Tooltip(_, _, child) | UiNode::Slot(_, child) => child.collect_fields(fields),
+
+            UiNode::Input(InputProps { id, .. }, _)
+            | UiNode::TextArea(TextAreaProps { id, .. })
+            | UiNode::NumberInput(NumberInputProps { id, .. })
+            | UiNode::Checkbox(CheckboxProps { id, .. })
+            | UiNode::ButtonGroup(ButtonGroupProps { id, .. })
+            | UiNode::Select(SelectProps { id, .. })
+            | UiNode::Radio(RadioProps { id, .. }) => fields.push(id.clone()),
+
+            _ => {}
+        }
+    }
+
+    /// Makes buttons send the form's fields and inputs submit the form on
+    /// Enter. Nested forms keep their own behaviour.
+    fn attach_form(&mut self, on_submit: &Action, fields: &[String]) {
+        match self {
+            UiNode::Container(_, children, _) => {
+                for child in children {
+                    child.attach_form(on_submit, fields);
+                }
+            }
+
+            UiNode::Tooltip(_, _, child) | UiNode::Slot(_, child) => child.attach_form(on_submit, fields),
+
+            UiNode::Button(ButtonProps { on_click, .. }, _) => {
+                *on_click = with_keys(on_click.clone(), fields);
+            }
+
+            UiNode::Input(_, state) => state.on_submit = Some(on_submit.clone()),
+
+            _ => {}
+        }
+    }
+
+    pub fn has_spinner(&self) -> bool {
+        match self {
+            UiNode::Container(_, children, _) | UiNode::Form(children) => {
+                children.iter().any(UiNode::has_spinner)
+            }
+
+            UiNode::Tooltip(_, _, child) | UiNode::Slot(_, child) => child.has_spinner(),
+
+            UiNode::Spinner(_, _) => true,
+
+            _ => false,
+        }
+    }
+
+    /// Moves every spinner on to its next animation frame.
+    pub fn advance_spinners(&mut self) {
+        match self {
+            UiNode::Container(_, children, _) | UiNode::Form(children) => {
+                for child in children {
+                    child.advance_spinners();
+                }
+            }
+
+            UiNode::Tooltip(_, _, child) | UiNode::Slot(_, child) => child.advance_spinners(),
+
+            UiNode::Spinner(_, state) => state.frame = state.frame.wrapping_add(1),
+
+            _ => {}
+        }
+    }
+
+    /// Ids of the input fields in the order Tab should visit them.
+    pub fn focus_order(&self) -> Vec<String> {
+        let mut inputs = Vec::new();
+        self.collect_inputs(&mut inputs);
+
+        let mut focusable: Vec<_> = inputs
+            .into_iter()
+            .filter(|props| props.tab_index.unwrap_or(0) >= 0)
+            .collect();
+
+        // Stable, so fields without a positive index keep document order
+        focusable.sort_by_key(|props| match props.tab_index {
+            Some(index) if index > 0 => (0, index),
+            _ => (1, 0),
+        });
+
+        focusable.into_iter().map(|props| props.id.clone()).collect()
+    }
+
+    /// Id of the input or text area that should be focused when the document
+    /// is shown, the first in document order if several ask for it.
+    pub fn autofocus(&self) -> Option<String> {
+        match self {
+            UiNode::Container(_, children, _) | UiNode::Form(children) => {
+                children.iter().find_map(|child| child.autofocus())
+            }
+
+            UiNode::Tooltip(_, _, child) | UiNode::Slot(_, child) => child.autofocus(),
+
+            UiNode::Input(InputProps { id, autofocus: true, .. }, _)
+            | UiNode::TextArea(TextAreaProps { id, autofocus: true, .. }) => Some(id.clone()),
+
+            _ => None,
+        }
+    }
+
+    /// Toasts anywhere in the document, in document order.
+    pub fn toasts(&self) -> Vec<ToastProps> {
+        match self {
+            UiNode::Container(_, children, _) | UiNode::Form(children) => {
+                children.iter().flat_map(|child| child.toasts()).collect()
+            }
+
+            UiNode::Tooltip(_, _, child) | UiNode::Slot(_, child) => child.toasts(),
+
+            UiNode::Toast(props) => vec![props.clone()],
+
+            _ => Vec::new(),
+        }
+    }
+
+    fn collect_inputs<'a>(&'a self, inputs: &mut Vec<&'a InputProps>) {
+        match self {
+            UiNode::Container(_, children, _) | UiNode::Form(children) => {
+                for child in children {
+                    child.collect_inputs(inputs);
+                }
+            }
+
+            UiNode::Tooltip(_, _, child) | UiNode::Slot(_, child) => child.collect_inputs(inputs),
+
+            UiNode::Input(props, _state) => inputs.push(props),
+
+            _ => {}
+        }
+    }
+
+    /// Id of the form field this node is, if it is one.
+    fn field_id(&self) -> Option<&str> {
+        match self {
+            UiNode::Input(InputProps { id, .. }, _)
+            | UiNode::TextArea(TextAreaProps { id, .. })
+            | UiNode::NumberInput(NumberInputProps { id, .. })
+            | UiNode::Checkbox(CheckboxProps { id, .. })
+            | UiNode::ButtonGroup(ButtonGroupProps { id, .. })
+            | UiNode::Select(SelectProps { id, .. })
+            | UiNode::Radio(RadioProps { id, .. }) => Some(id),
+
+            _ => None,
+        }
+    }
+
     pub fn view(
         &self,
         stylesheet: &Stylesheet,
         state_map: &StateMap,
-    ) -> iced::Element<PinholeMessage> {
+    ) -> iced::Element<'_, PinholeMessage> {
+        let element = self.view_node(stylesheet, state_map);
+
+        let error = self
+            .field_id()
+            .and_then(|id| state_map.get(&validation_error_key(id)));
+
+        match error {
+            Some(error) => Column::with_children(vec![
+                element,
+                Text::new(error.string().to_string())
+                    .style(stylesheet.validation_error_colour())
+                    .into(),
+            ])
+            .spacing(2)
+            .into(),
+            None => element,
+        }
+    }
+
+    fn view_node(
+        &self,
+        stylesheet: &Stylesheet,
+        state_map: &StateMap,
+    ) -> iced::Element<'_, PinholeMessage> {
         match self {
             UiNode::Empty => Space::new(Length::Fill, Length::Fill).into(),
-            UiNode::Text(TextProps { text }) => Text::new(text.clone()).into(),
-            UiNode::Button(ButtonProps { label, on_click }, _state) => {
-                Button::new(Text::new(label.clone()))
-                    .on_press(PinholeMessage::PerformAction(on_click.clone()))
-                    .into()
+            UiNode::Text(TextProps { text, align }) => {
+                let text = Text::new(text.clone()).horizontal_alignment(to_horizontal(*align));
+
+                match align {
+                    Position::Start => text.into(),
+                    Position::Centre | Position::End => text.width(Length::Fill).into(),
+                }
             }
+            UiNode::Button(
+                ButtonProps {
+                    label,
+                    on_click,
+                    padding,
+                    disabled,
+                },
+                _state,
+            ) => {
+                // Without a message to send, iced draws the button disabled
+                let mut button = Button::new(Text::new(label.clone()));
+                if !disabled {
+                    button = button.on_press(PinholeMessage::PerformAction(on_click.clone()));
+                }
+
+                if let Some(padding) = padding {
+                    button = button.padding(to_padding(*padding));
+                }
+
+                button.into()
+            }
+
+            UiNode::Divider(DividerProps { spacing }) => Rule::horizontal(*spacing as u16).into(),
+
+            UiNode::Progress(value) => ProgressBar::new(0.0..=1.0, *value)
+                .height(Length::Fixed(PROGRESS_BAR_HEIGHT))
+                .into(),
+
+            // Iced can't lay out differently styled text as one paragraph, so
+            // each span is its own text in a row. It only bundles a regular
+            // face, so bold and italic spans look like the rest.
+            UiNode::RichText(RichTextProps { spans }) => Row::with_children(
+                spans
+                    .iter()
+                    .map(|Span { text, colour, link, .. }| {
+                        let mut text = Text::new(text.clone());
+                        if let Some(Colour { red, green, blue }) = colour {
+                            text = text.style(iced::Color::from_rgb8(*red, *green, *blue));
+                        }
+
+                        match link {
+                            Some(to) => Button::new(text)
+                                .style(theme::Button::Custom(Box::new(stylesheet.link_style())))
+                                .padding(0)
+                                .on_press(PinholeMessage::StartNavigation(to.clone()))
+                                .into(),
+                            None => text.into(),
+                        }
+                    })
+                    .collect(),
+            )
+            .align_items(Alignment::Center)
+            .into(),
+
+            UiNode::Link(LinkProps { label, to }) => Button::new(Text::new(label.clone()))
+                .style(theme::Button::Custom(Box::new(stylesheet.link_style())))
+                .padding(0)
+                .on_press(PinholeMessage::StartNavigation(to.clone()))
+                .into(),
 
             UiNode::Checkbox(CheckboxProps {
                 id,
                 label,
                 checked,
                 on_change,
+                disabled,
+                indeterminate,
             }) => {
                 let id = id.clone();
                 let checked = *checked;
                 let on_change = on_change.clone();
-                let default_value = StateValue::Boolean(checked);
-                let value = state_map.get(&id).unwrap_or(&default_value);
+                let default_value = if *indeterminate { StateValue::Null } else { StateValue::Boolean(checked) };
+                let current = state_map.get(&id).unwrap_or(&default_value).clone();
+                let indeterminate = current == StateValue::Null;
+                let disabled = *disabled;
+
+                let mut widget = Checkbox::new(label.clone(), indeterminate || current.boolean(), move |value| {
+                    // This version of iced can't disable a checkbox, so a
+                    // disabled one answers clicks by keeping its value
+                    let (value, action) = if disabled {
+                        (current.clone(), None)
+                    } else if indeterminate {
+                        (StateValue::Boolean(true), Some(on_change.clone()))
+                    } else {
+                        (StateValue::Boolean(value), Some(on_change.clone()))
+                    };
+
+                    PinholeMessage::FormValueChanged { id: id.clone(), value, action }
+                });
+
+                // Iced has no indeterminate checkbox, so it's drawn checked
+                // with a dash in place of the tick
+                if indeterminate {
+                    widget = widget.icon(checkbox::Icon {
+                        font: iced::Font::Default,
+                        code_point: '\u{2212}',
+                        size: None,
+                    });
+                }
+
+                if disabled {
+                    widget = widget.style(theme::Checkbox::Custom(Box::new(stylesheet.disabled_checkbox_style())));
+                }
+
+                widget.into()
+            }
+
+            UiNode::ButtonGroup(ButtonGroupProps {
+                id,
+                options,
+                on_change,
+                ..
+            }) => {
+                let current = state_map.get(id).map(StateValue::string);
+
+                Row::with_children(
+                    options
+                        .iter()
+                        .map(|(value, label)| {
+                            let selected = current == Some(value.as_str());
+
+                            Button::new(Text::new(label.clone()))
+                                .style(theme::Button::Custom(Box::new(
+                                    stylesheet.button_group_style(selected),
+                                )))
+                                .on_press(PinholeMessage::FormValueChanged {
+                                    id: id.clone(),
+                                    value: StateValue::String(value.clone()),
+                                    action: on_change.clone(),
+                                })
+                                .into()
+                        })
+                        .collect(),
+                )
+                .into()
+            }
+
+            UiNode::Select(SelectProps {
+                id,
+                options,
+                on_change,
+                ..
+            }) => {
+                let options: Vec<SelectOption> = options
+                    .iter()
+                    .map(|(value, label)| SelectOption {
+                        value: value.clone(),
+                        label: label.clone(),
+                    })
+                    .collect();
+
+                let current = state_map.get(id).map(StateValue::string);
+                let selected = options
+                    .iter()
+                    .find(|option| current == Some(option.value.as_str()))
+                    .cloned();
 
-                Checkbox::new(label.clone(), value.boolean(), move |value| {
+                let id = id.clone();
+                let on_change = on_change.clone();
+                PickList::new(options, selected, move |option: SelectOption| {
                     PinholeMessage::FormValueChanged {
                         id: id.clone(),
-                        value: StateValue::Boolean(value),
+                        value: StateValue::String(option.value),
                         action: Some(on_change.clone()),
                     }
                 })
+                .padding(5)
+                .into()
+            }
+
+            UiNode::Radio(RadioProps {
+                id,
+                options,
+                on_change,
+                ..
+            }) => {
+                // Radios need a `Copy` value, so they stand for their option's index
+                let current = state_map.get(id).map(StateValue::string);
+                let selected = options
+                    .iter()
+                    .position(|(value, _)| current == Some(value.as_str()));
+
+                Column::with_children(
+                    options
+                        .iter()
+                        .enumerate()
+                        .map(|(index, (value, label))| {
+                            Radio::new(index, label.clone(), selected, |_| {
+                                PinholeMessage::FormValueChanged {
+                                    id: id.clone(),
+                                    value: StateValue::String(value.clone()),
+                                    action: Some(on_change.clone()),
+                                }
+                            })
+                            .into()
+                        })
+                        .collect(),
+                )
+                .spacing(5)
                 .into()
             }
 
-            UiNode::Container(layout, children) => {
+            UiNode::Container(layout, children, scroll_id) => {
                 let mut elements = Vec::new();
 
                 for element in children.iter() {
                     elements.push(element.view(stylesheet, state_map));
                 }
 
-                let container = Container::new(Column::with_children(elements))
-                    .align_x(match layout.horizontal.position {
-                        Position::Centre => Horizontal::Center,
-                        Position::Start => Horizontal::Left,
-                        Position::End => Horizontal::Right,
-                    })
+                let column = Column::with_children(elements).spacing(layout.gap);
+                let content: iced::Element<'_, PinholeMessage> = match scroll_id {
+                    Some(scroll_id) => Scrollable::new(column).height(Length::Fill).id(scroll_id.clone()).into(),
+                    None => column.into(),
+                };
+
+                let (width, spare_width) = to_length(layout.horizontal.size);
+                let (height, spare_height) = to_length(layout.vertical.size);
+
+                let mut container: iced::Element<'_, PinholeMessage> = Container::new(content)
+                    .padding(to_padding(layout.padding))
+                    .align_x(to_horizontal(layout.horizontal.position))
                     .align_y(match layout.vertical.position {
                         Position::Centre => Vertical::Center,
                         Position::Start => Vertical::Top,
                         Position::End => Vertical::Bottom,
                     })
-                    .width(match layout.horizontal.size {
-                        Size::Auto => Length::Shrink,
-                        Size::Fixed(size) => Length::Fixed(size),
-                        Size::Fill => Length::Fill,
-                    })
-                    .height(match layout.vertical.size {
-                        Size::Auto => Length::Shrink,
-                        Size::Fixed(size) => Length::Fixed(size),
-                        Size::Fill => Length::Fill,
-                    });
+                    .width(width)
+                    .height(height)
+                    .into();
+
+                if let Some(spare) = spare_width {
+                    container = Row::with_children(vec![container, Space::with_width(spare).into()])
+                        .width(Length::Fill)
+                        .height(height)
+                        .into();
+                }
+
+                if let Some(spare) = spare_height {
+                    container = Column::with_children(vec![container, Space::with_height(spare).into()])
+                        .width(if spare_width.is_some() { Length::Fill } else { width })
+                        .height(Length::Fill)
+                        .into();
+                }
 
-                container.into()
+                // Iced has no margins, so they're padding on a container
+                // around this one, which only fills if this one does
+                if layout.margin != Padding::default() {
+                    let outer = |length: Length| if length.fill_factor() > 0 { Length::Fill } else { Length::Shrink };
+
+                    container = Container::new(container)
+                        .padding(to_padding(layout.margin))
+                        .width(outer(width))
+                        .height(outer(height))
+                        .into();
+                }
+
+                container
             }
 
             UiNode::Input(
@@ -112,34 +865,420 @@ impl UiNode {
                     label,
                     password,
                     placeholder,
+                    on_change,
+                    debounce_ms,
+                    ..
                 },
-                _state,
+                state,
             ) => {
+                let input_id = id.clone();
                 let value = match state_map.get(id) {
                     Some(value) => value.clone(),
                     None => StateValue::String("".to_string()),
                 };
 
                 let id = id.clone();
+                let on_change = on_change.clone();
+                let debounce = debounce_ms.map(Duration::from_millis);
                 let placeholder = &placeholder.clone().unwrap_or("".to_string());
                 let mut input =
-                    TextInput::new(placeholder, &value.string(), move |new_value| {
-                        PinholeMessage::FormValueChanged {
-                            id: id.clone(),
-                            value: StateValue::String(new_value),
-                            action: None,
+                    TextInput::new(placeholder, value.string(), move |new_value| {
+                        let id = id.clone();
+                        let value = StateValue::String(new_value);
+                        match (on_change.clone(), debounce) {
+                            (Some(action), Some(after)) => PinholeMessage::FormValueChangedDebounced {
+                                id,
+                                value,
+                                action,
+                                after,
+                            },
+                            (action, _) => PinholeMessage::FormValueChanged { id, value, action },
                         }
                     })
+                    .id(text_input::Id::new(input_id))
                     .padding(5);
 
                 if *password {
                     input = input.password();
                 }
 
+                if let Some(on_submit) = &state.on_submit {
+                    input = input.on_submit(PinholeMessage::PerformAction(on_submit.clone()));
+                }
+
                 Row::with_children(vec![Text::new(label.clone()).into(), input.into()])
                     .align_items(Alignment::Center)
                     .into()
             }
+
+            UiNode::TextArea(TextAreaProps {
+                id,
+                label,
+                placeholder,
+                rows,
+                ..
+            }) => {
+                let value = state_map.get(id).map_or("", StateValue::string);
+                let placeholder = placeholder.as_deref().unwrap_or("");
+
+                // This version of iced has no multi-line editor, so the text
+                // is edited on one line, in space reserved for `rows` lines
+                let input = TextInput::new(placeholder, value, |new_value| {
+                    PinholeMessage::FormValueChanged {
+                        id: id.clone(),
+                        value: StateValue::String(new_value),
+                        action: None,
+                    }
+                })
+                .id(text_input::Id::new(id.clone()))
+                .padding(5);
+
+                Column::with_children(vec![
+                    Text::new(label.clone()).into(),
+                    Container::new(input)
+                        .height(Length::Fixed(f32::from(*rows) * TEXT_AREA_LINE_HEIGHT + 10.))
+                        .align_y(Vertical::Top)
+                        .into(),
+                ])
+                .spacing(5)
+                .into()
+            }
+
+            UiNode::NumberInput(NumberInputProps {
+                id,
+                label,
+                value,
+                min,
+                max,
+                step,
+                on_change,
+            }) => {
+                let current = number_value(state_map, id, *value);
+
+                // What's typed is kept as it is, so that e.g. "1" on the way
+                // to "15" isn't clamped to a minimum of 10, until Enter, Tab,
+                // a step button or an action commits it
+                let text = match state_map.get(&number_text_key(id)) {
+                    Some(StateValue::String(text)) => text.clone(),
+                    _ => current.to_string(),
+                };
+
+                let input = TextInput::new("", &text, move |text| PinholeMessage::FormValueChanged {
+                    id: number_text_key(id),
+                    value: StateValue::String(text),
+                    action: None,
+                })
+                .on_submit(PinholeMessage::CommitNumberInputs)
+                .id(text_input::Id::new(id.clone()))
+                .padding(5)
+                .width(Length::Fixed(80.));
+
+                // Steps from what's typed, if it's a number
+                let base = parse_number(&text).unwrap_or(current);
+                let changed = |new_value: f64| PinholeMessage::FormValueChanged {
+                    id: id.clone(),
+                    value: StateValue::Number(clamp(new_value, *min, *max)),
+                    action: Some(on_change.clone()),
+                };
+
+                let mut decrement = Button::new(Text::new("-"));
+                if min.is_none_or(|min| base > min) {
+                    decrement = decrement.on_press(changed(base - step));
+                }
+
+                let mut increment = Button::new(Text::new("+"));
+                if max.is_none_or(|max| base < max) {
+                    increment = increment.on_press(changed(base + step));
+                }
+
+                Row::with_children(vec![
+                    Text::new(label.clone()).into(),
+                    decrement.into(),
+                    input.into(),
+                    increment.into(),
+                ])
+                .spacing(5)
+                .align_items(Alignment::Center)
+                .into()
+            }
+
+            UiNode::Form(children) => Column::with_children(
+                children
+                    .iter()
+                    .map(|child| child.view(stylesheet, state_map))
+                    .collect(),
+            )
+            .into(),
+
+            // The client can't load images yet, so every avatar uses initials
+            UiNode::Avatar(props) => {
+                Container::new(Text::new(props.initials()).size(props.size * 0.4))
+                    .width(Length::Fixed(props.size))
+                    .height(Length::Fixed(props.size))
+                    .center_x()
+                    .center_y()
+                    .style(theme::Container::Custom(Box::new(
+                        stylesheet.avatar_style(&props.name, props.size),
+                    )))
+                    .into()
+            }
+
+            // A row of dots with a highlight that moves along each frame
+            UiNode::Spinner(SpinnerProps { size }, SpinnerState { frame }) => {
+                const DOTS: usize = 3;
+                let dot = size / (DOTS as f32 * 2.);
+
+                Row::with_children(
+                    (0..DOTS)
+                        .map(|index| {
+                            Container::new(Space::new(Length::Fixed(dot), Length::Fixed(dot)))
+                                .style(theme::Container::Custom(Box::new(
+                                    stylesheet.spinner_style(index == frame % DOTS),
+                                )))
+                                .into()
+                        })
+                        .collect(),
+                )
+                .spacing(dot)
+                .width(Length::Fixed(*size))
+                .into()
+            }
+
+            UiNode::Icon(IconProps { name, size }) => {
+                let size = size.unwrap_or(DEFAULT_ICON_SIZE);
+
+                match icon_glyph(name) {
+                    Some((font, glyph)) => Text::new(glyph.to_string()).font(font).size(size).into(),
+                    None => Space::new(Length::Fixed(size.into()), Length::Fixed(size.into())).into(),
+                }
+            }
+
+            // Toasts are drawn above the document rather than in place
+            UiNode::Toast(_) => Space::new(Length::Shrink, Length::Shrink).into(),
+
+            UiNode::Slot(_, child) => child.view(stylesheet, state_map),
+
+            UiNode::Tooltip(text, position, child) => Tooltip::new(
+                child.view(stylesheet, state_map),
+                text.clone(),
+                match position {
+                    TooltipPosition::Top => tooltip::Position::Top,
+                    TooltipPosition::Bottom => tooltip::Position::Bottom,
+                    TooltipPosition::Left => tooltip::Position::Left,
+                    TooltipPosition::Right => tooltip::Position::Right,
+                },
+            )
+            .padding(5)
+            .style(theme::Container::Custom(Box::new(stylesheet.tooltip_style())))
+            .into(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn slot(id: &str) -> Node {
+        Node::Slot(SlotProps { id: id.to_string(), child: Node::Empty.boxed() })
+    }
+
+    fn container(scrollable: bool, children: Vec<Node>) -> Node {
+        Node::Container {
+            layout: Layout::default().scrollable(scrollable),
+            children: children.into_iter().map(Node::boxed).collect(),
+        }
+    }
+
+    fn offsets(document: &UiNode, id: &str) -> Vec<f32> {
+        document.scroll_to(id).into_iter().map(|(_, offset)| offset.y).collect()
+    }
+
+    #[test]
+    fn scroll_to_offsets_by_position_among_children() {
+        let document = UiNode::from(container(true, vec![slot("a"), slot("b"), slot("c")]));
+
+        assert_eq!(offsets(&document, "a"), vec![0.]);
+        assert_eq!(offsets(&document, "b"), vec![0.5]);
+        assert_eq!(offsets(&document, "c"), vec![1.]);
+    }
+
+    #[test]
+    fn scroll_to_scrolls_every_scrollable_around_the_target() {
+        let inner = container(true, vec![slot("a"), slot("b")]);
+        let document = UiNode::from(container(true, vec![slot("x"), container(false, vec![inner])]));
+
+        assert_eq!(offsets(&document, "b"), vec![1., 1.]);
+    }
+
+    #[test]
+    fn scroll_to_ignores_unknown_ids_and_plain_containers() {
+        let document = UiNode::from(container(false, vec![slot("a"), slot("b")]));
+
+        assert!(document.scroll_to("a").is_empty());
+        assert!(document.scroll_to("missing").is_empty());
+    }
+
+    /// A document holding a quantity field from 10 to 100, in steps of 5
+    fn quantity_form() -> UiNode {
+        UiNode::from(container(false, vec![Node::NumberInput(NumberInputProps {
+            id: "quantity".to_string(),
+            label: "Quantity".to_string(),
+            value: 20.,
+            min: Some(10.),
+            max: Some(100.),
+            step: 5.,
+            on_change: Action::named("set_quantity", vec!["quantity".to_string()]),
+        })]))
+    }
+
+    /// The quantity after typing `text` and committing it, and whether it fired `on_change`
+    fn commit_typed(text: &str) -> (Option<StateValue>, bool) {
+        let document = quantity_form();
+        let mut state_map = StateMap::new();
+        document.apply_form_values(&mut state_map);
+        state_map.insert(number_text_key("quantity"), StateValue::String(text.to_string()));
+
+        let actions = document.commit_number_inputs(&mut state_map);
+        assert!(!state_map.contains_key(&number_text_key("quantity")));
+        (state_map.get("quantity").cloned(), !actions.is_empty())
+    }
+
+    #[test]
+    fn number_inputs_clamp_to_their_bounds_when_committed() {
+        assert_eq!(commit_typed("5"), (Some(StateValue::Number(10.)), true));
+        assert_eq!(commit_typed("10"), (Some(StateValue::Number(10.)), true));
+        assert_eq!(commit_typed("15"), (Some(StateValue::Number(15.)), true));
+        assert_eq!(commit_typed(" 100 "), (Some(StateValue::Number(100.)), true));
+        assert_eq!(commit_typed("150"), (Some(StateValue::Number(100.)), true));
+        assert_eq!(commit_typed("-7.5"), (Some(StateValue::Number(10.)), true));
+    }
+
+    #[test]
+    fn number_inputs_keep_their_value_for_text_that_isnt_a_number() {
+        for text in ["", "-", "1.2.3", "ten", "inf", "NaN"] {
+            assert_eq!(commit_typed(text), (Some(StateValue::Number(20.)), false), "{:?}", text);
         }
     }
+
+    #[test]
+    fn number_inputs_only_fire_on_change_when_the_value_changes() {
+        assert_eq!(commit_typed("20"), (Some(StateValue::Number(20.)), false));
+        assert_eq!(commit_typed("20.0"), (Some(StateValue::Number(20.)), false));
+    }
+
+    #[test]
+    fn number_inputs_leave_values_alone_while_nothing_is_typed() {
+        let document = quantity_form();
+        let mut state_map = StateMap::new();
+        state_map.insert("quantity".to_string(), StateValue::Number(35.));
+
+        assert!(document.commit_number_inputs(&mut state_map).is_empty());
+        assert_eq!(state_map.get("quantity"), Some(&StateValue::Number(35.)));
+    }
+
+    #[test]
+    fn number_inputs_take_the_rendered_value_but_keep_typed_text() {
+        let document = quantity_form();
+        let mut state_map = StateMap::new();
+        state_map.insert("quantity".to_string(), StateValue::Number(35.));
+        state_map.insert(number_text_key("quantity"), StateValue::String("4".to_string()));
+
+        document.apply_form_values(&mut state_map);
+        assert_eq!(state_map.get("quantity"), Some(&StateValue::Number(20.)));
+        assert_eq!(state_map.get(&number_text_key("quantity")), Some(&StateValue::String("4".to_string())));
+    }
+
+    #[test]
+    fn dividers_are_drawn_rather_than_left_empty() {
+        let document = UiNode::from(Node::Divider(DividerProps { spacing: 12. }));
+        assert!(matches!(document, UiNode::Divider(DividerProps { spacing }) if spacing == 12.));
+    }
+
+    fn input(id: &str) -> Node {
+        Node::Input(InputProps {
+            id: id.to_string(),
+            label: id.to_string(),
+            password: false,
+            placeholder: None,
+            value: None,
+            tab_index: None,
+            autofocus: false,
+            on_change: None,
+            debounce_ms: None,
+        })
+    }
+
+    fn button(label: &str) -> Node {
+        Node::Button(ButtonProps {
+            label: label.to_string(),
+            on_click: Action::named(label, vec![]),
+            padding: None,
+            disabled: false,
+        })
+    }
+
+    fn button_keys(node: &UiNode) -> &[String] {
+        match node {
+            UiNode::Button(ButtonProps { on_click, .. }, _) => &on_click.keys,
+            _ => panic!("Expected a button"),
+        }
+    }
+
+    #[test]
+    fn form_buttons_send_every_field_in_the_form() {
+        let checkbox = Node::Checkbox(CheckboxProps {
+            id: "agree".to_string(),
+            label: "Agree".to_string(),
+            checked: false,
+            on_change: Action::named("agree", vec![]),
+            disabled: false,
+            indeterminate: false,
+        });
+        let tooltip = Node::Tooltip(TooltipProps {
+            text: "Where we'll write".to_string(),
+            position: TooltipPosition::default(),
+            child: input("email").boxed(),
+        });
+        let slot = Node::Slot(SlotProps { id: "terms".to_string(), child: checkbox.boxed() });
+
+        let document = UiNode::from(
+            Node::form(Action::named("save", vec![]))
+                .children([input("name"), tooltip, slot, button("save")])
+                .build(),
+        );
+
+        let UiNode::Form(nodes) = &document else { panic!("Expected a form") };
+        assert_eq!(button_keys(&nodes[3]), ["name", "email", "agree"]);
+    }
+
+    #[test]
+    fn nested_forms_keep_their_fields_to_themselves() {
+        let nested = Node::form(Action::named("subscribe", vec![])).child(input("newsletter")).child(button("join")).build();
+        let document = UiNode::from(
+            Node::form(Action::named("save", vec![]))
+                .children([input("name"), nested, button("save")])
+                .build(),
+        );
+
+        let UiNode::Form(nodes) = &document else { panic!("Expected a form") };
+        assert_eq!(button_keys(&nodes[2]), ["name"]);
+
+        let UiNode::Form(nested) = &nodes[1] else { panic!("Expected a nested form") };
+        assert_eq!(button_keys(&nested[1]), ["newsletter"]);
+    }
+
+    #[test]
+    fn patches_replace_the_content_of_their_slot() {
+        let tooltip = Node::Tooltip(TooltipProps { text: "Sales so far".to_string(), position: TooltipPosition::Top, child: slot("sales").boxed() });
+        let mut document = UiNode::from(container(false, vec![slot("visitors"), Node::form(Action::named("save", vec![])).child(tooltip).build()]));
+
+        *document.slot_mut("sales").unwrap() = Node::text("Sales: 42").into();
+
+        let UiNode::Container(_, children, _) = &document else { panic!("Expected a container") };
+        let UiNode::Form(fields) = &children[1] else { panic!("Expected a form") };
+        let UiNode::Tooltip(_, _, slot) = &fields[0] else { panic!("Expected a tooltip") };
+        assert!(matches!(&**slot, UiNode::Slot(id, child) if id == "sales" && matches!(&**child, UiNode::Text(text) if text.text == "Sales: 42")));
+        assert!(document.slot_mut("missing").is_none());
+    }
 }
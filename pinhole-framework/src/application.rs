@@ -1,17 +1,98 @@
-use crate::Route;
+use std::time::Duration;
+
+use pinhole_protocol::network::NetworkLimits;
+
+use crate::{
+    router::RoutePattern, Metrics, NoMetrics, Params, Render, Route, Storage, Strings,
+    DEFAULT_IDLE_TIMEOUT,
+};
 
 pub type BoxedRoute = Box<dyn Route>;
 
 pub trait Application: Copy + Send + Sync + Sized {
     fn routes(&self) -> Vec<BoxedRoute>;
 
-    fn route(&self, path: &str) -> Option<BoxedRoute> {
-        for route in self.routes() {
-            if route.path() == path {
-                return Some(route);
-            }
-        }
+    /// The route matching `path`, with the parameters its pattern captured.
+    /// When several routes match, the most specific wins: literal segments
+    /// beat `:params`, which beat `*catch-alls`.
+    fn route(&self, path: &str) -> Option<(BoxedRoute, Params)> {
+        self.routes()
+            .into_iter()
+            .filter_map(|route| {
+                let pattern = RoutePattern::parse(route.path()).ok()?;
+                let params = pattern.matches(path)?;
+                Some((pattern, route, params))
+            })
+            .max_by(|(a, ..), (b, ..)| a.specificity(b))
+            .map(|(_, route, params)| (route, params))
+    }
 
+    /// Runs before each load, prefetch and action, whichever route matches
+    /// `path`, e.g. to send signed-out users to a sign-in page. Returning a
+    /// `Render` sends it in place of the route's: the route isn't rendered
+    /// and the action isn't run.
+    fn before_request(&self, _path: &str, _storage: &Storage) -> Option<Render> {
         None
     }
+
+    fn document_size_limits(&self) -> DocumentSizeLimits {
+        DocumentSizeLimits::default()
+    }
+
+    /// Localized messages for `Context::t`. Called once per connection.
+    fn strings(&self) -> Strings {
+        Strings::default()
+    }
+
+    /// Whether to disable Nagle's algorithm on client connections. Pinhole
+    /// sends many small messages, so this is on by default to avoid delaying
+    /// each round trip; turning it off can reduce packet overhead for
+    /// applications that mostly send large documents.
+    fn tcp_nodelay(&self) -> bool {
+        true
+    }
+
+    /// Bounds on the requests a client may send. Requests are small and
+    /// shallow unless they carry large state values; a client that exceeds
+    /// the limits is disconnected.
+    fn network_limits(&self) -> NetworkLimits {
+        NetworkLimits::default()
+    }
+
+    /// How long a client may send nothing before its connection is closed,
+    /// or `None` to keep idle connections open. Connected clients ping
+    /// servers that support it, so only ones that have gone away time out.
+    fn idle_timeout(&self) -> Option<Duration> {
+        Some(DEFAULT_IDLE_TIMEOUT)
+    }
+
+    /// Where to report connections opening and closing and messages going
+    /// each way, e.g. a `static` whose counters are exported to Prometheus.
+    fn metrics(&self) -> &'static dyn Metrics {
+        &NoMetrics
+    }
+}
+
+/// Bounds on the encoded size of rendered documents, in bytes.
+#[derive(Copy, Clone, Debug)]
+pub struct DocumentSizeLimits {
+    /// Documents larger than this are sent, but logged as a warning
+    pub warn: Option<usize>,
+
+    /// Documents larger than this are refused and an error sent instead
+    pub max: Option<usize>,
+
+    /// Documents larger than this whose root is a container are sent a
+    /// child at a time, to clients that support it
+    pub stream: Option<usize>,
+}
+
+impl Default for DocumentSizeLimits {
+    fn default() -> Self {
+        DocumentSizeLimits {
+            warn: Some(1024 * 1024),
+            max: Some(8 * 1024 * 1024),
+            stream: Some(256 * 1024),
+        }
+    }
 }
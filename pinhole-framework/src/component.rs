@@ -0,0 +1,37 @@
+use crate::{Layout, Node, Size, Sizing, Storage};
+
+/// A reusable piece of UI that routes can embed in their documents. It
+/// expands to plain nodes on the server, so the client never sees it.
+pub trait Component {
+    fn render(&self, storage: &Storage) -> Node;
+}
+
+/// Lays out its children one after another in a default container.
+pub struct Stack(pub Vec<Node>);
+
+impl Component for Stack {
+    fn render(&self, _storage: &Storage) -> Node {
+        Node::Container {
+            layout: Layout::default(),
+            children: self.0.iter().cloned().map(Node::boxed).collect(),
+        }
+    }
+}
+
+/// Centres its child in a box of the given size.
+pub struct Centred {
+    pub width: Size,
+    pub height: Size,
+    pub child: Node,
+}
+
+impl Component for Centred {
+    fn render(&self, _storage: &Storage) -> Node {
+        Node::Container {
+            layout: Layout::default()
+                .horizontal(Sizing::default().centred().size(self.width))
+                .vertical(Sizing::default().centred().size(self.height)),
+            children: vec![self.child.clone().boxed()],
+        }
+    }
+}
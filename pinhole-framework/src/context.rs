@@ -1,13 +1,67 @@
-use crate::{Result, ServerToClientMessage, StorageScope};
-use pinhole_protocol::{network::send_response, storage::StateMap};
+use crate::{
+    respond, ConnectionState, Directive, Document, ErrorCode, Node, PushHandle, RenderMeta, Result,
+    ServerToClientMessage, StateValue, Storage, StorageScope, ToastSeverity, Viewport,
+    DOWNLOAD_CHUNK_SIZE, STORE_ACK_TIMEOUT,
+};
+use async_std::future;
+use futures::{select, FutureExt};
+use pinhole_protocol::{messages::ClientToServerMessage, storage::StateMap};
+use serde::de::DeserializeOwned;
+use std::time::Duration;
 
 pub struct Context<'a> {
     pub state_map: StateMap,
 
     pub(crate) stream: &'a mut async_std::net::TcpStream,
+    pub(crate) path: String,
+    pub(crate) connection: &'a mut ConnectionState,
+    pub(crate) meta: RenderMeta,
+
+    /// The client's storage, kept up to date with what's been stored since
+    pub(crate) storage: Storage,
+    pub(crate) rerender: bool,
 }
 
 impl Context<'_> {
+    async fn send(&mut self, message: ServerToClientMessage) -> Result<()> {
+        respond(self.stream, self.connection, message).await
+    }
+
+    /// The path the client requested, as opposed to the route's `path()`.
+    pub fn current_path(&self) -> &str {
+        &self.path
+    }
+
+    /// Window size last reported by the client, if it has sent one.
+    pub fn viewport(&self) -> Option<Viewport> {
+        self.connection.viewport
+    }
+
+    /// Locale the client prefers, as a language tag such as `en-US`.
+    pub fn locale(&self) -> Option<&str> {
+        self.connection.locale.as_deref()
+    }
+
+    /// Formats the localized message `key` for the client's locale using the
+    /// application's `strings()`, e.g. `context.t("welcome", &[("name", name)])`.
+    pub fn t(&self, key: &str, args: &[(&str, &str)]) -> String {
+        self.connection.strings.get(self.locale(), key, args)
+    }
+
+    /// Metadata sent along with the document this render produces, such as
+    /// `context.meta().max_age_ms = Some(60_000)` to let the client reuse it
+    /// for a minute. Ignored outside of `render`.
+    pub fn meta(&mut self) -> &mut RenderMeta {
+        &mut self.meta
+    }
+
+    /// Deserializes the form values namespaced under `prefix` (keys such as
+    /// `prefix.email`) into a struct. Missing and extra fields are handled
+    /// according to the struct's serde attributes.
+    pub fn get_struct<T: DeserializeOwned>(&self, prefix: &str) -> Result<T> {
+        struct_from_form(&self.state_map, prefix)
+    }
+
     pub async fn store(
         &mut self,
         scope: StorageScope,
@@ -16,15 +70,356 @@ impl Context<'_> {
     ) -> Result<()> {
         let key = key.to_string();
         let value = value.to_string();
-        send_response(
-            self.stream,
-            ServerToClientMessage::Store { scope, key, value },
-        )
+        self.storage.insert(key.clone(), value.clone());
+        self.send(ServerToClientMessage::Store {
+            scope,
+            key,
+            value,
+            ack: None,
+        })
+        .await
+    }
+
+    /// Stores several values in one message, so the client gets all of them
+    /// or, if the connection drops, none. Values other than strings are
+    /// stored as JSON.
+    pub async fn store_batch<K: ToString>(
+        &mut self,
+        items: impl IntoIterator<Item = (StorageScope, K, StateValue)>,
+    ) -> Result<()> {
+        let bytes = self.connection.bytes;
+        let items: Vec<(StorageScope, String, StateValue)> = items
+            .into_iter()
+            .map(|(scope, key, value)| {
+                // Clients that can't decode bytes get them as numbers
+                let value = if bytes { value } else { value.without_bytes() };
+                (scope, key.to_string(), value)
+            })
+            .collect();
+
+        for (_, key, value) in &items {
+            self.storage
+                .insert(key.clone(), value.clone().into_storage_string());
+        }
+
+        self.send(ServerToClientMessage::StoreBatch { items }).await
+    }
+
+    /// Removes everything the client has stored in `scope`.
+    pub async fn clear_storage(&mut self, scope: StorageScope) -> Result<()> {
+        // Clients only send their session storage with requests
+        if let StorageScope::Session = scope {
+            self.storage.clear();
+        }
+
+        self.send(ServerToClientMessage::ClearStorage { scope })
+            .await
+    }
+
+    /// Like `store`, but waits for the client to confirm it has stored the
+    /// value. This costs a round trip, so it's only worth it when what comes
+    /// next depends on the client having the value. Clients that don't
+    /// advertise `STORE_ACK` get a plain `store`, and the wait is limited to
+    /// `STORE_ACK_TIMEOUT`.
+    pub async fn store_and_wait(
+        &mut self,
+        scope: StorageScope,
+        key: impl ToString,
+        value: impl ToString,
+    ) -> Result<()> {
+        if !self.connection.store_ack {
+            return self.store(scope, key, value).await;
+        }
+
+        let id = self.connection.next_ack_id;
+        self.connection.next_ack_id += 1;
+
+        let key = key.to_string();
+        let value = value.to_string();
+        self.storage.insert(key.clone(), value.clone());
+        self.send(ServerToClientMessage::Store {
+            scope,
+            key,
+            value,
+            ack: Some(id),
+        })
+        .await?;
+
+        future::timeout(STORE_ACK_TIMEOUT, self.wait_for_ack(id))
+            .await
+            .map_err(|_| "The client didn't acknowledge the store in time")?
+    }
+
+    /// Waits for the client's `StoreAck` with `id`. Pings are answered
+    /// meanwhile, so the client doesn't give up on the connection, and
+    /// anything else is handled once the route is done.
+    async fn wait_for_ack(&mut self, id: u64) -> Result<()> {
+        let closing = self.connection.closing.clone();
+
+        loop {
+            let request = select! {
+                request = self.connection.requests.recv().fuse() => request,
+                _ = closing.recv().fuse() => {
+                    return Err("Shutting down before the store was acknowledged".into())
+                }
+            };
+
+            match request {
+                Ok(Ok(ClientToServerMessage::StoreAck { id: acked })) if acked == id => {
+                    return Ok(())
+                }
+                Ok(Ok(ClientToServerMessage::Ping)) => {
+                    self.send(ServerToClientMessage::Pong).await?
+                }
+                Ok(Ok(request)) => self.connection.deferred.push_back(request),
+                Ok(Err(err)) => return Err(err),
+                Err(_) => return Err("Connection closed before the store was acknowledged".into()),
+            }
+        }
+    }
+
+    /// Asks the client to load its current path again after `after`, e.g. to
+    /// poll for updates. Calling this on each render keeps the cycle going;
+    /// rendering without it lets it stop.
+    pub async fn schedule_reload(&mut self, after: Duration) -> Result<()> {
+        let after_ms = after.as_millis() as u64;
+        self.send(ServerToClientMessage::ScheduleReload { after_ms })
+            .await
+    }
+
+    /// Shows `document` right away, before `render` returns. Parts that
+    /// aren't ready yet can be `Slot`s, filled in later with `patch`. The
+    /// document `render` finally returns replaces the skeleton as usual.
+    pub async fn render_skeleton(&mut self, document: Document) -> Result<()> {
+        self.send(ServerToClientMessage::Render {
+            document,
+            meta: None,
+            request_id: None,
+        })
+        .await
+    }
+
+    /// Replaces the content of the slot `id` in the document the client is
+    /// showing.
+    pub async fn patch(&mut self, id: impl ToString, node: Node) -> Result<()> {
+        let id = id.to_string();
+        self.send(ServerToClientMessage::Patch { id, node }).await
+    }
+
+    /// Suggests paths the user is likely to visit next. The client renders
+    /// them in the background, so navigating to one can show it immediately.
+    pub async fn prefetch(&mut self, paths: &[&str]) -> Result<()> {
+        let paths = paths.iter().map(|path| path.to_string()).collect();
+        self.send(ServerToClientMessage::Prefetch { paths }).await
+    }
+
+    /// Asks the client to do something on its own, such as copying text to
+    /// the clipboard, without rendering a new document.
+    pub async fn client_directive(&mut self, kind: Directive) -> Result<()> {
+        self.send(ServerToClientMessage::ClientDirective { kind })
+            .await
+    }
+
+    /// Sends a file for the client to save in the user's downloads folder.
+    /// Files over `DOWNLOAD_CHUNK_SIZE` are sent in parts to clients that
+    /// can take them; older clients get the whole file in one message, so
+    /// for them it must fit within the protocol's message size.
+    pub async fn download(
+        &mut self,
+        filename: impl ToString,
+        mime: impl ToString,
+        bytes: Vec<u8>,
+    ) -> Result<()> {
+        let filename = filename.to_string();
+        let mime = mime.to_string();
+
+        if bytes.len() <= DOWNLOAD_CHUNK_SIZE || !self.connection.chunked_downloads {
+            return self
+                .send(ServerToClientMessage::Download {
+                    filename,
+                    mime,
+                    bytes,
+                })
+                .await;
+        }
+
+        self.send(ServerToClientMessage::DownloadBegin { filename, mime })
+            .await?;
+
+        for chunk in bytes.chunks(DOWNLOAD_CHUNK_SIZE) {
+            let bytes = chunk.to_vec();
+            self.send(ServerToClientMessage::DownloadChunk { bytes })
+                .await?;
+        }
+
+        self.send(ServerToClientMessage::DownloadEnd).await
+    }
+
+    /// A handle for sending this client messages later, outside of any
+    /// request, such as a new document when the data behind this page
+    /// changes.
+    pub fn push_handle(&self) -> PushHandle {
+        PushHandle {
+            path: self.path.clone(),
+            sender: self.connection.push_sender.clone(),
+        }
+    }
+
+    /// Renders the current page again once the action returns, with what the
+    /// action stored, so the client needn't load it again to see the result.
+    /// Ignored outside of `action`.
+    pub fn rerender(&mut self) {
+        self.rerender = true;
+    }
+
+    /// Shows each message beside the form field with the given id, e.g.
+    /// `context.validation_errors([("email", "Enter an email address")])`.
+    /// Replaces the errors shown before, so passing none clears them.
+    pub async fn validation_errors<K: ToString, V: ToString>(
+        &mut self,
+        errors: impl IntoIterator<Item = (K, V)>,
+    ) -> Result<()> {
+        let errors = errors
+            .into_iter()
+            .map(|(field, message)| (field.to_string(), message.to_string()))
+            .collect();
+        self.send(ServerToClientMessage::ValidationErrors { errors })
+            .await
+    }
+
+    /// Shows `text` briefly above the client's document, e.g.
+    /// `context.notify(ToastSeverity::Success, "Saved")` after an action,
+    /// without rendering a new one.
+    pub async fn notify(&mut self, severity: ToastSeverity, text: impl ToString) -> Result<()> {
+        let text = text.to_string();
+        self.send(ServerToClientMessage::Notify { severity, text })
+            .await
+    }
+
+    /// Tells the client it's sending requests too quickly, e.g. from an
+    /// action that enforces a rate limit, instead of carrying it out.
+    pub async fn reject_rate_limited(&mut self) -> Result<()> {
+        self.send(ServerToClientMessage::Error {
+            code: ErrorCode::TooManyRequests,
+            message: "Too many requests, please try again shortly".to_string(),
+            request_id: None,
+        })
+        .await
+    }
+
+    /// Tells the client the request needs a signed-in user. Clients
+    /// configured with a sign-in page go there.
+    pub async fn reject_unauthorized(&mut self) -> Result<()> {
+        self.send(ServerToClientMessage::Error {
+            code: ErrorCode::Unauthorized,
+            message: "Please sign in to continue".to_string(),
+            request_id: None,
+        })
+        .await
+    }
+
+    /// Tells the client the signed-in user isn't allowed to do this.
+    pub async fn reject_forbidden(&mut self) -> Result<()> {
+        self.send(ServerToClientMessage::Error {
+            code: ErrorCode::Forbidden,
+            message: "You don't have permission to do that".to_string(),
+            request_id: None,
+        })
         .await
     }
 
     pub async fn redirect(&mut self, path: impl ToString) -> Result<()> {
         let path = path.to_string();
-        send_response(self.stream, ServerToClientMessage::RedirectTo { path }).await
+        self.send(ServerToClientMessage::RedirectTo {
+            path,
+            request_id: None,
+        })
+        .await
+    }
+}
+
+/// The values in `state_map` namespaced under `prefix`, as a `T`.
+fn struct_from_form<T: DeserializeOwned>(state_map: &StateMap, prefix: &str) -> Result<T> {
+    let prefix = format!("{}.", prefix);
+    let object = state_map
+        .iter()
+        .filter_map(|(key, value)| {
+            key.strip_prefix(&prefix)
+                .map(|field| (field.to_string(), value.clone().into()))
+        })
+        .collect();
+
+    Ok(serde_json::from_value(serde_json::Value::Object(object))?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde::Deserialize;
+
+    #[derive(Debug, PartialEq, Deserialize)]
+    struct SignUp {
+        email: String,
+        age: u32,
+        #[serde(default)]
+        newsletter: bool,
+    }
+
+    fn form(fields: &[(&str, StateValue)]) -> StateMap {
+        fields
+            .iter()
+            .map(|(key, value)| (key.to_string(), value.clone()))
+            .collect()
+    }
+
+    #[test]
+    fn fields_under_the_prefix_make_up_the_struct() {
+        let state_map = form(&[
+            (
+                "signup.email",
+                StateValue::String("ada@example.com".to_string()),
+            ),
+            ("signup.age", StateValue::Number(36.)),
+            ("search", StateValue::String("unrelated".to_string())),
+        ]);
+
+        let signup: SignUp = struct_from_form(&state_map, "signup").unwrap();
+        assert_eq!(
+            signup,
+            SignUp {
+                email: "ada@example.com".to_string(),
+                age: 36,
+                newsletter: false,
+            }
+        );
+    }
+
+    #[test]
+    fn keys_that_only_start_like_the_prefix_are_left_out() {
+        let state_map = form(&[
+            (
+                "signup.email",
+                StateValue::String("ada@example.com".to_string()),
+            ),
+            ("signup.age", StateValue::Number(36.)),
+            ("signupx.newsletter", StateValue::Boolean(true)),
+            ("signup_newsletter", StateValue::Boolean(true)),
+        ]);
+
+        let signup: SignUp = struct_from_form(&state_map, "signup").unwrap();
+        assert!(!signup.newsletter);
+    }
+
+    #[test]
+    fn fields_of_the_wrong_type_are_an_error() {
+        let state_map = form(&[
+            (
+                "signup.email",
+                StateValue::String("ada@example.com".to_string()),
+            ),
+            ("signup.age", StateValue::String("thirty-six".to_string())),
+        ]);
+
+        assert!(struct_from_form::<SignUp>(&state_map, "signup").is_err());
     }
 }
@@ -1,101 +1,930 @@
 mod application;
+mod component;
 mod context;
+mod metrics;
+mod push;
+mod retry;
 mod route;
+mod router;
+mod strings;
 
 use kv_log_macro as log;
 
 use async_std::{
-    future::Future,
-    net::{TcpListener, TcpStream, ToSocketAddrs},
+    channel::{self, Receiver},
+    future::{self, Future},
+    net::{Shutdown, TcpListener, TcpStream, ToSocketAddrs},
     prelude::*,
     task,
 };
+use futures::{select, FutureExt};
+use std::{
+    any::Any,
+    collections::{BTreeMap, VecDeque},
+    panic::AssertUnwindSafe,
+    time::{Duration, Instant},
+};
+
+use push::Pushed;
+use router::RoutePattern;
 
 use pinhole_protocol::{
+    document::EncodedDocument,
     messages::ClientToServerMessage,
-    network::{receive_request, send_response},
+    network::{
+        receive_request, send_encoded_document, send_encoded_prefetched, send_response,
+        supported_capabilities, Compression, WireFormat, BYTES, CHUNKED_DOWNLOADS, DEFLATE, JSON,
+        STORE_ACK, STREAMING,
+    },
 };
 
-pub use application::Application;
+pub use application::{Application, BoxedRoute, DocumentSizeLimits};
+pub use component::{Centred, Component, Stack};
 pub use context::Context;
+pub use metrics::{Metrics, NoMetrics};
 pub use pinhole_protocol::{
     action::Action,
     document::Document,
-    layout::{Layout, Position, Size, Sizing},
-    messages::ServerToClientMessage,
-    node::{ButtonProps, CheckboxProps, InputProps, Node, TextProps},
+    layout::{Layout, Padding, Position, Size, Sizing, Viewport},
+    messages::{Directive, ErrorCode, RenderMeta, ServerToClientMessage},
+    network::NetworkLimits,
+    node::{
+        AvatarProps, ButtonGroupProps, ButtonProps, CheckboxProps, Colour, ContainerBuilder,
+        DividerProps, FormProps, IconProps, InputProps, LinkProps, Node, NumberInputProps,
+        ProgressProps, RadioProps, RichTextProps, SelectProps, SlotProps, Span, SpinnerProps,
+        TextAreaProps, TextProps, ToastProps, ToastSeverity, TooltipPosition, TooltipProps,
+    },
     storage::{StateMap, StateValue, StorageScope},
 };
-pub use route::{Render, Route, Storage};
+pub use push::PushHandle;
+pub use retry::retry;
+pub use route::{ActionResponse, Render, Route, Storage};
+pub use router::Params;
+pub use strings::{Bundle, Strings};
 
 pub type Result<T> = std::result::Result<T, Box<dyn std::error::Error + Send + Sync>>;
 
 pub fn run(application: impl Application + 'static, address: impl ToSocketAddrs) -> Result<()> {
+    run_with_shutdown(application, address, future::pending())
+}
+
+/// Like `run`, but returns once `shutdown` completes. New connections are
+/// refused from then on, and open ones are closed as soon as they've
+/// finished the request they're handling, waiting at most `DRAIN_TIMEOUT`.
+pub fn run_with_shutdown(
+    application: impl Application + 'static,
+    address: impl ToSocketAddrs,
+    shutdown: impl Future<Output = ()>,
+) -> Result<()> {
     femme::start();
 
-    task::block_on(accept_loop(application, address))
+    validate_routes(&application)?;
+    task::block_on(accept_loop(application, address, shutdown))
+}
+
+/// How many requests a connection reads ahead of the one being handled.
+const REQUEST_QUEUE_LENGTH: usize = 8;
+
+/// How long shutting down waits for connections to finish their requests.
+pub const DRAIN_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// How long `Context::store_and_wait` waits for the client to confirm it
+/// has stored a value.
+pub const STORE_ACK_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Files bigger than this are sent in parts of this size, to clients that
+/// can take them, so they needn't fit in one message.
+pub const DOWNLOAD_CHUNK_SIZE: usize = 1024 * 1024;
+
+/// How long a connection may go without a request before it's closed, unless
+/// the application says otherwise. Clients ping far more often than this.
+pub const DEFAULT_IDLE_TIMEOUT: Duration = Duration::from_secs(5 * 60);
+
+/// Catches route paths that could never be reached, before serving anything.
+fn validate_routes(application: &impl Application) -> Result<()> {
+    let mut patterns: Vec<(&str, RoutePattern)> = Vec::new();
+
+    for route in application.routes() {
+        let path = route.path();
+        let pattern = RoutePattern::parse(path)?;
+
+        if let Some((other, _)) = patterns
+            .iter()
+            .find(|(_, other)| other.same_shape(&pattern))
+        {
+            return Err(format!("Routes {:?} and {:?} match the same paths", other, path).into());
+        }
+
+        patterns.push((path, pattern));
+    }
+
+    Ok(())
 }
 
 async fn accept_loop(
     application: impl Application + 'static,
     addr: impl ToSocketAddrs,
+    shutdown: impl Future<Output = ()>,
 ) -> Result<()> {
     let listener = TcpListener::bind(addr).await?;
 
+    // Nothing is sent on these. Dropping the sender of `closing` tells the
+    // connections to close, and `finished` closes once every connection
+    // has dropped its sender.
+    let (closing_sender, closing) = channel::bounded::<()>(1);
+    let (finished_sender, finished) = channel::bounded::<()>(1);
+
     let mut incoming = listener.incoming();
-    while let Some(stream) = incoming.next().await {
-        spawn_and_log_error(connection_loop(application, stream?));
+    let shutdown = shutdown.fuse();
+    futures::pin_mut!(shutdown);
+
+    loop {
+        select! {
+            stream = incoming.next().fuse() => {
+                let Some(stream) = stream else { break };
+                let stream = stream?;
+
+                // Only a latency tweak, and failing here usually means the
+                // peer has already gone, so it's no reason to stop serving
+                if let Err(err) = stream.set_nodelay(application.tcp_nodelay()) {
+                    log::warn!("Failed to set TCP_NODELAY: {:?}", err);
+                }
+
+                let closing = closing.clone();
+                let finished = finished_sender.clone();
+                spawn_and_log_error(async move {
+                    let _finished = finished;
+                    connection_loop(application, stream, closing).await
+                });
+            },
+            () = shutdown => break,
+        }
+    }
+
+    log::info!("Shutting down");
+    drop(incoming);
+    drop(listener);
+    drop(closing_sender);
+    drop(finished_sender);
+
+    if future::timeout(DRAIN_TIMEOUT, finished.recv())
+        .await
+        .is_err()
+    {
+        log::warn!("Connections were still busy when the drain timeout ran out");
     }
 
     Ok(())
 }
 
-async fn connection_loop(application: impl Application, mut stream: TcpStream) -> Result<()> {
+async fn connection_loop(
+    application: impl Application,
+    mut stream: TcpStream,
+    closing: Receiver<()>,
+) -> Result<()> {
     log::info!("New connection", {
         address: format!("{:?}", stream.peer_addr()?)
     });
 
-    while let Some(ref request) = receive_request(&mut stream).await? {
-        match request {
-            ClientToServerMessage::Action {
-                path,
-                action,
-                state_map,
-            } => {
-                log::info!("Received action", {path: path, action: action});
-                if let Some(route) = application.route(path) {
-                    let mut context = Context {
-                        state_map: state_map.clone(),
-                        stream: &mut stream,
-                    };
-
-                    route.action(action, &mut context).await?;
-                } else {
-                    log::error!("No route found", { path: path });
+    // Requests are read on their own task, so that waiting for one can be
+    // interrupted by a push without losing a partly read message. The queue
+    // is short, so a client that sends faster than its requests are handled
+    // is held back by the socket instead of filling the server's memory.
+    let (request_sender, requests) = channel::bounded(REQUEST_QUEUE_LENGTH);
+    let metrics = application.metrics();
+    metrics.connection_opened();
+
+    task::spawn(read_requests(
+        stream.clone(),
+        application.network_limits(),
+        metrics,
+        request_sender,
+    ));
+
+    let (push_sender, pushes) = channel::unbounded();
+    let mut connection = ConnectionState {
+        viewport: None,
+        locale: None,
+        strings: application.strings(),
+        next_ack_id: 0,
+        request_id: None,
+        compression: Compression::None,
+        wire_format: WireFormat::Cbor,
+        streaming: false,
+        bytes: false,
+        store_ack: false,
+        chunked_downloads: false,
+        current_path: None,
+        requests,
+        closing: closing.clone(),
+        push_sender,
+        deferred: VecDeque::new(),
+        metrics,
+    };
+
+    let result = serve(&application, &mut stream, &mut connection, pushes, closing).await;
+
+    // Also stops the reader, which has its own handle on the socket
+    stream.shutdown(Shutdown::Both).ok();
+    metrics.connection_closed();
+    result
+}
+
+async fn read_requests(
+    mut stream: TcpStream,
+    limits: NetworkLimits,
+    metrics: &'static dyn Metrics,
+    requests: channel::Sender<Result<ClientToServerMessage>>,
+) {
+    while let Some(request) = receive_request(&mut stream, &limits).await.transpose() {
+        let request = request.map(|(request, size)| {
+            metrics.request_received(size);
+            request
+        });
+
+        // After an error the stream may not be at the start of a message
+        let failed = request.is_err();
+        if requests.send(request).await.is_err() || failed {
+            break;
+        }
+    }
+}
+
+async fn serve(
+    application: &impl Application,
+    stream: &mut TcpStream,
+    connection: &mut ConnectionState,
+    pushes: Receiver<Pushed>,
+    closing: Receiver<()>,
+) -> Result<()> {
+    let idle_timeout = application.idle_timeout();
+    let mut last_request = Instant::now();
+
+    loop {
+        let idle_deadline = idle_timeout.map(|timeout| last_request + timeout);
+        let idle = async move {
+            match idle_deadline {
+                Some(deadline) => {
+                    task::sleep(deadline.saturating_duration_since(Instant::now())).await
                 }
+                None => future::pending().await,
             }
+        };
 
-            ClientToServerMessage::Load { path, storage } => {
-                if let Some(route) = application.route(path) {
-                    match route.render(storage).await {
-                        Render::Document(document) => {
-                            send_response(&mut stream, ServerToClientMessage::Render { document })
-                        }
-                        Render::RedirectTo(path) => {
-                            send_response(&mut stream, ServerToClientMessage::RedirectTo { path })
-                        }
+        let request = match connection.deferred.pop_front() {
+            Some(request) => request,
+            None => select! {
+                request = connection.requests.recv().fuse() => match request {
+                    Ok(request) => request?,
+                    Err(_) => break,
+                },
+                _ = idle.fuse() => {
+                    log::info!("Closing idle connection");
+                    respond(
+                        stream,
+                        connection,
+                        ServerToClientMessage::Error {
+                            code: ErrorCode::SessionTimeout,
+                            message: "Closing the connection after a period of inactivity"
+                                .to_string(),
+                            request_id: None,
+                        },
+                    )
+                    .await?;
+                    break;
+                },
+                pushed = pushes.recv().fuse() => {
+                    // The connection holds a sender, so this never runs dry
+                    if let Ok(pushed) = pushed {
+                        send_pushed(application, stream, connection, pushed).await?;
+                    }
+                    continue;
+                },
+                // Only while idle, so the request in progress is finished first
+                _ = closing.recv().fuse() => break,
+            },
+        };
+
+        last_request = Instant::now();
+        connection.request_id = request.request_id();
+
+        let result = AssertUnwindSafe(handle_request(application, stream, connection, &request))
+            .catch_unwind()
+            .await;
+
+        match result {
+            Ok(result) => result?,
+            Err(panic) => {
+                log::error!("Route panicked", { message: panic_message(&panic) });
+                respond(
+                    stream,
+                    connection,
+                    ServerToClientMessage::Error {
+                        code: ErrorCode::InternalServerError,
+                        message: "Internal server error".to_string(),
+                        request_id: None,
+                    },
+                )
+                .await?;
+            }
+        }
+
+        // Pushes and timeouts between requests don't answer any of them
+        connection.request_id = None;
+    }
+
+    Ok(())
+}
+
+/// Forwards a message a `PushHandle` sent, unless it's a document for a
+/// page the client has since left.
+async fn send_pushed(
+    application: &impl Application,
+    stream: &mut TcpStream,
+    connection: &ConnectionState,
+    Pushed { path, message }: Pushed,
+) -> Result<()> {
+    match message {
+        ServerToClientMessage::Render { document, .. } => {
+            if connection.current_path.as_ref() == Some(&path) {
+                send_document(
+                    application,
+                    stream,
+                    connection,
+                    &path,
+                    document,
+                    RenderMeta::default(),
+                    None,
+                )
+                .await
+            } else {
+                log::debug!("Dropping pushed render for a page no longer shown", { path: path });
+                Ok(())
+            }
+        }
+        message => respond(stream, connection, message).await,
+    }
+}
+
+/// What the server knows about a client for the lifetime of its connection.
+pub(crate) struct ConnectionState {
+    pub(crate) viewport: Option<Viewport>,
+    pub(crate) locale: Option<String>,
+    pub(crate) strings: Strings,
+    pub(crate) next_ack_id: u64,
+
+    /// The id of the request being handled, echoed in the answers to it
+    pub(crate) request_id: Option<u64>,
+
+    /// How responses are compressed, once both sides have agreed on it
+    pub(crate) compression: Compression,
+
+    /// How responses are serialized, once both sides have agreed on it
+    pub(crate) wire_format: WireFormat,
+
+    /// Whether the client can take large documents in parts
+    pub(crate) streaming: bool,
+
+    /// Whether the client can decode `StateValue::Bytes`
+    pub(crate) bytes: bool,
+
+    /// Whether the client acknowledges stores that ask it to
+    pub(crate) store_ack: bool,
+
+    /// Whether the client can take large files in parts
+    pub(crate) chunked_downloads: bool,
+
+    /// The path the client last loaded or acted on
+    pub(crate) current_path: Option<String>,
+
+    /// Requests as they're read from the client
+    pub(crate) requests: Receiver<Result<ClientToServerMessage>>,
+
+    /// Closes when the server is shutting down
+    pub(crate) closing: Receiver<()>,
+
+    /// Handed out in `PushHandle`s
+    pub(crate) push_sender: channel::Sender<Pushed>,
+
+    /// Requests that arrived while a route was waiting on the client
+    pub(crate) deferred: VecDeque<ClientToServerMessage>,
+
+    pub(crate) metrics: &'static dyn Metrics,
+}
+
+/// Sends a message to the client, counting it in the application's metrics.
+pub(crate) async fn respond(
+    stream: &mut TcpStream,
+    connection: &ConnectionState,
+    mut message: ServerToClientMessage,
+) -> Result<()> {
+    message.set_request_id(connection.request_id);
+    let size = send_response(
+        stream,
+        message,
+        connection.compression,
+        connection.wire_format,
+    )
+    .await?;
+    connection.metrics.response_sent(size);
+    Ok(())
+}
+
+/// Sends a rendered document to the client, as `respond` would.
+async fn respond_render(
+    stream: &mut TcpStream,
+    connection: &ConnectionState,
+    document: &EncodedDocument,
+    meta: RenderMeta,
+) -> Result<()> {
+    let size = send_encoded_document(
+        stream,
+        document,
+        Some(meta),
+        connection.request_id,
+        connection.compression,
+        connection.wire_format,
+    )
+    .await?;
+    connection.metrics.response_sent(size);
+    Ok(())
+}
+
+const MAX_PATH_LENGTH: usize = 2048;
+const MAX_ACTION_NAME_LENGTH: usize = 256;
+
+/// Checks the client-supplied strings that get matched against routes and
+/// written to logs. CBOR decoding has already ensured they're valid UTF-8.
+fn validate_request(request: &ClientToServerMessage) -> std::result::Result<(), String> {
+    fn check_path(path: &str) -> std::result::Result<(), String> {
+        if !path.starts_with('/') {
+            return Err("Path must start with '/'".to_string());
+        }
+
+        check("Path", path, MAX_PATH_LENGTH)
+    }
+
+    fn check(field: &str, value: &str, max_length: usize) -> std::result::Result<(), String> {
+        if value.len() > max_length {
+            Err(format!("{} is longer than {} bytes", field, max_length))
+        } else if value.chars().any(char::is_control) {
+            Err(format!("{} contains control characters", field))
+        } else {
+            Ok(())
+        }
+    }
+
+    match request {
+        ClientToServerMessage::Load { path, .. } | ClientToServerMessage::Prefetch { path, .. } => {
+            check_path(path)
+        }
+        ClientToServerMessage::Action { path, action, .. } => {
+            check_path(path)?;
+            check("Action name", &action.name, MAX_ACTION_NAME_LENGTH)
+        }
+        _ => Ok(()),
+    }
+}
+
+async fn handle_request(
+    application: &impl Application,
+    stream: &mut TcpStream,
+    connection: &mut ConnectionState,
+    request: &ClientToServerMessage,
+) -> Result<()> {
+    if let Err(message) = validate_request(request) {
+        log::warn!("Rejected bad request", { reason: message });
+        return respond(
+            stream,
+            connection,
+            ServerToClientMessage::Error {
+                code: ErrorCode::BadRequest,
+                message,
+                request_id: None,
+            },
+        )
+        .await;
+    }
+
+    match request {
+        ClientToServerMessage::Action {
+            path,
+            action,
+            state_map,
+            storage,
+            ..
+        } => {
+            log::info!("Received action", { path: path, action: action.name });
+            connection.current_path = Some(path.clone());
+            if let Some(render) = application.before_request(path, storage) {
+                log::debug!("Action stopped before reaching its route", { path: path });
+                send_render(
+                    application,
+                    stream,
+                    connection,
+                    path,
+                    render,
+                    RenderMeta::default(),
+                    None,
+                )
+                .await?;
+            } else if let Some((route, params)) = application.route(path) {
+                log::debug!("Action arguments", {
+                    arguments: redacted_arguments(action, state_map, route.sensitive_keys())
+                });
+
+                let mut context = Context {
+                    state_map: state_map.clone(),
+                    stream,
+                    path: path.clone(),
+                    connection: &mut *connection,
+                    meta: RenderMeta::default(),
+                    storage: storage.clone(),
+                    rerender: false,
+                };
+
+                let response = route.action(action, &params, &mut context).await?;
+                let Context {
+                    meta,
+                    storage,
+                    rerender,
+                    ..
+                } = context;
+
+                match response {
+                    ActionResponse::None => {}
+                    ActionResponse::Render(document) => {
+                        send_document(application, stream, connection, path, document, meta, None)
+                            .await?
+                    }
+                    ActionResponse::Redirect(path) => {
+                        respond(
+                            stream,
+                            connection,
+                            ServerToClientMessage::RedirectTo {
+                                path,
+                                request_id: None,
+                            },
+                        )
+                        .await?
+                    }
+                    ActionResponse::Data(value) => {
+                        // Clients that can't decode bytes get them as
+                        // numbers, as they would through JSON
+                        let value = if connection.bytes {
+                            value
+                        } else {
+                            value.without_bytes()
+                        };
+
+                        respond(
+                            stream,
+                            connection,
+                            ServerToClientMessage::ActionData { value },
+                        )
+                        .await?
+                    }
+                }
+
+                if rerender {
+                    load(application, stream, connection, path, &storage, None).await?;
+                }
+            } else {
+                log::error!("No route found", { path: path });
+            }
+        }
+
+        ClientToServerMessage::Load {
+            path,
+            storage,
+            version,
+            ..
+        } => {
+            connection.current_path = Some(path.clone());
+            load(application, stream, connection, path, storage, *version).await?
+        }
+
+        ClientToServerMessage::Prefetch { path, storage, .. } => {
+            // Redirects and oversized documents aren't worth prefetching, and
+            // the user hasn't asked for this page, so failures stay quiet
+            match render_route(application, stream, connection, path, storage).await {
+                Some((Render::Document(document), meta)) => {
+                    let document = document.encode()?;
+                    if check_document_size(application, path, document.size()).is_ok() {
+                        let meta = (meta != RenderMeta::default()).then_some(meta);
+                        let size = send_encoded_prefetched(
+                            stream,
+                            path,
+                            &document,
+                            meta,
+                            connection.compression,
+                            connection.wire_format,
+                        )
+                        .await?;
+                        connection.metrics.response_sent(size);
                     }
-                    .await?
-                } else {
-                    log::error!("No route found", { path: path });
                 }
+                Some((Render::RedirectTo(_), _)) => {}
+                None => log::debug!("No route found to prefetch", { path: path }),
             }
         }
+
+        ClientToServerMessage::StoreAck { id } => {
+            log::debug!("Ignoring unexpected store acknowledgement", { id: id });
+        }
+
+        ClientToServerMessage::Capabilities { capabilities } => {
+            let supported = supported_capabilities();
+            let common: Vec<String> = capabilities
+                .iter()
+                .filter(|capability| supported.contains(capability))
+                .cloned()
+                .collect();
+            log::debug!("Negotiated capabilities", { capabilities: format!("{:?}", common) });
+            connection.streaming = common.iter().any(|capability| capability == STREAMING);
+            connection.bytes = common.iter().any(|capability| capability == BYTES);
+            connection.store_ack = common.iter().any(|capability| capability == STORE_ACK);
+            connection.chunked_downloads = common
+                .iter()
+                .any(|capability| capability == CHUNKED_DOWNLOADS);
+
+            // The reply goes out uncompressed and as CBOR, since the client
+            // can't expect anything else until it has seen it
+            let deflate = common.iter().any(|capability| capability == DEFLATE);
+            let json = common.iter().any(|capability| capability == JSON);
+
+            respond(
+                stream,
+                connection,
+                ServerToClientMessage::Capabilities {
+                    capabilities: common,
+                },
+            )
+            .await?;
+
+            if deflate {
+                connection.compression = Compression::Deflate;
+            }
+
+            if json {
+                connection.wire_format = WireFormat::Json;
+            }
+        }
+
+        ClientToServerMessage::Ping => {
+            respond(stream, connection, ServerToClientMessage::Pong).await?
+        }
+
+        ClientToServerMessage::Unknown => {
+            log::debug!("Ignoring unknown request");
+        }
+
+        ClientToServerMessage::Locale { locale } => {
+            log::debug!("Locale changed", { locale: locale });
+            connection.locale = Some(locale.clone());
+        }
+
+        ClientToServerMessage::Viewport { width, height } => {
+            log::debug!("Viewport changed", { width: width, height: height });
+            connection.viewport = Some(Viewport {
+                width: *width,
+                height: *height,
+            });
+        }
+    }
+
+    Ok(())
+}
+
+/// An action's arguments and captured values for logging, with the values of
+/// `sensitive` keys hidden.
+fn redacted_arguments(action: &Action, state_map: &StateMap, sensitive: &[&str]) -> String {
+    let redact = |key: &str, value: String| {
+        if sensitive.contains(&key) {
+            "[redacted]".to_string()
+        } else {
+            value
+        }
+    };
+
+    let arguments: BTreeMap<&str, String> = action
+        .args
+        .iter()
+        .map(|(key, value)| (key.as_str(), redact(key, value.clone())))
+        .chain(
+            state_map
+                .iter()
+                .map(|(key, value)| (key.as_str(), redact(key, format!("{:?}", value)))),
+        )
+        .collect();
+
+    format!("{:?}", arguments)
+}
+
+/// Renders the route for `path` and sends the result to the client, or
+/// `NotModified` if it's the `version` the client already has.
+async fn load(
+    application: &impl Application,
+    stream: &mut TcpStream,
+    connection: &mut ConnectionState,
+    path: &str,
+    storage: &Storage,
+    version: Option<u64>,
+) -> Result<()> {
+    match render_route(application, stream, connection, path, storage).await {
+        Some((render, meta)) => {
+            send_render(application, stream, connection, path, render, meta, version).await
+        }
+        None => {
+            log::error!("No route found", { path: path });
+            Ok(())
+        }
+    }
+}
+
+async fn send_render(
+    application: &impl Application,
+    stream: &mut TcpStream,
+    connection: &ConnectionState,
+    path: &str,
+    render: Render,
+    meta: RenderMeta,
+    known_version: Option<u64>,
+) -> Result<()> {
+    match render {
+        Render::Document(document) => {
+            send_document(
+                application,
+                stream,
+                connection,
+                path,
+                document,
+                meta,
+                known_version,
+            )
+            .await
+        }
+        Render::RedirectTo(path) => {
+            respond(
+                stream,
+                connection,
+                ServerToClientMessage::RedirectTo {
+                    path,
+                    request_id: None,
+                },
+            )
+            .await
+        }
+    }
+}
+
+/// Renders the route for `path`, returning `None` if there isn't one. The
+/// application's `before_request` can answer instead of the route.
+async fn render_route(
+    application: &impl Application,
+    stream: &mut TcpStream,
+    connection: &mut ConnectionState,
+    path: &str,
+    storage: &Storage,
+) -> Option<(Render, RenderMeta)> {
+    if let Some(render) = application.before_request(path, storage) {
+        return Some((render, RenderMeta::default()));
+    }
+
+    let (route, params) = application.route(path)?;
+    let mut context = Context {
+        state_map: StateMap::new(),
+        stream,
+        path: path.to_string(),
+        connection,
+        meta: RenderMeta::default(),
+        storage: storage.clone(),
+        rerender: false,
+    };
+
+    let render = route.render(&params, storage, &mut context).await;
+    Some((render, context.meta))
+}
+
+async fn send_document(
+    application: &impl Application,
+    stream: &mut TcpStream,
+    connection: &ConnectionState,
+    path: &str,
+    document: Document,
+    mut meta: RenderMeta,
+    known_version: Option<u64>,
+) -> Result<()> {
+    let document = document.encode()?;
+    let version = document.version();
+    if known_version == Some(version) {
+        log::debug!("Document not modified", { path: path });
+        return respond(stream, connection, ServerToClientMessage::NotModified).await;
+    }
+
+    if let Err(message) = check_document_size(application, path, document.size()) {
+        return respond(
+            stream,
+            connection,
+            ServerToClientMessage::Error {
+                code: ErrorCode::DocumentTooLarge,
+                message,
+                request_id: None,
+            },
+        )
+        .await;
+    }
+
+    meta.version = Some(version);
+
+    if connection.streaming && should_stream(application, &document) {
+        let Document(Node::Container { layout, children }) = document.into_document() else {
+            unreachable!("only containers are streamed");
+        };
+        return send_streamed(stream, connection, layout, children, meta).await;
+    }
+
+    respond_render(stream, connection, &document, meta).await
+}
+
+/// Whether a document is big enough to send in parts, which only works if
+/// its root is a container.
+fn should_stream(application: &impl Application, document: &EncodedDocument) -> bool {
+    let Some(threshold) = application.document_size_limits().stream else {
+        return false;
+    };
+
+    matches!(document.document().0, Node::Container { .. }) && document.size() > threshold
+}
+
+/// Sends a container's children one message at a time, so the client can
+/// start receiving a large document before all of it has been encoded.
+async fn send_streamed(
+    stream: &mut TcpStream,
+    connection: &ConnectionState,
+    layout: Layout,
+    children: impl IntoIterator<Item = Box<Node>>,
+    meta: RenderMeta,
+) -> Result<()> {
+    log::debug!("Streaming document");
+
+    respond(
+        stream,
+        connection,
+        ServerToClientMessage::RenderBegin {
+            layout,
+            meta: Some(meta),
+        },
+    )
+    .await?;
+
+    for child in children {
+        respond(
+            stream,
+            connection,
+            ServerToClientMessage::RenderAppend { node: *child },
+        )
+        .await?;
+    }
+
+    respond(stream, connection, ServerToClientMessage::RenderEnd).await
+}
+
+/// Checks the encoded size of a rendered document against the
+/// application's limits, returning the message to send the client if it's
+/// too large to send.
+fn check_document_size(
+    application: &impl Application,
+    path: &str,
+    size: usize,
+) -> std::result::Result<(), String> {
+    let limits = application.document_size_limits();
+
+    if let Some(max) = limits.max.filter(|max| size > *max) {
+        log::error!("Document too large, refusing to send", { path: path, size: size, max: max });
+        return Err(format!(
+            "Document is {} bytes, over the {} byte limit",
+            size, max
+        ));
+    }
+
+    if let Some(warn) = limits.warn.filter(|warn| size > *warn) {
+        log::warn!("Document is unusually large", { path: path, size: size, warn: warn });
     }
 
     Ok(())
 }
 
+fn panic_message(panic: &Box<dyn Any + Send>) -> String {
+    if let Some(message) = panic.downcast_ref::<&str>() {
+        message.to_string()
+    } else if let Some(message) = panic.downcast_ref::<String>() {
+        message.clone()
+    } else {
+        "unknown panic".to_string()
+    }
+}
+
 fn spawn_and_log_error<F>(fut: F) -> task::JoinHandle<()>
 where
     F: Future<Output = Result<()>> + Send + 'static,
@@ -106,3 +935,1051 @@ where
         }
     })
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use async_trait::async_trait;
+    use pinhole_protocol::network::{receive_response, send_request};
+    use std::collections::HashMap;
+
+    #[derive(Copy, Clone)]
+    struct TestApp;
+
+    impl Application for TestApp {
+        fn routes(&self) -> Vec<BoxedRoute> {
+            routes![TestRoute, TodoRoute, DashboardRoute, ListRoute]
+        }
+    }
+
+    /// Serves the usual routes with other document size limits.
+    #[derive(Copy, Clone)]
+    struct SizedApp(DocumentSizeLimits);
+
+    impl Application for SizedApp {
+        fn routes(&self) -> Vec<BoxedRoute> {
+            TestApp.routes()
+        }
+
+        fn document_size_limits(&self) -> DocumentSizeLimits {
+            self.0
+        }
+    }
+
+    /// Serves the usual routes, closing connections idle for 200ms.
+    #[derive(Copy, Clone)]
+    struct IdleApp;
+
+    impl Application for IdleApp {
+        fn routes(&self) -> Vec<BoxedRoute> {
+            TestApp.routes()
+        }
+
+        fn idle_timeout(&self) -> Option<Duration> {
+            Some(Duration::from_millis(200))
+        }
+    }
+
+    const LIMITS: DocumentSizeLimits = DocumentSizeLimits {
+        warn: Some(100),
+        max: Some(200),
+        stream: Some(50),
+    };
+
+    /// Renders the id its path was loaded with.
+    struct TodoRoute;
+
+    #[async_trait]
+    impl Route for TodoRoute {
+        fn path(&self) -> &'static str {
+            "/todos/:id"
+        }
+
+        async fn action<'a>(
+            &self,
+            _action: &Action,
+            _params: &Params,
+            _context: &mut Context<'a>,
+        ) -> Result<ActionResponse> {
+            Ok(ActionResponse::None)
+        }
+
+        async fn render<'a>(
+            &self,
+            params: &Params,
+            _storage: &Storage,
+            _context: &mut Context<'a>,
+        ) -> Render {
+            let id = params.get("id").unwrap_or("missing");
+            Render::Document(Document(Node::text(format!("Todo {}", id))))
+        }
+    }
+
+    /// Shows a skeleton with a slot for sales, patches it, then renders
+    /// the whole dashboard.
+    struct DashboardRoute;
+
+    #[async_trait]
+    impl Route for DashboardRoute {
+        fn path(&self) -> &'static str {
+            "/dashboard"
+        }
+
+        async fn action<'a>(
+            &self,
+            _action: &Action,
+            _params: &Params,
+            _context: &mut Context<'a>,
+        ) -> Result<ActionResponse> {
+            Ok(ActionResponse::None)
+        }
+
+        async fn render<'a>(
+            &self,
+            _params: &Params,
+            _storage: &Storage,
+            context: &mut Context<'a>,
+        ) -> Render {
+            let skeleton = Node::Slot(SlotProps {
+                id: "sales".to_string(),
+                child: Node::text("Loading").boxed(),
+            });
+            context.render_skeleton(Document(skeleton)).await.unwrap();
+            context
+                .patch("sales", Node::text("Sales: 42"))
+                .await
+                .unwrap();
+
+            Render::Document(Document(
+                Node::container(Layout::default())
+                    .child(Node::text("Sales: 42"))
+                    .child(Node::text("Visitors: 7"))
+                    .build(),
+            ))
+        }
+    }
+
+    /// Renders a list of three items.
+    struct ListRoute;
+
+    #[async_trait]
+    impl Route for ListRoute {
+        fn path(&self) -> &'static str {
+            "/list"
+        }
+
+        async fn action<'a>(
+            &self,
+            _action: &Action,
+            _params: &Params,
+            _context: &mut Context<'a>,
+        ) -> Result<ActionResponse> {
+            Ok(ActionResponse::None)
+        }
+
+        async fn render<'a>(
+            &self,
+            _params: &Params,
+            _storage: &Storage,
+            _context: &mut Context<'a>,
+        ) -> Render {
+            Render::Document(Document(
+                Node::container(Layout::default())
+                    .children(["one", "two", "three"].map(Node::text))
+                    .build(),
+            ))
+        }
+    }
+
+    /// Answers `remember` by storing a batch of values and sending back what
+    /// it then has stored, `sign_in` by storing a token once the client has
+    /// it, `export` with a file of `size` bytes, `sign_out` by redirecting
+    /// home, `slow` after a pause, `crash` by panicking,
+    /// `spam`, `admin` and `delete_everything` by rejecting them as too
+    /// many requests, unauthorized and forbidden, and any other action with
+    /// an avatar's bytes.
+    struct TestRoute;
+
+    #[async_trait]
+    impl Route for TestRoute {
+        fn path(&self) -> &'static str {
+            "/"
+        }
+
+        async fn action<'a>(
+            &self,
+            action: &Action,
+            _params: &Params,
+            context: &mut Context<'a>,
+        ) -> Result<ActionResponse> {
+            if action.name == "remember" {
+                context
+                    .store_batch([
+                        (
+                            StorageScope::Session,
+                            "name",
+                            StateValue::String("Ada".to_string()),
+                        ),
+                        (StorageScope::Session, "visits", StateValue::Number(3.0)),
+                        (StorageScope::Session, "admin", StateValue::Boolean(true)),
+                    ])
+                    .await?;
+
+                let stored = context
+                    .storage
+                    .iter()
+                    .map(|(key, value)| (key.clone(), StateValue::String(value.clone())))
+                    .collect();
+                return Ok(ActionResponse::Data(StateValue::Object(stored)));
+            }
+
+            if action.name == "sign_in" {
+                context
+                    .store_and_wait(StorageScope::Session, "token", "abc")
+                    .await?;
+                return Ok(ActionResponse::Data(StateValue::Boolean(true)));
+            }
+
+            if action.name == "crash" {
+                panic!("crashed on purpose");
+            }
+
+            if action.name == "export" {
+                let size = action.arg_i64("size").unwrap() as usize;
+                context
+                    .download("export.bin", "application/octet-stream", vec![7; size])
+                    .await?;
+                return Ok(ActionResponse::None);
+            }
+
+            if action.name == "slow" {
+                task::sleep(Duration::from_millis(200)).await;
+                return Ok(ActionResponse::Data(StateValue::Boolean(true)));
+            }
+
+            if action.name == "sign_out" {
+                return Ok(ActionResponse::Redirect("/".to_string()));
+            }
+
+            match action.name.as_str() {
+                "spam" => context.reject_rate_limited().await?,
+                "admin" => context.reject_unauthorized().await?,
+                "delete_everything" => context.reject_forbidden().await?,
+                _ => {
+                    return Ok(ActionResponse::Data(StateValue::Object(HashMap::from([(
+                        "avatar".to_string(),
+                        StateValue::Bytes(vec![1, 2, 3]),
+                    )]))))
+                }
+            }
+
+            Ok(ActionResponse::None)
+        }
+
+        async fn render<'a>(
+            &self,
+            _params: &Params,
+            _storage: &Storage,
+            _context: &mut Context<'a>,
+        ) -> Render {
+            Render::Document(Document::empty())
+        }
+    }
+
+    /// A client's end of a connection served by `application`, and the
+    /// sender that keeps the server from closing it
+    async fn connect(application: impl Application + 'static) -> (TcpStream, channel::Sender<()>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let client = TcpStream::connect(listener.local_addr().unwrap())
+            .await
+            .unwrap();
+        let (server, _) = listener.accept().await.unwrap();
+
+        let (closing_sender, closing) = channel::bounded(1);
+        task::spawn(connection_loop(application, server, closing));
+        (client, closing_sender)
+    }
+
+    async fn request(
+        client: &mut TcpStream,
+        request: ClientToServerMessage,
+    ) -> ServerToClientMessage {
+        send_request(client, request, Compression::None, WireFormat::Cbor)
+            .await
+            .unwrap();
+        let (response, _size) = receive_response(client, &NetworkLimits::default())
+            .await
+            .unwrap()
+            .expect("connection closed");
+        response
+    }
+
+    #[test]
+    fn ping_is_answered_with_pong() {
+        task::block_on(async {
+            let (mut client, _closing) = connect(TestApp).await;
+
+            let response = request(&mut client, ClientToServerMessage::Ping).await;
+            assert!(matches!(response, ServerToClientMessage::Pong));
+
+            // The connection carries on as before
+            let response = request(&mut client, ClientToServerMessage::Ping).await;
+            assert!(matches!(response, ServerToClientMessage::Pong));
+        });
+    }
+
+    #[test]
+    fn pings_leave_route_dispatch_untouched() {
+        task::block_on(async {
+            let (mut client, _closing) = connect(TestApp).await;
+
+            for _ in 0..2 {
+                let response = request(&mut client, load_request("/")).await;
+                assert!(
+                    matches!(response, ServerToClientMessage::Render { ref document, .. } if *document == Document::empty()),
+                    "{:?}",
+                    response
+                );
+
+                let response = request(&mut client, ClientToServerMessage::Ping).await;
+                assert!(matches!(response, ServerToClientMessage::Pong));
+            }
+        });
+    }
+
+    #[test]
+    fn path_params_reach_the_route() {
+        task::block_on(async {
+            let (mut client, _closing) = connect(TestApp).await;
+
+            for id in ["1", "abc"] {
+                let path = format!("/todos/{}", id);
+                match request(&mut client, load_request(&path)).await {
+                    ServerToClientMessage::Render { document, .. } => {
+                        assert_eq!(document, Document(Node::text(format!("Todo {}", id))))
+                    }
+                    other => panic!("Expected a render, got {:?}", other),
+                }
+            }
+        });
+    }
+
+    #[test]
+    fn loading_the_version_the_client_has_is_not_modified() {
+        task::block_on(async {
+            let (mut client, _closing) = connect(TestApp).await;
+
+            let version = match request(&mut client, load_request("/todos/1")).await {
+                ServerToClientMessage::Render {
+                    meta:
+                        Some(RenderMeta {
+                            version: Some(version),
+                            ..
+                        }),
+                    ..
+                } => version,
+                other => panic!("Expected a versioned render, got {:?}", other),
+            };
+
+            let load_version = |path: &str, version| ClientToServerMessage::Load {
+                path: path.to_string(),
+                storage: HashMap::new(),
+                version: Some(version),
+                request_id: None,
+            };
+
+            let response = request(&mut client, load_version("/todos/1", version)).await;
+            assert!(
+                matches!(response, ServerToClientMessage::NotModified),
+                "{:?}",
+                response
+            );
+
+            // A different document isn't the version the client has
+            let response = request(&mut client, load_version("/todos/2", version)).await;
+            assert!(
+                matches!(response, ServerToClientMessage::Render { .. }),
+                "{:?}",
+                response
+            );
+        });
+    }
+
+    #[test]
+    fn prefetched_documents_are_sent_without_changing_the_page() {
+        task::block_on(async {
+            let (mut client, _closing) = connect(TestApp).await;
+
+            let prefetch = ClientToServerMessage::Prefetch {
+                path: "/todos/7".to_string(),
+                storage: HashMap::new(),
+                request_id: None,
+            };
+            match request(&mut client, prefetch).await {
+                ServerToClientMessage::Prefetched { path, document, .. } => {
+                    assert_eq!(path, "/todos/7");
+                    assert_eq!(document, Document(Node::text("Todo 7")));
+                }
+                other => panic!("Expected a prefetched document, got {:?}", other),
+            }
+        });
+    }
+
+    #[test]
+    fn a_panicking_route_is_an_internal_error_and_the_connection_carries_on() {
+        task::block_on(async {
+            let (mut client, _closing) = connect(TestApp).await;
+
+            let response = request(&mut client, action("crash")).await;
+            assert!(
+                matches!(
+                    response,
+                    ServerToClientMessage::Error {
+                        code: ErrorCode::InternalServerError,
+                        ..
+                    }
+                ),
+                "{:?}",
+                response
+            );
+
+            let response = request(&mut client, load_request("/todos/1")).await;
+            assert!(
+                matches!(response, ServerToClientMessage::Render { .. }),
+                "{:?}",
+                response
+            );
+        });
+    }
+
+    #[test]
+    fn documents_over_the_maximum_are_refused() {
+        let app = SizedApp(LIMITS);
+        assert!(check_document_size(&app, "/", 200).is_ok());
+
+        let message = check_document_size(&app, "/", 201).unwrap_err();
+        assert_eq!(message, "Document is 201 bytes, over the 200 byte limit");
+    }
+
+    #[test]
+    fn documents_over_the_warning_size_are_still_sent() {
+        let app = SizedApp(LIMITS);
+        assert!(check_document_size(&app, "/", 101).is_ok());
+
+        let unlimited = SizedApp(DocumentSizeLimits {
+            warn: None,
+            max: None,
+            stream: None,
+        });
+        assert!(check_document_size(&unlimited, "/", usize::MAX).is_ok());
+    }
+
+    #[test]
+    fn only_containers_over_the_streaming_size_are_streamed() {
+        let container = |count| {
+            Node::container(Layout::default())
+                .children((0..count).map(|n| Node::text(format!("Item {}", n))))
+                .build()
+        };
+        let small = Document(container(1)).encode().unwrap();
+        let large = Document(container(20)).encode().unwrap();
+        let text = Document(Node::text("x".repeat(small.size())))
+            .encode()
+            .unwrap();
+
+        // Anything bigger than the small container is worth streaming
+        let limits = DocumentSizeLimits {
+            stream: Some(small.size()),
+            ..LIMITS
+        };
+        let app = SizedApp(limits);
+        assert!(!should_stream(&app, &small));
+        assert!(should_stream(&app, &large));
+        assert!(!should_stream(&app, &text));
+
+        let never = SizedApp(DocumentSizeLimits {
+            stream: None,
+            ..limits
+        });
+        assert!(!should_stream(&never, &large));
+    }
+
+    fn is_bad_request(response: &ServerToClientMessage) -> bool {
+        matches!(
+            response,
+            ServerToClientMessage::Error {
+                code: ErrorCode::BadRequest,
+                ..
+            }
+        )
+    }
+
+    #[test]
+    fn malformed_paths_and_action_names_are_bad_requests() {
+        task::block_on(async {
+            let (mut client, _closing) = connect(TestApp).await;
+            let long_path = format!("/{}", "a".repeat(MAX_PATH_LENGTH));
+            let long_name = "a".repeat(MAX_ACTION_NAME_LENGTH + 1);
+
+            let mut long_path_action = action("upload");
+            if let ClientToServerMessage::Action { path, .. } = &mut long_path_action {
+                *path = long_path.clone();
+            }
+
+            let requests = [
+                load_request(&long_path),
+                load_request("todos/1"),
+                load_request("/todos/\u{1b}[2J"),
+                long_path_action,
+                action(&long_name),
+                action("up\nload"),
+            ];
+
+            for bad in requests {
+                let description = format!("{:?}", bad);
+                let response = request(&mut client, bad).await;
+                assert!(is_bad_request(&response), "{}: {:?}", description, response);
+            }
+
+            // The limits themselves are allowed
+            let longest_path = format!("/todos/{}", "a".repeat(MAX_PATH_LENGTH - 7));
+            let response = request(&mut client, load_request(&longest_path)).await;
+            assert!(
+                matches!(response, ServerToClientMessage::Render { .. }),
+                "{:?}",
+                response
+            );
+
+            let longest_name = "a".repeat(MAX_ACTION_NAME_LENGTH);
+            let response = request(&mut client, action(&longest_name)).await;
+            assert!(!is_bad_request(&response), "{:?}", response);
+        });
+    }
+
+    #[test]
+    fn sensitive_arguments_are_redacted() {
+        let action = Action::named("sign_in", vec![])
+            .arg("email", "ada@example.com")
+            .arg("password", "hunter2");
+        let state_map = StateMap::from([
+            ("remember".to_string(), StateValue::Boolean(true)),
+            ("pin".to_string(), StateValue::String("1234".to_string())),
+        ]);
+
+        let logged = redacted_arguments(&action, &state_map, &["password", "pin"]);
+        assert_eq!(
+            logged,
+            r#"{"email": "ada@example.com", "password": "[redacted]", "pin": "[redacted]", "remember": "Boolean(true)"}"#
+        );
+        assert!(!logged.contains("hunter2"));
+        assert!(!logged.contains("1234"));
+    }
+
+    fn load_request(path: &str) -> ClientToServerMessage {
+        ClientToServerMessage::Load {
+            path: path.to_string(),
+            storage: HashMap::new(),
+            version: None,
+            request_id: None,
+        }
+    }
+
+    fn action(name: &str) -> ClientToServerMessage {
+        action_request(Action::named(name, vec![]))
+    }
+
+    fn action_request(action: Action) -> ClientToServerMessage {
+        ClientToServerMessage::Action {
+            path: "/".to_string(),
+            action,
+            state_map: StateMap::new(),
+            storage: HashMap::new(),
+            request_id: None,
+        }
+    }
+
+    /// The avatar an action's data carries, after advertising `capabilities`
+    fn avatar_data(capabilities: &[&str]) -> StateValue {
+        task::block_on(async {
+            let (mut client, _closing) = connect(TestApp).await;
+            let capabilities = capabilities.iter().map(|c| c.to_string()).collect();
+            request(
+                &mut client,
+                ClientToServerMessage::Capabilities { capabilities },
+            )
+            .await;
+
+            match request(&mut client, action("upload")).await {
+                ServerToClientMessage::ActionData {
+                    value: StateValue::Object(mut fields),
+                } => fields.remove("avatar").unwrap(),
+                other => panic!("Expected action data, got {:?}", other),
+            }
+        })
+    }
+
+    #[test]
+    fn action_data_keeps_bytes_for_clients_that_decode_them() {
+        assert_eq!(avatar_data(&[BYTES]), StateValue::Bytes(vec![1, 2, 3]));
+    }
+
+    #[test]
+    fn action_data_sends_bytes_as_numbers_to_other_clients() {
+        let numbers = [1., 2., 3.].map(StateValue::Number).to_vec();
+        assert_eq!(avatar_data(&[]), StateValue::Array(numbers));
+    }
+
+    #[test]
+    fn a_batch_arrives_as_one_message_and_every_value_persists() {
+        task::block_on(async {
+            let (mut client, _closing) = connect(TestApp).await;
+
+            match request(&mut client, action("remember")).await {
+                ServerToClientMessage::StoreBatch { items } => {
+                    let keys: Vec<&str> = items.iter().map(|(_, key, _)| key.as_str()).collect();
+                    assert_eq!(keys, ["name", "visits", "admin"]);
+                }
+                other => panic!("Expected a store batch, got {:?}", other),
+            }
+
+            let stored = match receive(&mut client).await {
+                ServerToClientMessage::ActionData { value } => value,
+                other => panic!("Expected action data, got {:?}", other),
+            };
+            let expected: StateValue = serde_json::json!({
+                "name": "Ada",
+                "visits": "3",
+                "admin": "true",
+            })
+            .into();
+            assert_eq!(stored, expected);
+        });
+    }
+
+    /// What an `export` of `size` bytes sends, after advertising
+    /// `capabilities`
+    fn export(size: usize, capabilities: &[&str]) -> Vec<ServerToClientMessage> {
+        task::block_on(async {
+            let (mut client, _closing) = connect(TestApp).await;
+            let capabilities = capabilities.iter().map(|c| c.to_string()).collect();
+            request(
+                &mut client,
+                ClientToServerMessage::Capabilities { capabilities },
+            )
+            .await;
+
+            let export = Action::named("export", vec![]).arg("size", size);
+            let mut messages = vec![request(&mut client, action_request(export)).await];
+            while matches!(
+                messages.last(),
+                Some(
+                    ServerToClientMessage::DownloadBegin { .. }
+                        | ServerToClientMessage::DownloadChunk { .. }
+                )
+            ) {
+                messages.push(receive(&mut client).await);
+            }
+            messages
+        })
+    }
+
+    #[test]
+    fn small_downloads_arrive_in_one_message() {
+        let messages = export(3, &[CHUNKED_DOWNLOADS]);
+        assert!(
+            matches!(
+                messages.as_slice(),
+                [ServerToClientMessage::Download { filename, bytes, .. }] if filename == "export.bin" && *bytes == [7; 3]
+            ),
+            "{:?}",
+            messages
+        );
+    }
+
+    #[test]
+    fn large_downloads_arrive_in_parts_to_clients_that_take_them() {
+        let size = DOWNLOAD_CHUNK_SIZE + 1;
+
+        let parts: Vec<usize> = export(size, &[CHUNKED_DOWNLOADS])
+            .into_iter()
+            .map(|message| match message {
+                ServerToClientMessage::DownloadBegin { filename, .. } => {
+                    assert_eq!(filename, "export.bin");
+                    0
+                }
+                ServerToClientMessage::DownloadChunk { bytes } => bytes.len(),
+                ServerToClientMessage::DownloadEnd => 0,
+                other => panic!("Expected part of a download, got {:?}", other),
+            })
+            .collect();
+        assert_eq!(parts, [0, DOWNLOAD_CHUNK_SIZE, 1, 0]);
+
+        let messages = export(size, &[]);
+        assert!(
+            matches!(messages.as_slice(), [ServerToClientMessage::Download { bytes, .. }] if bytes.len() == size),
+            "{} messages",
+            messages.len()
+        );
+    }
+
+    async fn receive(client: &mut TcpStream) -> ServerToClientMessage {
+        let (response, _size) = receive_response(client, &NetworkLimits::default())
+            .await
+            .unwrap()
+            .expect("connection closed");
+        response
+    }
+
+    #[test]
+    fn acknowledged_stores_wait_for_the_client() {
+        task::block_on(async {
+            let (mut client, _closing) = connect(TestApp).await;
+            let capabilities = vec![STORE_ACK.to_string()];
+            request(
+                &mut client,
+                ClientToServerMessage::Capabilities { capabilities },
+            )
+            .await;
+
+            let id = match request(&mut client, action("sign_in")).await {
+                ServerToClientMessage::Store {
+                    key, ack: Some(id), ..
+                } if key == "token" => id,
+                other => panic!("Expected a store to acknowledge, got {:?}", other),
+            };
+
+            // The route is still waiting, but pings are answered meanwhile
+            let response = request(&mut client, ClientToServerMessage::Ping).await;
+            assert!(matches!(response, ServerToClientMessage::Pong));
+
+            let response = request(&mut client, ClientToServerMessage::StoreAck { id }).await;
+            assert!(
+                matches!(
+                    response,
+                    ServerToClientMessage::ActionData {
+                        value: StateValue::Boolean(true)
+                    }
+                ),
+                "{:?}",
+                response
+            );
+        });
+    }
+
+    #[test]
+    fn stores_arent_acknowledged_by_clients_that_cant() {
+        task::block_on(async {
+            let (mut client, _closing) = connect(TestApp).await;
+
+            let response = request(&mut client, action("sign_in")).await;
+            assert!(
+                matches!(response, ServerToClientMessage::Store { ack: None, .. }),
+                "{:?}",
+                response
+            );
+            assert!(matches!(
+                receive(&mut client).await,
+                ServerToClientMessage::ActionData { .. }
+            ));
+        });
+    }
+
+    #[test]
+    fn skeletons_and_patches_arrive_before_the_finished_render() {
+        task::block_on(async {
+            let (mut client, _closing) = connect(TestApp).await;
+
+            let skeleton = request(&mut client, load_request("/dashboard")).await;
+            assert!(
+                matches!(&skeleton, ServerToClientMessage::Render { document: Document(Node::Slot(slot)), meta: None, .. } if slot.id == "sales"),
+                "{:?}",
+                skeleton
+            );
+
+            let patch = receive(&mut client).await;
+            assert!(
+                matches!(&patch, ServerToClientMessage::Patch { id, node } if id == "sales" && *node == Node::text("Sales: 42")),
+                "{:?}",
+                patch
+            );
+
+            let render = receive(&mut client).await;
+            assert!(
+                matches!(&render, ServerToClientMessage::Render { document: Document(Node::Container { children, .. }), meta: Some(_), .. } if children.len() == 2),
+                "{:?}",
+                render
+            );
+        });
+    }
+
+    /// What loading the list gives a client with `capabilities`, from a
+    /// server that streams documents over 10 bytes.
+    fn load_list(capabilities: &[&str]) -> Vec<ServerToClientMessage> {
+        task::block_on(async {
+            let app = SizedApp(DocumentSizeLimits {
+                stream: Some(10),
+                ..DocumentSizeLimits::default()
+            });
+            let (mut client, _closing) = connect(app).await;
+            let capabilities = capabilities.iter().map(|c| c.to_string()).collect();
+            request(
+                &mut client,
+                ClientToServerMessage::Capabilities { capabilities },
+            )
+            .await;
+
+            let mut messages = vec![request(&mut client, load_request("/list")).await];
+            while matches!(
+                messages.last(),
+                Some(
+                    ServerToClientMessage::RenderBegin { .. }
+                        | ServerToClientMessage::RenderAppend { .. }
+                )
+            ) {
+                messages.push(receive(&mut client).await);
+            }
+            messages
+        })
+    }
+
+    #[test]
+    fn large_documents_are_streamed_to_clients_that_take_them() {
+        let messages = load_list(&[STREAMING]);
+
+        let [ServerToClientMessage::RenderBegin {
+            meta: Some(meta), ..
+        }, appended @ .., ServerToClientMessage::RenderEnd] = messages.as_slice()
+        else {
+            panic!("Expected a streamed document, got {:?}", messages);
+        };
+        assert!(meta.version.is_some());
+
+        let children: Vec<&Node> = appended
+            .iter()
+            .map(|message| match message {
+                ServerToClientMessage::RenderAppend { node } => node,
+                message => panic!("Expected part of the document, got {:?}", message),
+            })
+            .collect();
+        assert_eq!(
+            children,
+            [&Node::text("one"), &Node::text("two"), &Node::text("three")]
+        );
+    }
+
+    #[test]
+    fn large_documents_are_rendered_whole_for_clients_that_dont_stream() {
+        let messages = load_list(&[]);
+
+        assert!(
+            matches!(
+                messages.as_slice(),
+                [ServerToClientMessage::Render { document: Document(Node::Container { children, .. }), .. }] if children.len() == 3
+            ),
+            "{:?}",
+            messages
+        );
+    }
+
+    #[test]
+    fn rejected_actions_are_errors_coded_by_reason() {
+        task::block_on(async {
+            let (mut client, _closing) = connect(TestApp).await;
+
+            for (name, expected) in [
+                ("spam", ErrorCode::TooManyRequests),
+                ("admin", ErrorCode::Unauthorized),
+                ("delete_everything", ErrorCode::Forbidden),
+            ] {
+                let response = request(&mut client, action(name)).await;
+                assert!(
+                    matches!(&response, ServerToClientMessage::Error { code, .. } if *code == expected),
+                    "{}: {:?}",
+                    name,
+                    response
+                );
+            }
+        });
+    }
+
+    #[test]
+    fn answers_carry_the_id_of_their_request() {
+        task::block_on(async {
+            let (mut client, _closing) = connect(TestApp).await;
+
+            let load = ClientToServerMessage::Load {
+                path: "/todos/1".to_string(),
+                storage: HashMap::new(),
+                version: None,
+                request_id: Some(5),
+            };
+            let render = request(&mut client, load).await;
+            assert!(
+                matches!(
+                    render,
+                    ServerToClientMessage::Render {
+                        request_id: Some(5),
+                        ..
+                    }
+                ),
+                "{:?}",
+                render
+            );
+
+            let sign_out = ClientToServerMessage::Action {
+                path: "/".to_string(),
+                action: Action::named("sign_out", vec![]),
+                state_map: StateMap::new(),
+                storage: HashMap::new(),
+                request_id: Some(6),
+            };
+            let redirect = request(&mut client, sign_out).await;
+            assert!(
+                matches!(
+                    redirect,
+                    ServerToClientMessage::RedirectTo {
+                        request_id: Some(6),
+                        ..
+                    }
+                ),
+                "{:?}",
+                redirect
+            );
+
+            let bad = ClientToServerMessage::Load {
+                path: "todos".to_string(),
+                storage: HashMap::new(),
+                version: None,
+                request_id: Some(7),
+            };
+            let error = request(&mut client, bad).await;
+            assert!(
+                matches!(
+                    error,
+                    ServerToClientMessage::Error {
+                        code: ErrorCode::BadRequest,
+                        request_id: Some(7),
+                        ..
+                    }
+                ),
+                "{:?}",
+                error
+            );
+        });
+    }
+
+    /// Whether the server has closed the client's connection
+    async fn closed(client: &mut TcpStream) -> bool {
+        matches!(
+            receive_response(client, &NetworkLimits::default()).await,
+            Ok(None)
+        )
+    }
+
+    #[test]
+    fn idle_connections_time_out_and_close() {
+        task::block_on(async {
+            let (mut client, _closing) = connect(IdleApp).await;
+
+            let response = receive(&mut client).await;
+            assert!(
+                matches!(
+                    response,
+                    ServerToClientMessage::Error {
+                        code: ErrorCode::SessionTimeout,
+                        ..
+                    }
+                ),
+                "{:?}",
+                response
+            );
+            assert!(closed(&mut client).await);
+        });
+    }
+
+    #[test]
+    fn requests_keep_connections_from_timing_out() {
+        task::block_on(async {
+            let (mut client, _closing) = connect(IdleApp).await;
+
+            // Long past the timeout in all, but never idle for that long
+            for _ in 0..6 {
+                task::sleep(Duration::from_millis(50)).await;
+                let response = request(&mut client, ClientToServerMessage::Ping).await;
+                assert!(
+                    matches!(response, ServerToClientMessage::Pong),
+                    "{:?}",
+                    response
+                );
+            }
+        });
+    }
+
+    #[test]
+    fn closing_waits_for_the_request_in_progress() {
+        task::block_on(async {
+            let (mut client, closing) = connect(TestApp).await;
+
+            send_request(
+                &mut client,
+                action("slow"),
+                Compression::None,
+                WireFormat::Cbor,
+            )
+            .await
+            .unwrap();
+            task::sleep(Duration::from_millis(50)).await;
+            drop(closing);
+
+            let response = receive(&mut client).await;
+            assert!(
+                matches!(response, ServerToClientMessage::ActionData { .. }),
+                "{:?}",
+                response
+            );
+            assert!(closed(&mut client).await);
+        });
+    }
+
+    #[test]
+    fn shutting_down_drains_connections_and_stops_accepting() {
+        task::block_on(async {
+            // A free port, to serve on once it's let go
+            let address = TcpListener::bind("127.0.0.1:0")
+                .await
+                .unwrap()
+                .local_addr()
+                .unwrap();
+
+            let (shutdown_sender, shutdown) = channel::bounded::<()>(1);
+            let server = task::spawn(accept_loop(TestApp, address, async move {
+                shutdown.recv().await.ok();
+            }));
+
+            let mut client = retry(3, Duration::from_millis(50), || TcpStream::connect(address))
+                .await
+                .unwrap();
+            send_request(
+                &mut client,
+                action("slow"),
+                Compression::None,
+                WireFormat::Cbor,
+            )
+            .await
+            .unwrap();
+            task::sleep(Duration::from_millis(50)).await;
+            drop(shutdown_sender);
+
+            let response = receive(&mut client).await;
+            assert!(
+                matches!(response, ServerToClientMessage::ActionData { .. }),
+                "{:?}",
+                response
+            );
+            assert!(closed(&mut client).await);
+
+            server.await.unwrap();
+            assert!(TcpStream::connect(address).await.is_err());
+        });
+    }
+}
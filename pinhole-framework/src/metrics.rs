@@ -0,0 +1,19 @@
+/// Told what the server is doing, e.g. to keep counters for Prometheus.
+/// Methods are called from every connection's task at once, so an
+/// implementation usually keeps atomics. Each does nothing by default.
+pub trait Metrics: Send + Sync {
+    fn connection_opened(&self) {}
+
+    fn connection_closed(&self) {}
+
+    /// A request arrived that took `bytes` bytes on the wire
+    fn request_received(&self, _bytes: usize) {}
+
+    /// A message was sent to a client that took `bytes` bytes on the wire
+    fn response_sent(&self, _bytes: usize) {}
+}
+
+/// Metrics that go nowhere, used unless the application provides its own.
+pub struct NoMetrics;
+
+impl Metrics for NoMetrics {}
@@ -0,0 +1,66 @@
+use async_std::channel::Sender;
+
+use crate::{Document, Result, ServerToClientMessage, StorageScope};
+
+/// A message sent to a client outside of any request.
+pub(crate) struct Pushed {
+    /// The page that was showing when the handle was made
+    pub(crate) path: String,
+    pub(crate) message: ServerToClientMessage,
+}
+
+/// Sends messages to one client without it asking, e.g. to show a chat
+/// message as soon as it arrives. Get one from `Context::push_handle` and
+/// keep it wherever the updates come from; it can be cloned and outlives the
+/// request it came from.
+///
+/// Sending fails once the client has disconnected, which is the cue to drop
+/// the handle.
+#[derive(Clone)]
+pub struct PushHandle {
+    pub(crate) path: String,
+    pub(crate) sender: Sender<Pushed>,
+}
+
+impl PushHandle {
+    /// Replaces the client's document. It's dropped if the client has
+    /// navigated away from the page the handle came from.
+    pub async fn render(&self, document: Document) -> Result<()> {
+        self.send(ServerToClientMessage::Render {
+            document,
+            meta: None,
+            request_id: None,
+        })
+        .await
+    }
+
+    pub async fn store(
+        &self,
+        scope: StorageScope,
+        key: impl ToString,
+        value: impl ToString,
+    ) -> Result<()> {
+        let key = key.to_string();
+        let value = value.to_string();
+        self.send(ServerToClientMessage::Store {
+            scope,
+            key,
+            value,
+            ack: None,
+        })
+        .await
+    }
+
+    /// Whether the client is still connected.
+    pub fn is_connected(&self) -> bool {
+        !self.sender.is_closed()
+    }
+
+    async fn send(&self, message: ServerToClientMessage) -> Result<()> {
+        let path = self.path.clone();
+        self.sender
+            .send(Pushed { path, message })
+            .await
+            .map_err(|_| "Client has disconnected".into())
+    }
+}
@@ -0,0 +1,92 @@
+use async_std::{future::Future, task};
+use std::time::Duration;
+
+/// Runs a fallible async operation up to `attempts` times, waiting `backoff`
+/// after the first failure and doubling the wait after each one after that.
+/// Returns the first success, or the last error if every attempt fails.
+pub async fn retry<T, E, F, Fut>(
+    attempts: usize,
+    backoff: Duration,
+    mut operation: F,
+) -> Result<T, E>
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Result<T, E>>,
+{
+    let mut delay = backoff;
+    let mut attempt = 1;
+
+    loop {
+        match operation().await {
+            Ok(value) => return Ok(value),
+            Err(err) if attempt >= attempts => return Err(err),
+            Err(_) => {
+                log::debug!("Attempt {} failed, retrying in {:?}", attempt, delay);
+                task::sleep(delay).await;
+                delay = delay.saturating_mul(2);
+                attempt += 1;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Instant;
+
+    #[test]
+    fn returns_the_first_success() {
+        let mut calls = 0;
+        let result: Result<usize, &str> = task::block_on(retry(5, Duration::ZERO, || {
+            calls += 1;
+            let calls = calls;
+            async move {
+                if calls < 3 {
+                    Err("not yet")
+                } else {
+                    Ok(calls)
+                }
+            }
+        }));
+
+        assert_eq!(result, Ok(3));
+        assert_eq!(calls, 3);
+    }
+
+    #[test]
+    fn returns_the_last_error_after_every_attempt_fails() {
+        let mut calls = 0;
+        let result: Result<(), usize> = task::block_on(retry(4, Duration::ZERO, || {
+            calls += 1;
+            let calls = calls;
+            async move { Err(calls) }
+        }));
+
+        assert_eq!(result, Err(4));
+        assert_eq!(calls, 4);
+    }
+
+    #[test]
+    fn always_makes_at_least_one_attempt() {
+        let mut calls = 0;
+        let result: Result<(), ()> = task::block_on(retry(0, Duration::ZERO, || {
+            calls += 1;
+            async { Err(()) }
+        }));
+
+        assert_eq!(result, Err(()));
+        assert_eq!(calls, 1);
+    }
+
+    #[test]
+    fn doubles_the_wait_after_each_failure() {
+        let start = Instant::now();
+        let result: Result<(), ()> =
+            task::block_on(retry(4, Duration::from_millis(10), || async { Err(()) }));
+
+        // Waits of 10, 20 and 40ms between the four attempts
+        assert_eq!(result, Err(()));
+        assert!(start.elapsed() >= Duration::from_millis(70));
+    }
+}
@@ -1,6 +1,6 @@
 use async_trait::async_trait;
 
-use crate::{Action, Context, Document, Result};
+use crate::{Action, Context, Document, Params, Result, StateValue};
 use std::collections::HashMap;
 
 pub type Storage = HashMap<String, String>;
@@ -10,9 +10,67 @@ pub enum Render {
     RedirectTo(String),
 }
 
+/// What the client is sent once an action is done, after anything the
+/// action sent through its `Context`.
+#[derive(Default)]
+pub enum ActionResponse {
+    #[default]
+    None,
+
+    /// Replaces the client's document, e.g. with the page in its new state
+    Render(Document),
+
+    /// Sends the client to another path
+    Redirect(String),
+
+    /// An object whose fields are merged into the client's state map, e.g.
+    /// to fill in form fields
+    Data(StateValue),
+}
+
+/// A page in the application.
+///
+/// `path` is a pattern: segments like `:id` match any one segment and
+/// `*rest` matches the remainder of the path, and what they matched is
+/// passed to `action` and `render` as `Params`.
+///
+/// A panic in `action` or `render` is caught by the framework and reported to
+/// the client as an `ErrorCode::InternalServerError`, leaving the connection
+/// open. The futures are treated as unwind-safe, so routes shouldn't leave
+/// shared state half-updated across a panic.
 #[async_trait]
 pub trait Route: Send + Sync {
     fn path(&self) -> &'static str;
-    async fn action<'a>(&self, action: &Action, context: &mut Context<'a>) -> Result<()>;
-    async fn render(&self, storage: &Storage) -> Render;
+    async fn action<'a>(
+        &self,
+        action: &Action,
+        params: &Params,
+        context: &mut Context<'a>,
+    ) -> Result<ActionResponse>;
+    async fn render<'a>(
+        &self,
+        params: &Params,
+        storage: &Storage,
+        context: &mut Context<'a>,
+    ) -> Render;
+
+    /// Action arguments and captured values, such as passwords, whose values
+    /// are hidden when actions are logged.
+    fn sensitive_keys(&self) -> &'static [&'static str] {
+        &[]
+    }
+}
+
+/// Builds the route list for `Application::routes`, boxing each route.
+///
+/// ```ignore
+/// fn routes(&self) -> Vec<BoxedRoute> {
+///     routes![IndexRoute, ListRoute]
+/// }
+/// ```
+#[macro_export]
+macro_rules! routes {
+    ($($route:expr),* $(,)?) => {
+        vec![$(Box::new($route) as $crate::BoxedRoute),*]
+    };
 }
@@ -0,0 +1,215 @@
+use std::{cmp::Ordering, collections::HashMap};
+
+/// Values captured from the request path by a route's `:name` and `*name`
+/// segments.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Params(HashMap<String, String>);
+
+impl Params {
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.0.get(name).map(String::as_str)
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+enum Segment {
+    Literal(String),
+
+    /// `:name` matches any one segment
+    Param(String),
+
+    /// `*name` matches the rest of the path, including nothing
+    CatchAll(String),
+}
+
+impl Segment {
+    /// Literals are more specific than parameters, which are more specific
+    /// than catch-alls.
+    fn rank(&self) -> u8 {
+        match self {
+            Segment::Literal(_) => 2,
+            Segment::Param(_) => 1,
+            Segment::CatchAll(_) => 0,
+        }
+    }
+}
+
+/// A route path such as `/todos/:id` or `/files/*path`.
+#[derive(Clone, Debug)]
+pub(crate) struct RoutePattern {
+    segments: Vec<Segment>,
+}
+
+impl RoutePattern {
+    pub fn parse(pattern: &str) -> Result<RoutePattern, String> {
+        let Some(rest) = pattern.strip_prefix('/') else {
+            return Err(format!("Route path {:?} must start with '/'", pattern));
+        };
+
+        let segments: Vec<Segment> = rest
+            .split('/')
+            .map(|segment| match segment.chars().next() {
+                Some(':') => Segment::Param(segment[1..].to_string()),
+                Some('*') => Segment::CatchAll(segment[1..].to_string()),
+                _ => Segment::Literal(segment.to_string()),
+            })
+            .collect();
+
+        for (index, segment) in segments.iter().enumerate() {
+            match segment {
+                Segment::Param(name) | Segment::CatchAll(name) if name.is_empty() => {
+                    return Err(format!("Route path {:?} has an unnamed parameter", pattern));
+                }
+                Segment::CatchAll(_) if index + 1 < segments.len() => {
+                    return Err(format!(
+                        "Route path {:?} has a catch-all before its last segment",
+                        pattern
+                    ));
+                }
+                _ => {}
+            }
+        }
+
+        Ok(RoutePattern { segments })
+    }
+
+    pub fn matches(&self, path: &str) -> Option<Params> {
+        let mut parts = path.strip_prefix('/')?.split('/');
+        let mut params = HashMap::new();
+
+        for segment in &self.segments {
+            match segment {
+                Segment::Literal(literal) => {
+                    if parts.next()? != literal {
+                        return None;
+                    }
+                }
+                Segment::Param(name) => {
+                    let part = parts.next().filter(|part| !part.is_empty())?;
+                    params.insert(name.clone(), part.to_string());
+                }
+                Segment::CatchAll(name) => {
+                    let rest: Vec<&str> = parts.by_ref().collect();
+                    params.insert(name.clone(), rest.join("/"));
+                }
+            }
+        }
+
+        match parts.next() {
+            Some(_) => None,
+            None => Some(Params(params)),
+        }
+    }
+
+    /// Orders patterns so that, of two that match the same path, the more
+    /// specific one is greater. Segments are compared from the left.
+    pub fn specificity(&self, other: &RoutePattern) -> Ordering {
+        let ranks = |pattern: &RoutePattern| -> Vec<u8> {
+            pattern.segments.iter().map(Segment::rank).collect()
+        };
+
+        ranks(self).cmp(&ranks(other))
+    }
+
+    /// Whether both patterns match exactly the same paths, i.e. they differ
+    /// at most in parameter names.
+    pub fn same_shape(&self, other: &RoutePattern) -> bool {
+        self.segments.len() == other.segments.len()
+            && self
+                .segments
+                .iter()
+                .zip(&other.segments)
+                .all(|pair| match pair {
+                    (Segment::Literal(a), Segment::Literal(b)) => a == b,
+                    (a, b) => a.rank() == b.rank(),
+                })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pattern(pattern: &str) -> RoutePattern {
+        RoutePattern::parse(pattern).unwrap()
+    }
+
+    fn params(pairs: &[(&str, &str)]) -> Params {
+        Params(
+            pairs
+                .iter()
+                .map(|(name, value)| (name.to_string(), value.to_string()))
+                .collect(),
+        )
+    }
+
+    #[test]
+    fn literals_match_only_themselves() {
+        assert_eq!(pattern("/").matches("/"), Some(params(&[])));
+        assert_eq!(pattern("/todos").matches("/todos"), Some(params(&[])));
+        assert_eq!(pattern("/todos").matches("/todo"), None);
+        assert_eq!(pattern("/todos").matches("/todos/1"), None);
+        assert_eq!(pattern("/todos/new").matches("/todos"), None);
+        assert_eq!(pattern("/todos").matches("todos"), None);
+    }
+
+    #[test]
+    fn params_capture_one_non_empty_segment() {
+        assert_eq!(
+            pattern("/todos/:id").matches("/todos/42"),
+            Some(params(&[("id", "42")]))
+        );
+        assert_eq!(
+            pattern("/:list/:id").matches("/work/7"),
+            Some(params(&[("list", "work"), ("id", "7")]))
+        );
+        assert_eq!(pattern("/todos/:id").matches("/todos/"), None);
+        assert_eq!(pattern("/todos/:id").matches("/todos/1/edit"), None);
+    }
+
+    #[test]
+    fn catch_alls_capture_the_rest_of_the_path() {
+        let files = pattern("/files/*path");
+
+        assert_eq!(
+            files.matches("/files/a/b/c.txt"),
+            Some(params(&[("path", "a/b/c.txt")]))
+        );
+        assert_eq!(files.matches("/files"), Some(params(&[("path", "")])));
+        assert_eq!(files.matches("/other/a"), None);
+    }
+
+    #[test]
+    fn bad_patterns_are_refused() {
+        assert!(RoutePattern::parse("todos").is_err());
+        assert!(RoutePattern::parse("/todos/:").is_err());
+        assert!(RoutePattern::parse("/files/*").is_err());
+        assert!(RoutePattern::parse("/files/*path/edit").is_err());
+    }
+
+    #[test]
+    fn more_specific_patterns_rank_higher() {
+        let literal = pattern("/todos/new");
+        let param = pattern("/todos/:id");
+        let catch_all = pattern("/todos/*rest");
+
+        assert_eq!(literal.specificity(&param), Ordering::Greater);
+        assert_eq!(param.specificity(&catch_all), Ordering::Greater);
+        assert_eq!(catch_all.specificity(&literal), Ordering::Less);
+
+        // Compared from the left, so an early literal wins
+        assert_eq!(
+            pattern("/a/:x").specificity(&pattern("/:x/b")),
+            Ordering::Greater
+        );
+    }
+
+    #[test]
+    fn same_shape_ignores_parameter_names() {
+        assert!(pattern("/todos/:id").same_shape(&pattern("/todos/:todo")));
+        assert!(pattern("/files/*a").same_shape(&pattern("/files/*b")));
+        assert!(!pattern("/todos/:id").same_shape(&pattern("/todos/new")));
+        assert!(!pattern("/todos/:id").same_shape(&pattern("/lists/:id")));
+        assert!(!pattern("/todos/:id").same_shape(&pattern("/todos/:id/edit")));
+    }
+}
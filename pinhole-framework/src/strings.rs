@@ -0,0 +1,109 @@
+use std::collections::HashMap;
+
+/// Message templates for one locale, keyed by message name. Templates can
+/// refer to arguments as `{name}`.
+pub type Bundle = HashMap<String, String>;
+
+/// Localized message bundles, looked up by the locale the client reports.
+#[derive(Clone, Debug, Default)]
+pub struct Strings {
+    default_locale: String,
+    bundles: HashMap<String, Bundle>,
+}
+
+impl Strings {
+    /// `default_locale` is used when the client's locale has no bundle.
+    pub fn new(default_locale: impl ToString) -> Strings {
+        Strings {
+            default_locale: default_locale.to_string(),
+            bundles: HashMap::new(),
+        }
+    }
+
+    pub fn with_bundle(mut self, locale: impl ToString, bundle: Bundle) -> Strings {
+        self.bundles.insert(locale.to_string(), bundle);
+        self
+    }
+
+    /// Formats the message `key` for `locale`, falling back from a regional
+    /// locale like `fr-CA` to its language `fr`, then to the default locale.
+    /// Returns the key itself if no bundle has the message.
+    pub fn get(&self, locale: Option<&str>, key: &str, args: &[(&str, &str)]) -> String {
+        let language = locale.and_then(|locale| locale.split('-').next());
+
+        let template = [locale, language, Some(self.default_locale.as_str())]
+            .into_iter()
+            .flatten()
+            .filter_map(|locale| self.bundles.get(locale))
+            .find_map(|bundle| bundle.get(key));
+
+        match template {
+            Some(template) => args.iter().fold(template.clone(), |text, (name, value)| {
+                text.replace(&format!("{{{}}}", name), value)
+            }),
+            None => key.to_string(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn bundle(messages: &[(&str, &str)]) -> Bundle {
+        messages
+            .iter()
+            .map(|(key, template)| (key.to_string(), template.to_string()))
+            .collect()
+    }
+
+    fn strings() -> Strings {
+        Strings::new("en")
+            .with_bundle(
+                "en",
+                bundle(&[("welcome", "Welcome, {name}!"), ("bye", "Goodbye")]),
+            )
+            .with_bundle("fr", bundle(&[("welcome", "Bienvenue, {name} !")]))
+            .with_bundle("fr-CA", bundle(&[("bye", "Bonjour")]))
+    }
+
+    #[test]
+    fn messages_are_looked_up_in_the_clients_locale() {
+        let strings = strings();
+        let args = [("name", "Ada")];
+
+        assert_eq!(
+            strings.get(Some("fr"), "welcome", &args),
+            "Bienvenue, Ada !"
+        );
+        assert_eq!(strings.get(Some("fr-CA"), "bye", &[]), "Bonjour");
+        assert_eq!(strings.get(Some("en"), "welcome", &args), "Welcome, Ada!");
+    }
+
+    #[test]
+    fn regional_locales_fall_back_to_their_language_then_the_default() {
+        let strings = strings();
+
+        assert_eq!(
+            strings.get(Some("fr-CA"), "welcome", &[("name", "Ada")]),
+            "Bienvenue, Ada !"
+        );
+        assert_eq!(strings.get(Some("fr"), "bye", &[]), "Goodbye");
+        assert_eq!(strings.get(Some("de-AT"), "bye", &[]), "Goodbye");
+        assert_eq!(strings.get(None, "bye", &[]), "Goodbye");
+    }
+
+    #[test]
+    fn missing_messages_are_their_key() {
+        assert_eq!(strings().get(Some("fr"), "missing", &[]), "missing");
+        assert_eq!(Strings::default().get(None, "welcome", &[]), "welcome");
+    }
+
+    #[test]
+    fn unknown_placeholders_are_left_alone() {
+        assert_eq!(
+            strings().get(None, "welcome", &[("other", "x")]),
+            "Welcome, {name}!"
+        );
+    }
+}
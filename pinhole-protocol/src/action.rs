@@ -1,10 +1,11 @@
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
 
-use serde::{Deserialize, Serialize};
+use serde::{Deserialize, Serialize, Serializer};
 
-#[derive(Clone, Debug, Serialize, Deserialize)]
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
 pub struct Action {
     pub name: String,
+    #[serde(serialize_with = "serialize_sorted")]
     pub args: HashMap<String, String>,
     pub keys: Vec<String>,
 }
@@ -21,10 +22,121 @@ impl Action {
             keys,
         }
     }
+
+    /// Adds the argument `key`, replacing any earlier value, e.g.
+    /// `Action::named("delete", vec![]).arg("id", &todo.id)`.
+    pub fn arg(mut self, key: impl ToString, value: impl ToString) -> Action {
+        self.args.insert(key.to_string(), value.to_string());
+        self
+    }
+
+    /// Adds `key` to the state map keys whose values the client sends along
+    /// with the action.
+    pub fn capture(mut self, key: impl ToString) -> Action {
+        self.keys.push(key.to_string());
+        self
+    }
+
+    pub fn arg_str(&self, key: &str) -> Option<&str> {
+        self.args.get(key).map(String::as_str)
+    }
+
+    /// The argument `key` as an integer, or `None` if it's missing or isn't
+    /// one.
+    pub fn arg_i64(&self, key: &str) -> Option<i64> {
+        self.arg_str(key)?.trim().parse().ok()
+    }
+
+    /// The argument `key` as a number, or `None` if it's missing or isn't
+    /// one.
+    pub fn arg_f64(&self, key: &str) -> Option<f64> {
+        self.arg_str(key)?.trim().parse().ok()
+    }
+
+    /// The argument `key` as a boolean, `"true"` or `"false"`, or `None` if
+    /// it's missing or something else.
+    pub fn arg_bool(&self, key: &str) -> Option<bool> {
+        self.arg_str(key)?.trim().parse().ok()
+    }
+}
+
+/// Encodes the arguments in key order, so equal documents encode the same
+/// and so have the same `Document::version`.
+fn serialize_sorted<S: Serializer>(
+    args: &HashMap<String, String>,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    args.iter()
+        .collect::<BTreeMap<_, _>>()
+        .serialize(serializer)
 }
 
 impl log::kv::ToValue for Action {
-    fn to_value(&self) -> log::kv::Value {
+    fn to_value(&self) -> log::kv::Value<'_> {
         log::kv::Value::from_debug(self)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn action() -> Action {
+        Action::named("update", vec![])
+            .arg("name", "Ada")
+            .arg("count", 42)
+            .arg("padded", " -7 ")
+            .arg("price", "2.5")
+            .arg("done", true)
+            .arg("empty", "")
+    }
+
+    #[test]
+    fn arg_str_returns_the_raw_value() {
+        let action = action();
+        assert_eq!(action.arg_str("name"), Some("Ada"));
+        assert_eq!(action.arg_str("count"), Some("42"));
+        assert_eq!(action.arg_str("empty"), Some(""));
+        assert_eq!(action.arg_str("missing"), None);
+    }
+
+    #[test]
+    fn arg_replaces_earlier_values() {
+        let action = action().arg("name", "Grace");
+        assert_eq!(action.arg_str("name"), Some("Grace"));
+    }
+
+    #[test]
+    fn arg_i64_parses_integers() {
+        let action = action();
+        assert_eq!(action.arg_i64("count"), Some(42));
+        assert_eq!(action.arg_i64("padded"), Some(-7));
+        assert_eq!(action.arg_i64("price"), None);
+        assert_eq!(action.arg_i64("name"), None);
+        assert_eq!(action.arg_i64("empty"), None);
+        assert_eq!(action.arg_i64("missing"), None);
+    }
+
+    #[test]
+    fn arg_f64_parses_numbers() {
+        let action = action();
+        assert_eq!(action.arg_f64("price"), Some(2.5));
+        assert_eq!(action.arg_f64("count"), Some(42.));
+        assert_eq!(action.arg_f64("padded"), Some(-7.));
+        assert_eq!(action.arg_f64("name"), None);
+        assert_eq!(action.arg_f64("missing"), None);
+    }
+
+    #[test]
+    fn arg_bool_parses_true_and_false_only() {
+        let action = action()
+            .arg("off", " false ")
+            .arg("yes", "yes")
+            .arg("one", 1);
+        assert_eq!(action.arg_bool("done"), Some(true));
+        assert_eq!(action.arg_bool("off"), Some(false));
+        assert_eq!(action.arg_bool("yes"), None);
+        assert_eq!(action.arg_bool("one"), None);
+        assert_eq!(action.arg_bool("missing"), None);
+    }
+}
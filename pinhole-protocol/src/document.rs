@@ -2,11 +2,103 @@ use serde::{Deserialize, Serialize};
 
 use crate::node::Node;
 
-#[derive(Clone, Debug, Serialize, Deserialize)]
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
 pub struct Document(pub Node);
 
 impl Document {
     pub fn empty() -> Document {
         Document(Node::Empty)
     }
+
+    /// Size of the document in bytes once encoded for the wire.
+    pub fn encoded_size(&self) -> Result<usize, serde_cbor::Error> {
+        Ok(serde_cbor::to_vec(self)?.len())
+    }
+
+    /// A hash of the encoded document, equal for equal documents whichever
+    /// build of the server computed it.
+    pub fn version(&self) -> Result<u64, serde_cbor::Error> {
+        Ok(fnv1a(&serde_cbor::to_vec(self)?))
+    }
+
+    /// Encodes the document for the wire once, so its version, its size and
+    /// the message that sends it can all be had from the same bytes.
+    pub fn encode(self) -> Result<EncodedDocument, serde_cbor::Error> {
+        let bytes = serde_cbor::to_vec(&self)?;
+        Ok(EncodedDocument {
+            document: self,
+            bytes,
+        })
+    }
+}
+
+/// A document with its CBOR encoding, from `Document::encode`.
+#[derive(Clone, Debug)]
+pub struct EncodedDocument {
+    document: Document,
+    bytes: Vec<u8>,
+}
+
+impl EncodedDocument {
+    pub fn document(&self) -> &Document {
+        &self.document
+    }
+
+    pub fn into_document(self) -> Document {
+        self.document
+    }
+
+    /// As `Document::encoded_size`.
+    pub fn size(&self) -> usize {
+        self.bytes.len()
+    }
+
+    /// As `Document::version`.
+    pub fn version(&self) -> u64 {
+        fnv1a(&self.bytes)
+    }
+
+    pub(crate) fn bytes(&self) -> &[u8] {
+        &self.bytes
+    }
+}
+
+/// The 64-bit FNV-1a hash of `bytes`. Unlike `DefaultHasher` it's fixed, so
+/// versions don't change when the server is built with a newer Rust.
+fn fnv1a(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf2_9ce4_8422_2325, |hash, byte| {
+        (hash ^ u64::from(*byte)).wrapping_mul(0x0100_0000_01b3)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fnv1a_matches_the_reference_values() {
+        assert_eq!(fnv1a(b""), 0xcbf2_9ce4_8422_2325);
+        assert_eq!(fnv1a(b"a"), 0xaf63_dc4c_8601_ec8c);
+        assert_eq!(fnv1a(b"foobar"), 0x8594_4171_f739_67e8);
+    }
+
+    #[test]
+    fn versions_follow_the_content() {
+        let hello = Document(Node::text("Hello"));
+        assert_eq!(hello.version().unwrap(), hello.clone().version().unwrap());
+        assert_ne!(
+            hello.version().unwrap(),
+            Document(Node::text("Goodbye")).version().unwrap()
+        );
+    }
+
+    #[test]
+    fn encoded_documents_agree_with_the_document() {
+        let document = Document(Node::text("Hello"));
+        let encoded = document.clone().encode().unwrap();
+
+        assert_eq!(encoded.version(), document.version().unwrap());
+        assert_eq!(encoded.size(), document.encoded_size().unwrap());
+        assert_eq!(encoded.document(), &document);
+    }
 }
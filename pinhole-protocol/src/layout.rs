@@ -1,9 +1,29 @@
 use serde::{Deserialize, Serialize};
 
-#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
 pub struct Layout {
     pub horizontal: Sizing,
     pub vertical: Sizing,
+
+    /// Space between the container's edges and its children
+    #[serde(default)]
+    pub padding: Padding,
+
+    /// Space kept clear around the outside of the container, in addition to
+    /// its parent's `gap`
+    #[serde(default)]
+    pub margin: Padding,
+
+    /// Space between adjacent children, in pixels. It isn't added before
+    /// the first child or after the last, where `padding` applies instead,
+    /// and nested containers have their own.
+    #[serde(default)]
+    pub gap: f32,
+
+    /// Lets the user scroll through children that don't fit the container's
+    /// height, instead of them overflowing it
+    #[serde(default)]
+    pub scrollable: bool,
 }
 
 impl Layout {
@@ -18,6 +38,25 @@ impl Layout {
         Layout { vertical, ..*self }
     }
 
+    pub const fn padding(&self, padding: Padding) -> Layout {
+        Layout { padding, ..*self }
+    }
+
+    pub const fn margin(&self, margin: Padding) -> Layout {
+        Layout { margin, ..*self }
+    }
+
+    pub const fn gap(&self, gap: f32) -> Layout {
+        Layout { gap, ..*self }
+    }
+
+    pub const fn scrollable(&self, scrollable: bool) -> Layout {
+        Layout {
+            scrollable,
+            ..*self
+        }
+    }
+
     pub const fn centred(&self) -> Layout {
         Layout {
             horizontal: Sizing {
@@ -33,7 +72,7 @@ impl Layout {
     }
 }
 
-#[derive(Copy, Clone, Debug, Default, Serialize, Deserialize)]
+#[derive(Copy, Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
 pub struct Sizing {
     pub position: Position,
     pub size: Size,
@@ -52,28 +91,53 @@ impl Sizing {
     }
 }
 
-#[derive(Copy, Clone, Debug, Serialize, Deserialize)]
+/// Space around content, in logical pixels.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct Padding {
+    pub top: f32,
+    pub right: f32,
+    pub bottom: f32,
+    pub left: f32,
+}
+
+impl Padding {
+    pub const fn all(padding: f32) -> Self {
+        Padding::symmetric(padding, padding)
+    }
+
+    pub const fn symmetric(horizontal: f32, vertical: f32) -> Self {
+        Padding {
+            top: vertical,
+            right: horizontal,
+            bottom: vertical,
+            left: horizontal,
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
 pub enum Position {
+    #[default]
     Start,
     End,
     Centre,
 }
 
-impl Default for Position {
-    fn default() -> Self {
-        Position::Start
-    }
-}
-
-#[derive(Copy, Clone, Debug, Serialize, Deserialize)]
+#[derive(Copy, Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
 pub enum Size {
     Auto,
     Fixed(f32),
+    #[default]
     Fill,
+
+    /// A percentage, from 0 to 100, of the space the parent would give a
+    /// `Fill` child
+    Percent(f32),
 }
 
-impl Default for Size {
-    fn default() -> Self {
-        Size::Fill
-    }
+/// Size of the client's window, in logical pixels.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Viewport {
+    pub width: u32,
+    pub height: u32,
 }
@@ -0,0 +1,289 @@
+//! Lets enums decode variants added by newer peers instead of failing.
+//!
+//! An enum opts in by deriving with `#[serde(remote = "Self")]`, which makes
+//! the derived code an inherent `deserialize`, and implementing the trait by
+//! passing a `Lenient` to it:
+//!
+//! ```ignore
+//! impl<'de> Deserialize<'de> for Node {
+//!     fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+//!         Node::deserialize(Lenient::new(deserializer, "Empty"))
+//!     }
+//! }
+//! ```
+//!
+//! A variant whose tag the enum doesn't know has its content skipped and is
+//! decoded as the unit variant named by `fallback` instead.
+
+use std::fmt;
+
+use serde::{
+    de::{
+        self, DeserializeSeed, EnumAccess, IgnoredAny, IntoDeserializer, MapAccess,
+        VariantAccess, Visitor,
+    },
+    forward_to_deserialize_any, Deserializer,
+};
+
+pub struct Lenient<D> {
+    inner: D,
+    fallback: &'static str,
+}
+
+impl<D> Lenient<D> {
+    pub fn new(inner: D, fallback: &'static str) -> Self {
+        Lenient { inner, fallback }
+    }
+}
+
+impl<'de, D: Deserializer<'de>> Deserializer<'de> for Lenient<D> {
+    type Error = D::Error;
+
+    fn deserialize_enum<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        variants: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, Self::Error> {
+        self.inner.deserialize_any(EnumVisitor {
+            variants,
+            fallback: self.fallback,
+            visitor,
+        })
+    }
+
+    fn deserialize_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
+        self.inner.deserialize_any(visitor)
+    }
+
+    forward_to_deserialize_any! {
+        bool i8 i16 i32 i64 i128 u8 u16 u32 u64 u128 f32 f64 char str string
+        bytes byte_buf option unit unit_struct newtype_struct seq tuple
+        tuple_struct map struct identifier ignored_any
+    }
+}
+
+/// Enums arrive as a bare tag for unit variants, or a one-entry map from the
+/// tag to the content for the rest.
+struct EnumVisitor<V> {
+    variants: &'static [&'static str],
+    fallback: &'static str,
+    visitor: V,
+}
+
+impl<'de, V: Visitor<'de>> Visitor<'de> for EnumVisitor<V> {
+    type Value = V::Value;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        self.visitor.expecting(formatter)
+    }
+
+    fn visit_str<E: de::Error>(self, tag: &str) -> Result<V::Value, E> {
+        let tag = if self.variants.contains(&tag) {
+            tag
+        } else {
+            self.fallback
+        };
+
+        self.visitor.visit_enum(tag.into_deserializer())
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<V::Value, A::Error> {
+        let tag: String = map
+            .next_key()?
+            .ok_or_else(|| de::Error::invalid_length(0, &self))?;
+
+        if self.variants.contains(&tag.as_str()) {
+            self.visitor.visit_enum(MapEnum { tag, map })
+        } else {
+            map.next_value::<IgnoredAny>()?;
+            self.visitor.visit_enum(self.fallback.into_deserializer())
+        }
+    }
+}
+
+/// A variant whose tag has already been read from the map.
+struct MapEnum<A> {
+    tag: String,
+    map: A,
+}
+
+impl<'de, A: MapAccess<'de>> EnumAccess<'de> for MapEnum<A> {
+    type Error = A::Error;
+    type Variant = MapVariant<A>;
+
+    fn variant_seed<S: DeserializeSeed<'de>>(
+        self,
+        seed: S,
+    ) -> Result<(S::Value, Self::Variant), Self::Error> {
+        let variant = seed.deserialize(self.tag.into_deserializer())?;
+        Ok((variant, MapVariant(self.map)))
+    }
+}
+
+struct MapVariant<A>(A);
+
+impl<'de, A: MapAccess<'de>> VariantAccess<'de> for MapVariant<A> {
+    type Error = A::Error;
+
+    fn unit_variant(mut self) -> Result<(), Self::Error> {
+        self.0.next_value()
+    }
+
+    fn newtype_variant_seed<S: DeserializeSeed<'de>>(
+        mut self,
+        seed: S,
+    ) -> Result<S::Value, Self::Error> {
+        self.0.next_value_seed(seed)
+    }
+
+    fn tuple_variant<V: Visitor<'de>>(
+        mut self,
+        len: usize,
+        visitor: V,
+    ) -> Result<V::Value, Self::Error> {
+        self.0.next_value_seed(TupleSeed { len, visitor })
+    }
+
+    fn struct_variant<V: Visitor<'de>>(
+        mut self,
+        fields: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, Self::Error> {
+        self.0.next_value_seed(StructSeed { fields, visitor })
+    }
+}
+
+struct TupleSeed<V> {
+    len: usize,
+    visitor: V,
+}
+
+impl<'de, V: Visitor<'de>> DeserializeSeed<'de> for TupleSeed<V> {
+    type Value = V::Value;
+
+    fn deserialize<D: Deserializer<'de>>(self, deserializer: D) -> Result<V::Value, D::Error> {
+        deserializer.deserialize_tuple(self.len, self.visitor)
+    }
+}
+
+struct StructSeed<V> {
+    fields: &'static [&'static str],
+    visitor: V,
+}
+
+impl<'de, V: Visitor<'de>> DeserializeSeed<'de> for StructSeed<V> {
+    type Value = V::Value;
+
+    fn deserialize<D: Deserializer<'de>>(self, deserializer: D) -> Result<V::Value, D::Error> {
+        deserializer.deserialize_struct("", self.fields, self.visitor)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde::Serialize;
+
+    use crate::{
+        layout::Layout,
+        messages::{ClientToServerMessage, ErrorCode, ServerToClientMessage},
+        node::{Node, TextProps},
+    };
+
+    /// The same shapes as `Node`, plus variants only a newer peer knows
+    #[derive(Serialize)]
+    enum NewerNode {
+        Container {
+            layout: Layout,
+            children: Vec<NewerNode>,
+        },
+        Text(TextProps),
+        Hologram {
+            depth: u32,
+        },
+        Blink,
+    }
+
+    #[derive(Serialize)]
+    enum NewerMessage {
+        Teleport { to: String },
+        Pong,
+    }
+
+    fn text(text: &str) -> TextProps {
+        match Node::text(text) {
+            Node::Text(props) => props,
+            _ => unreachable!(),
+        }
+    }
+
+    fn round_trip<T: serde::de::DeserializeOwned>(value: &impl Serialize) -> (T, T) {
+        let cbor = serde_cbor::from_slice(&serde_cbor::to_vec(value).unwrap()).unwrap();
+        let json = serde_json::from_slice(&serde_json::to_vec(value).unwrap()).unwrap();
+        (cbor, json)
+    }
+
+    #[test]
+    fn unknown_nodes_decode_as_empty() {
+        for newer in [NewerNode::Hologram { depth: 3 }, NewerNode::Blink] {
+            let (cbor, json): (Node, Node) = round_trip(&newer);
+            assert_eq!(cbor, Node::Empty);
+            assert_eq!(json, Node::Empty);
+        }
+    }
+
+    #[test]
+    fn unknown_nodes_keep_their_siblings() {
+        let newer = NewerNode::Container {
+            layout: Layout::default(),
+            children: vec![
+                NewerNode::Text(text("before")),
+                NewerNode::Hologram { depth: 3 },
+                NewerNode::Text(text("after")),
+            ],
+        };
+
+        let expected = Node::Container {
+            layout: Layout::default(),
+            children: vec![
+                Node::text("before").boxed(),
+                Node::Empty.boxed(),
+                Node::text("after").boxed(),
+            ],
+        };
+
+        let (cbor, json): (Node, Node) = round_trip(&newer);
+        assert_eq!(cbor, expected);
+        assert_eq!(json, expected);
+    }
+
+    #[test]
+    fn unknown_messages_decode_as_unknown() {
+        let newer = NewerMessage::Teleport {
+            to: "/elsewhere".to_string(),
+        };
+
+        let (cbor, json): (ServerToClientMessage, ServerToClientMessage) = round_trip(&newer);
+        assert!(matches!(cbor, ServerToClientMessage::Unknown));
+        assert!(matches!(json, ServerToClientMessage::Unknown));
+
+        let (cbor, json): (ClientToServerMessage, ClientToServerMessage) = round_trip(&newer);
+        assert!(matches!(cbor, ClientToServerMessage::Unknown));
+        assert!(matches!(json, ClientToServerMessage::Unknown));
+    }
+
+    #[test]
+    fn known_variants_still_decode() {
+        let (cbor, json): (ServerToClientMessage, ServerToClientMessage) =
+            round_trip(&NewerMessage::Pong);
+        assert!(matches!(cbor, ServerToClientMessage::Pong));
+        assert!(matches!(json, ServerToClientMessage::Pong));
+    }
+
+    #[test]
+    fn unknown_error_codes_decode_as_unknown() {
+        let (cbor, json): (ErrorCode, ErrorCode) = round_trip(&"PaymentRequired");
+        assert_eq!(cbor, ErrorCode::Unknown);
+        assert_eq!(json, ErrorCode::Unknown);
+    }
+}
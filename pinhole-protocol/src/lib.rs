@@ -1,6 +1,8 @@
 pub mod action;
+mod bytes;
 pub mod document;
 pub mod layout;
+mod lenient;
 pub mod messages;
 pub mod network;
 pub mod node;
@@ -1,43 +1,406 @@
 use std::collections::HashMap;
 
-use serde::{Deserialize, Serialize};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
 
 use crate::{
     action::Action,
     document::Document,
-    storage::{StateMap, StorageScope},
+    layout::Layout,
+    lenient::Lenient,
+    node::{Node, ToastSeverity},
+    storage::{StateMap, StateValue, StorageScope},
 };
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(remote = "Self")]
 pub enum ClientToServerMessage {
     Load {
         path: String,
         storage: HashMap<String, String>,
+        /// The `RenderMeta::version` of the document the client is showing
+        /// for this path, if any. The server answers with `NotModified`
+        /// instead of sending the same document again.
+        #[serde(default)]
+        version: Option<u64>,
+        /// Chosen by the client to match the answer to this request, which
+        /// carries the same id
+        #[serde(default)]
+        request_id: Option<u64>,
     },
     Action {
         path: String,
         action: Action,
         state_map: StateMap,
+        /// The client's storage, for rendering the page again if the action
+        /// asks to
+        #[serde(default)]
+        storage: HashMap<String, String>,
+        /// Chosen by the client to match the answer to this request, which
+        /// carries the same id
+        #[serde(default)]
+        request_id: Option<u64>,
+    },
+    Viewport {
+        width: u32,
+        height: u32,
+    },
+    StoreAck {
+        id: u64,
     },
+    Locale {
+        locale: String,
+    },
+    /// Lists the optional protocol features the client supports, such as
+    /// `network::DEFLATE`. Sent first on each connection; the server answers
+    /// with the ones both sides support.
+    Capabilities {
+        capabilities: Vec<String>,
+    },
+    /// Checks the connection is still alive. The server answers with `Pong`
+    /// if it advertised `network::PING`.
+    Ping,
+    /// Like `Load`, but for a path the user may visit next. The server
+    /// answers with `Prefetched` instead of `Render`, and the client's current
+    /// path is unaffected.
+    Prefetch {
+        path: String,
+        storage: HashMap<String, String>,
+        /// Chosen by the client to match the answer to this request, which
+        /// carries the same id
+        #[serde(default)]
+        request_id: Option<u64>,
+    },
+    /// A message this version doesn't know, e.g. from a newer client. The
+    /// server ignores it.
+    #[serde(skip_serializing)]
+    Unknown,
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(remote = "Self")]
 pub enum ServerToClientMessage {
     Render {
         document: Document,
+        #[serde(default)]
+        meta: Option<RenderMeta>,
+        /// The `request_id` of the request this answers, if it had one
+        #[serde(default)]
+        request_id: Option<u64>,
     },
     RedirectTo {
         path: String,
+        /// The `request_id` of the request this answers, if it had one
+        #[serde(default)]
+        request_id: Option<u64>,
     },
+    /// The answer to a `Load` whose document is the version the client
+    /// already has, which it keeps showing.
+    NotModified,
     Store {
         scope: StorageScope,
         key: String,
         value: String,
+
+        /// When set, the client replies with a `StoreAck` carrying this id
+        /// once the value has been stored. Only sent to clients that
+        /// advertise `network::STORE_ACK`.
+        #[serde(default)]
+        ack: Option<u64>,
+    },
+    /// Several values for the client to store at once, so it never has
+    /// some of them without the rest. Storage holds text, so values other
+    /// than strings are stored as JSON.
+    StoreBatch {
+        items: Vec<(StorageScope, String, StateValue)>,
+    },
+    /// Removes everything the client has stored in `scope`, e.g. when the
+    /// user signs out.
+    ClearStorage {
+        scope: StorageScope,
+    },
+    Error {
+        code: ErrorCode,
+        message: String,
+        /// The `request_id` of the request this answers, if it had one
+        #[serde(default)]
+        request_id: Option<u64>,
+    },
+    /// Ask the client to load the current path again after a delay. A later
+    /// `ScheduleReload` replaces any pending one.
+    ScheduleReload {
+        after_ms: u64,
+    },
+    /// A file for the client to save to the user's downloads folder.
+    Download {
+        filename: String,
+        mime: String,
+        #[serde(with = "crate::bytes")]
+        bytes: Vec<u8>,
+    },
+    /// Starts a file sent in parts, for files too big for one message. Each
+    /// `DownloadChunk` adds the next part, and `DownloadEnd` saves the file
+    /// as if it had arrived in one `Download`. Only sent to clients that
+    /// advertise `network::CHUNKED_DOWNLOADS`.
+    DownloadBegin {
+        filename: String,
+        mime: String,
+    },
+    DownloadChunk {
+        #[serde(with = "crate::bytes")]
+        bytes: Vec<u8>,
+    },
+    DownloadEnd,
+    /// The client's `Capabilities` that the server supports too, which either
+    /// side may now use.
+    Capabilities {
+        capabilities: Vec<String>,
+    },
+    /// The answer to a `Ping`.
+    Pong,
+    /// Problems with the values of form fields, keyed by field id, e.g.
+    /// after an action rejects its input. Replaces any sent before, so an
+    /// empty map clears them.
+    ValidationErrors {
+        errors: HashMap<String, String>,
+    },
+    /// A brief message for the client to show above the document, such as
+    /// "Saved" after an action, dismissed on its own after a few seconds.
+    Notify {
+        severity: ToastSeverity,
+        text: String,
+    },
+    /// Values an action answered with. The fields of an object are merged
+    /// into the client's state map.
+    ActionData {
+        value: StateValue,
+    },
+    /// Suggests paths the user is likely to visit next, for the client to
+    /// fetch in the background with `Prefetch`.
+    Prefetch {
+        paths: Vec<String>,
     },
+    /// The document for a path the client asked to `Prefetch`, to show if
+    /// the user navigates there.
+    Prefetched {
+        path: String,
+        document: Document,
+        #[serde(default)]
+        meta: Option<RenderMeta>,
+    },
+    /// Replaces the content of the slot with this id in the client's current
+    /// document, so a page can be filled in as its parts become ready.
+    Patch {
+        id: String,
+        node: Node,
+    },
+    /// Starts a document sent in parts, whose root is a container with this
+    /// layout. Each `RenderAppend` adds a child to it, and `RenderEnd` shows
+    /// the result as if it had arrived in one `Render`. Only sent to clients
+    /// that advertise `STREAMING`.
+    RenderBegin {
+        layout: Layout,
+        #[serde(default)]
+        meta: Option<RenderMeta>,
+    },
+    RenderAppend {
+        node: Node,
+    },
+    RenderEnd,
+    /// An effect for the client to carry out on its own, without a render.
+    ClientDirective {
+        kind: Directive,
+    },
+    /// A message this version doesn't know, e.g. from a newer server. The
+    /// client ignores it.
+    #[serde(skip_serializing)]
+    Unknown,
+}
+
+impl ClientToServerMessage {
+    /// The id the client gave this request, for the server to echo in its
+    /// answer.
+    pub fn request_id(&self) -> Option<u64> {
+        match self {
+            ClientToServerMessage::Load { request_id, .. }
+            | ClientToServerMessage::Action { request_id, .. }
+            | ClientToServerMessage::Prefetch { request_id, .. } => *request_id,
+            _ => None,
+        }
+    }
+}
+
+impl ServerToClientMessage {
+    /// Marks this as the answer to the request with `id`, if it's a kind of
+    /// message that carries one.
+    pub fn set_request_id(&mut self, id: Option<u64>) {
+        match self {
+            ServerToClientMessage::Render { request_id, .. }
+            | ServerToClientMessage::RedirectTo { request_id, .. }
+            | ServerToClientMessage::Error { request_id, .. } => *request_id = id,
+            _ => {}
+        }
+    }
+}
+
+impl Serialize for ClientToServerMessage {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        ClientToServerMessage::serialize(self, serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for ClientToServerMessage {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        ClientToServerMessage::deserialize(Lenient::new(deserializer, "Unknown"))
+    }
+}
+
+impl Serialize for ServerToClientMessage {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        ServerToClientMessage::serialize(self, serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for ServerToClientMessage {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        ServerToClientMessage::deserialize(Lenient::new(deserializer, "Unknown"))
+    }
+}
+
+/// Client-side effects the server can ask for, e.g. in response to an
+/// action, that don't need a new document.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(remote = "Self")]
+pub enum Directive {
+    /// Put this text on the system clipboard
+    CopyToClipboard(String),
+
+    /// Scroll the form field or slot with this id into view, within any
+    /// scrollable containers around it
+    ScrollTo(String),
+
+    /// Move keyboard focus to the input with this id
+    FocusInput(String),
+
+    /// A directive this version doesn't know. The client ignores it.
+    #[serde(skip_serializing)]
+    Unknown,
+}
+
+impl Serialize for Directive {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        Directive::serialize(self, serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for Directive {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        Directive::deserialize(Lenient::new(deserializer, "Unknown"))
+    }
+}
+
+/// Hints about a rendered document, sent alongside it.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RenderMeta {
+    /// How long the client may reuse this document when navigating back to
+    /// its path, without asking the server again.
+    #[serde(default)]
+    pub max_age_ms: Option<u64>,
+
+    /// The path the document should be known by, when it was reached through
+    /// another, e.g. an alias or a redirect.
+    #[serde(default)]
+    pub canonical_path: Option<String>,
+
+    /// Identifies the version of the content the document was rendered from.
+    #[serde(default)]
+    pub revision: Option<String>,
+
+    /// Identifies the document itself, for the client to send back when it
+    /// loads the same path again. Filled in by the server.
+    #[serde(default)]
+    pub version: Option<u64>,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(remote = "Self")]
+pub enum ErrorCode {
+    /// The route failed while handling the request, e.g. it panicked
+    InternalServerError,
+
+    /// The rendered document exceeded the server's size limit
+    DocumentTooLarge,
+
+    /// The request was malformed, e.g. its path was too long
+    BadRequest,
+
+    /// The client is sending requests faster than the server allows
+    TooManyRequests,
+
+    /// The page needs a signed-in user, and there isn't one
+    Unauthorized,
+
+    /// The signed-in user isn't allowed to see the page or do the action
+    Forbidden,
+
+    /// The client sent nothing for too long, and the server is closing the
+    /// connection
+    SessionTimeout,
+
+    /// An error this version doesn't know
+    #[serde(skip_serializing)]
+    Unknown,
+}
+
+impl Serialize for ErrorCode {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        ErrorCode::serialize(self, serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for ErrorCode {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        ErrorCode::deserialize(Lenient::new(deserializer, "Unknown"))
+    }
 }
 
 impl log::kv::ToValue for ServerToClientMessage {
-    fn to_value(&self) -> log::kv::Value {
+    fn to_value(&self) -> log::kv::Value<'_> {
         log::kv::Value::from_debug(self)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn batch() -> ServerToClientMessage {
+        ServerToClientMessage::StoreBatch {
+            items: vec![
+                (
+                    StorageScope::Session,
+                    "name".to_string(),
+                    StateValue::String("Ada".to_string()),
+                ),
+                (
+                    StorageScope::Persistent,
+                    "visits".to_string(),
+                    StateValue::Number(3.),
+                ),
+            ],
+        }
+    }
+
+    #[test]
+    fn store_batch_round_trips_through_cbor() {
+        let bytes = serde_cbor::to_vec(&batch()).unwrap();
+        let decoded: ServerToClientMessage = serde_cbor::from_slice(&bytes).unwrap();
+        assert_eq!(format!("{:?}", decoded), format!("{:?}", batch()));
+    }
+
+    #[test]
+    fn store_batch_round_trips_through_json() {
+        let json = serde_json::to_string(&batch()).unwrap();
+        let decoded: ServerToClientMessage = serde_json::from_str(&json).unwrap();
+        assert_eq!(format!("{:?}", decoded), format!("{:?}", batch()));
+    }
+}
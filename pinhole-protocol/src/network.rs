@@ -1,77 +1,1428 @@
-use async_std::{net::TcpStream, prelude::*};
+use async_std::{future, net::TcpStream, prelude::*};
+use flate2::{read::DeflateDecoder, write::DeflateEncoder};
+use serde::{de::DeserializeOwned, Serialize};
+use std::{
+    io::{Read as _, Write as _},
+    time::Duration,
+};
 
-use crate::messages::{ClientToServerMessage, ServerToClientMessage};
+use crate::{
+    document::{Document, EncodedDocument},
+    messages::{ClientToServerMessage, RenderMeta, ServerToClientMessage},
+};
 
 use kv_log_macro as log;
 
 type Result<T> = std::result::Result<T, Box<dyn std::error::Error + Send + Sync>>;
 
-pub async fn send_request(stream: &mut TcpStream, request: ClientToServerMessage) -> Result<()> {
+/// Bounds on the messages a peer may send, so a hostile or broken one can't
+/// exhaust the receiver's memory or tie up a connection.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct NetworkLimits {
+    /// Largest a message may be, in bytes, after any decompression
+    pub max_message_size: usize,
+
+    /// How deeply arrays, maps and tags may nest in a message
+    pub max_depth: usize,
+
+    /// How long a peer may take to send the rest of a message once its
+    /// first byte has arrived. Waiting for a message to start isn't limited,
+    /// since connections are often idle.
+    pub read_timeout: Duration,
+}
+
+impl Default for NetworkLimits {
+    fn default() -> Self {
+        NetworkLimits {
+            max_message_size: DEFAULT_MAX_MESSAGE_SIZE,
+            max_depth: DEFAULT_MAX_DEPTH,
+            read_timeout: DEFAULT_READ_TIMEOUT,
+        }
+    }
+}
+
+pub const DEFAULT_MAX_MESSAGE_SIZE: usize = 10 * 1024 * 1024;
+
+pub const DEFAULT_MAX_DEPTH: usize = 64;
+
+/// The depth limit for documents from the server. Each nested container takes
+/// three levels, so this allows about 40 of them, which is also as deep as the
+/// CBOR and JSON decoders go whatever the limit.
+pub const DEFAULT_MAX_DOCUMENT_DEPTH: usize = 128;
+
+/// A peer announced or sent a message larger than the receiver allows, or
+/// a message was too large for its length prefix to announce.
+#[derive(Debug)]
+pub struct MessageTooLarge {
+    pub limit: usize,
+}
+
+impl std::fmt::Display for MessageTooLarge {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Message is larger than {} bytes", self.limit)
+    }
+}
+
+impl std::error::Error for MessageTooLarge {}
+
+/// A received message nested more deeply than the receiver allows. It's
+/// refused before decoding so a hostile peer can't exhaust the stack.
+#[derive(Debug)]
+pub struct NestingTooDeep {
+    pub limit: usize,
+}
+
+impl std::fmt::Display for NestingTooDeep {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Message is nested more than {} levels deep", self.limit)
+    }
+}
+
+impl std::error::Error for NestingTooDeep {}
+
+pub const DEFAULT_READ_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// A peer started sending a message but didn't finish within the
+/// receiver's limit, e.g. a client holding a connection open by stalling.
+#[derive(Debug)]
+pub struct ReadTimeout {
+    pub limit: Duration,
+}
+
+impl std::fmt::Display for ReadTimeout {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Message wasn't received within {:?}", self.limit)
+    }
+}
+
+impl std::error::Error for ReadTimeout {}
+
+/// Advertised by peers that can send and receive deflate-compressed messages.
+pub const DEFLATE: &str = "pinhole:compression:deflate";
+
+/// Advertised by servers that answer `Ping` with `Pong`.
+pub const PING: &str = "pinhole:ping";
+
+/// Advertised by clients that can assemble a document sent in parts with
+/// `RenderBegin`, `RenderAppend` and `RenderEnd`.
+pub const STREAMING: &str = "pinhole:render:streaming";
+
+/// Advertised by peers that can decode `StateValue::Bytes`: servers in the
+/// state maps clients send, and clients in the values servers send back.
+pub const BYTES: &str = "pinhole:storage:bytes";
+
+/// Advertised by clients that answer a `Store` carrying an `ack` id with
+/// `StoreAck`. Older clients ignore the id.
+pub const STORE_ACK: &str = "pinhole:storage:ack";
+
+/// Advertised by clients that can assemble a file sent in parts with
+/// `DownloadBegin`, `DownloadChunk` and `DownloadEnd`.
+pub const CHUNKED_DOWNLOADS: &str = "pinhole:download:chunked";
+
+/// Advertised by peers that can send messages encoded as JSON. Clients
+/// only offer it when asked to, since CBOR is more compact.
+pub const JSON: &str = "pinhole:wire:json";
+
+/// The capabilities this side of the protocol supports, to advertise in a
+/// `Capabilities` message.
+pub fn supported_capabilities() -> Vec<String> {
+    vec![
+        DEFLATE.to_string(),
+        PING.to_string(),
+        STREAMING.to_string(),
+        BYTES.to_string(),
+        JSON.to_string(),
+        STORE_ACK.to_string(),
+        CHUNKED_DOWNLOADS.to_string(),
+    ]
+}
+
+/// How outgoing messages are encoded. Only use `Deflate` once the peer has
+/// advertised `DEFLATE`; compressed messages are always accepted.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum Compression {
+    #[default]
+    None,
+    Deflate,
+}
+
+/// How outgoing messages are serialized. Only use `Json` once the peer has
+/// advertised `JSON`; messages in either format are always accepted.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum WireFormat {
+    #[default]
+    Cbor,
+    Json,
+}
+
+impl WireFormat {
+    fn encode(self, message: &impl Serialize) -> Result<Vec<u8>> {
+        Ok(match self {
+            WireFormat::Cbor => serde_cbor::to_vec(message)?,
+            WireFormat::Json => serde_json::to_vec(message)?,
+        })
+    }
+}
+
+/// Set in the length prefix when the message that follows is compressed.
+const COMPRESSED_FLAG: u32 = 1 << 31;
+
+/// Set in the length prefix when the message that follows is JSON rather
+/// than CBOR.
+const JSON_FLAG: u32 = 1 << 30;
+
+/// The largest body a length prefix can announce, as the bits above it are
+/// flags.
+const MAX_FRAME_SIZE: usize = (JSON_FLAG - 1) as usize;
+
+/// Messages smaller than this aren't worth compressing.
+const COMPRESSION_THRESHOLD: usize = 1024;
+
+/// The length prefix and body to send for a message.
+fn frame(
+    message: &impl Serialize,
+    compression: Compression,
+    format: WireFormat,
+) -> Result<(u32, Vec<u8>)> {
+    frame_encoded(format.encode(message)?, compression, format)
+}
+
+/// The length prefix and body to send for a message already encoded in
+/// `format`.
+fn frame_encoded(
+    bytes: Vec<u8>,
+    compression: Compression,
+    format: WireFormat,
+) -> Result<(u32, Vec<u8>)> {
+    let format_flag = match format {
+        WireFormat::Cbor => 0,
+        WireFormat::Json => JSON_FLAG,
+    };
+
+    if compression == Compression::Deflate && bytes.len() >= COMPRESSION_THRESHOLD {
+        let mut encoder = DeflateEncoder::new(Vec::new(), flate2::Compression::default());
+        encoder.write_all(&bytes)?;
+        let compressed = encoder.finish()?;
+
+        if compressed.len() < bytes.len() {
+            let length = frame_size(&compressed)? | COMPRESSED_FLAG | format_flag;
+            return Ok((length, compressed));
+        }
+    }
+
+    Ok((frame_size(&bytes)? | format_flag, bytes))
+}
+
+/// The length to announce for a body, which must leave the flag bits clear.
+fn frame_size(bytes: &[u8]) -> Result<u32> {
+    if bytes.len() > MAX_FRAME_SIZE {
+        return Err(MessageTooLarge {
+            limit: MAX_FRAME_SIZE,
+        }
+        .into());
+    }
+
+    Ok(bytes.len() as u32)
+}
+
+/// Reads a message's length prefix, or None if the peer closed the
+/// connection between messages. Once the first byte has arrived the rest
+/// must follow within `timeout`.
+async fn read_length(stream: &mut TcpStream, timeout: Duration) -> Result<Option<u32>> {
+    let mut bytes = [0u8; 4];
+    if stream.read(&mut bytes[..1]).await? == 0 {
+        return Ok(None);
+    }
+
+    future::timeout(timeout, stream.read_exact(&mut bytes[1..]))
+        .await
+        .map_err(|_| ReadTimeout { limit: timeout })??;
+
+    Ok(Some(u32::from_le_bytes(bytes)))
+}
+
+/// Length of the body a prefix announces, as sent.
+fn body_size(length: u32) -> usize {
+    (length & !(COMPRESSED_FLAG | JSON_FLAG)) as usize
+}
+
+/// Decodes a message body in the format its length prefix announces,
+/// refusing ones nested more deeply than `max_depth`.
+fn decode<T: DeserializeOwned>(bytes: &[u8], length: u32, max_depth: usize) -> Result<T> {
+    if length & JSON_FLAG == 0 {
+        check_depth(bytes, max_depth)?;
+        Ok(serde_cbor::from_slice(bytes)?)
+    } else {
+        check_json_depth(bytes, max_depth)?;
+        Ok(serde_json::from_slice(bytes)?)
+    }
+}
+
+/// Reads the body announced by a length prefix, decompressing it if needed.
+/// Oversized bodies are refused before anything is allocated for them, and
+/// compressed ones are cut off once they decompress past the limit.
+async fn read_body(stream: &mut TcpStream, length: u32, limits: &NetworkLimits) -> Result<Vec<u8>> {
+    let size = body_size(length);
+    if size > limits.max_message_size {
+        return Err(MessageTooLarge {
+            limit: limits.max_message_size,
+        }
+        .into());
+    }
+
+    let mut bytes = vec![0u8; size];
+    future::timeout(limits.read_timeout, stream.read_exact(&mut bytes))
+        .await
+        .map_err(|_| ReadTimeout {
+            limit: limits.read_timeout,
+        })??;
+
+    if length & COMPRESSED_FLAG == 0 {
+        return Ok(bytes);
+    }
+
+    let mut decompressed = Vec::new();
+    DeflateDecoder::new(bytes.as_slice())
+        .take(limits.max_message_size as u64 + 1)
+        .read_to_end(&mut decompressed)?;
+
+    if decompressed.len() > limits.max_message_size {
+        return Err(MessageTooLarge {
+            limit: limits.max_message_size,
+        }
+        .into());
+    }
+
+    Ok(decompressed)
+}
+
+/// Sends a request, returning its size on the wire in bytes.
+pub async fn send_request(
+    stream: &mut TcpStream,
+    request: ClientToServerMessage,
+    compression: Compression,
+    format: WireFormat,
+) -> Result<usize> {
     log::debug!("Sending request: {:?}", request);
-    let bytes = serde_cbor::to_vec(&request)?;
+    let (request_length, bytes) = frame(&request, compression, format)?;
 
-    let request_length: u32 = bytes.len() as u32;
-    stream.write(&request_length.to_le_bytes()).await?;
-    stream.write(&bytes).await?;
+    stream.write_all(&request_length.to_le_bytes()).await?;
+    stream.write_all(&bytes).await?;
 
-    Ok(())
+    Ok(4 + bytes.len())
 }
 
-pub async fn send_response(stream: &mut TcpStream, response: ServerToClientMessage) -> Result<()> {
+/// Sends a response, returning its size on the wire in bytes.
+pub async fn send_response(
+    stream: &mut TcpStream,
+    response: ServerToClientMessage,
+    compression: Compression,
+    format: WireFormat,
+) -> Result<usize> {
     log::debug!("Sending response: {:?}", response);
 
-    let bytes = serde_cbor::to_vec(&response)?;
+    let (response_length, bytes) = frame(&response, compression, format)?;
 
-    let response_length: u32 = bytes.len() as u32;
-    stream.write(&response_length.to_le_bytes()).await?;
-    stream.write(&bytes).await?;
+    stream.write_all(&response_length.to_le_bytes()).await?;
+    stream.write_all(&bytes).await?;
 
-    Ok(())
+    Ok(4 + bytes.len())
+}
+
+/// Sends `ServerToClientMessage::Render` for a document that's already been
+/// encoded, returning its size on the wire in bytes. Over CBOR the document's
+/// bytes are sent as they are instead of being encoded again.
+pub async fn send_encoded_document(
+    stream: &mut TcpStream,
+    document: &EncodedDocument,
+    meta: Option<RenderMeta>,
+    request_id: Option<u64>,
+    compression: Compression,
+    format: WireFormat,
+) -> Result<usize> {
+    log::debug!("Sending render", { size: document.size() });
+
+    let bytes = match format {
+        WireFormat::Cbor => render_cbor(document, &meta, request_id)?,
+        WireFormat::Json => format.encode(&DocumentRef::Render {
+            document: document.document(),
+            meta: &meta,
+            request_id,
+        })?,
+    };
+
+    send_encoded(stream, bytes, compression, format).await
+}
+
+/// Sends `ServerToClientMessage::Prefetched` for a document that's already
+/// been encoded, as `send_encoded_document` does for `Render`.
+pub async fn send_encoded_prefetched(
+    stream: &mut TcpStream,
+    path: &str,
+    document: &EncodedDocument,
+    meta: Option<RenderMeta>,
+    compression: Compression,
+    format: WireFormat,
+) -> Result<usize> {
+    log::debug!("Sending prefetched document", { path: path, size: document.size() });
+
+    let bytes = match format {
+        WireFormat::Cbor => prefetched_cbor(path, document, &meta)?,
+        WireFormat::Json => format.encode(&DocumentRef::Prefetched {
+            path,
+            document: document.document(),
+            meta: &meta,
+        })?,
+    };
+
+    send_encoded(stream, bytes, compression, format).await
 }
 
-pub async fn receive_response(stream: &mut TcpStream) -> Result<Option<ServerToClientMessage>> {
+/// Frames and sends a message already encoded in `format`, returning its
+/// size on the wire in bytes.
+async fn send_encoded(
+    stream: &mut TcpStream,
+    bytes: Vec<u8>,
+    compression: Compression,
+    format: WireFormat,
+) -> Result<usize> {
+    let (length, bytes) = frame_encoded(bytes, compression, format)?;
+
+    stream.write_all(&length.to_le_bytes()).await?;
+    stream.write_all(&bytes).await?;
+
+    Ok(4 + bytes.len())
+}
+
+/// The messages that carry a whole document, by reference, encoded the same
+/// way as `ServerToClientMessage`.
+#[derive(Serialize)]
+enum DocumentRef<'a> {
+    Render {
+        document: &'a Document,
+        meta: &'a Option<RenderMeta>,
+        request_id: Option<u64>,
+    },
+    Prefetched {
+        path: &'a str,
+        document: &'a Document,
+        meta: &'a Option<RenderMeta>,
+    },
+}
+
+/// `ServerToClientMessage::Render` as CBOR, with the document's encoding
+/// copied in.
+fn render_cbor(
+    document: &EncodedDocument,
+    meta: &Option<RenderMeta>,
+    request_id: Option<u64>,
+) -> Result<Vec<u8>> {
+    cbor_variant(
+        "Render",
+        &[
+            ("document", document.bytes()),
+            ("meta", &serde_cbor::to_vec(meta)?),
+            ("request_id", &serde_cbor::to_vec(&request_id)?),
+        ],
+    )
+}
+
+/// `ServerToClientMessage::Prefetched` as CBOR, with the document's encoding
+/// copied in.
+fn prefetched_cbor(
+    path: &str,
+    document: &EncodedDocument,
+    meta: &Option<RenderMeta>,
+) -> Result<Vec<u8>> {
+    cbor_variant(
+        "Prefetched",
+        &[
+            ("path", &serde_cbor::to_vec(&path)?),
+            ("document", document.bytes()),
+            ("meta", &serde_cbor::to_vec(meta)?),
+        ],
+    )
+}
+
+/// An enum variant with named fields as serde_cbor encodes it, from the
+/// fields' encodings in declaration order: a map from the variant's name to
+/// a map of its fields.
+fn cbor_variant(variant: &str, fields: &[(&str, &[u8])]) -> Result<Vec<u8>> {
+    // A map header holds up to 23 entries in its first byte
+    assert!(
+        fields.len() < 24,
+        "too many fields for a one-byte map header"
+    );
+
+    let mut bytes = vec![0xa1];
+    serde_cbor::to_writer(&mut bytes, &variant)?;
+    bytes.push(0xa0 | fields.len() as u8);
+    for (name, value) in fields {
+        serde_cbor::to_writer(&mut bytes, name)?;
+        bytes.extend_from_slice(value);
+    }
+    Ok(bytes)
+}
+
+/// Waits for the next response, returning it with its size on the wire in
+/// bytes, or `None` once the server closes the connection.
+pub async fn receive_response(
+    stream: &mut TcpStream,
+    limits: &NetworkLimits,
+) -> Result<Option<(ServerToClientMessage, usize)>> {
     log::debug!("Waiting for response...");
 
-    let mut bytes = [0u8; 4];
-    stream.read(&mut bytes).await?;
-    let response_length = u32::from_le_bytes(bytes);
+    let Some(response_length) = read_length(stream, limits.read_timeout).await? else {
+        log::debug!("Connection closed");
+        return Ok(None);
+    };
 
     log::trace!("Incoming response", { length: response_length });
 
     if response_length > 0 {
-        let mut bytes = Vec::new();
-        bytes.resize(response_length as usize, 0u8);
-        stream.read(&mut bytes).await?;
+        let bytes = read_body(stream, response_length, limits).await?;
 
-        let response = serde_cbor::from_slice::<ServerToClientMessage>(&bytes)?;
+        let response: ServerToClientMessage = decode(&bytes, response_length, limits.max_depth)?;
 
         log::debug!("Received response", { response: response });
-        Ok(Some(response))
+        Ok(Some((response, 4 + body_size(response_length))))
     } else {
         log::debug!("Empty response");
         Ok(None)
     }
 }
 
-pub async fn receive_request(stream: &mut TcpStream) -> Result<Option<ClientToServerMessage>> {
+/// Waits for the next request, returning it with its size on the wire in
+/// bytes, or `None` once the client closes the connection.
+pub async fn receive_request(
+    stream: &mut TcpStream,
+    limits: &NetworkLimits,
+) -> Result<Option<(ClientToServerMessage, usize)>> {
     log::debug!("Waiting for request...");
 
-    let mut bytes = [0u8; 4];
-    stream.read(&mut bytes).await?;
-    let request_length = u32::from_le_bytes(bytes);
+    let Some(request_length) = read_length(stream, limits.read_timeout).await? else {
+        log::debug!("Connection closed");
+        return Ok(None);
+    };
 
     log::trace!("Incoming request", { length: request_length });
 
     if request_length > 0 {
-        let mut bytes = Vec::new();
-        bytes.resize(request_length as usize, 0u8);
-        stream.read(&mut bytes).await?;
+        let bytes = read_body(stream, request_length, limits).await?;
 
-        let request = serde_cbor::from_slice::<ClientToServerMessage>(&bytes)?;
-        log::debug!("Received request: {:?}", request);
-        Ok(Some(request))
+        // Not logged, since requests carry whatever the user typed; the
+        // server logs what it can with sensitive values redacted
+        let request: ClientToServerMessage = decode(&bytes, request_length, limits.max_depth)?;
+        Ok(Some((request, 4 + body_size(request_length))))
     } else {
         log::debug!("Received empty request");
         Ok(None)
     }
 }
+
+/// Walks the CBOR item headers in `bytes` without decoding anything, failing
+/// if containers open more than `max_depth` deep. Malformed input is left for
+/// the decoder to report.
+fn check_depth(bytes: &[u8], max_depth: usize) -> Result<()> {
+    // Items left in each open container, or None if it ends with a break
+    let mut open: Vec<Option<u64>> = Vec::new();
+    let mut position = 0;
+
+    while position < bytes.len() {
+        let initial = bytes[position];
+        position += 1;
+
+        let container = if initial == 0xff {
+            open.pop();
+            None
+        } else {
+            let major = initial >> 5;
+            let length = match initial & 0x1f {
+                info @ 0..=23 => Some(info as u64),
+                info @ 24..=27 => {
+                    let size = 1 << (info - 24);
+                    let Some(argument) = bytes.get(position..position + size) else {
+                        return Ok(());
+                    };
+                    position += size;
+                    Some(argument.iter().fold(0u64, |n, byte| n << 8 | *byte as u64))
+                }
+                31 => None,
+                _ => return Ok(()),
+            };
+
+            match (major, length) {
+                (2 | 3, Some(length)) => {
+                    position = position.saturating_add(length as usize);
+                    None
+                }
+                (2..=5, None) => Some(None),
+                (4, Some(length)) => Some(Some(length)),
+                (5, Some(length)) => Some(Some(length.saturating_mul(2))),
+                (6, _) => Some(Some(1)),
+                _ => None,
+            }
+        };
+
+        if let Some(remaining) = container {
+            if remaining != Some(0) {
+                open.push(remaining);
+                if open.len() > max_depth {
+                    return Err(NestingTooDeep { limit: max_depth }.into());
+                }
+                continue;
+            }
+        }
+
+        // An item just finished, which may finish the containers around it
+        loop {
+            match open.last_mut() {
+                None => return Ok(()),
+                Some(Some(remaining)) => {
+                    *remaining -= 1;
+                    if *remaining > 0 {
+                        break;
+                    }
+                    open.pop();
+                }
+                Some(None) => break,
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Like `check_depth`, for a JSON message. Brackets inside strings don't
+/// count.
+fn check_json_depth(bytes: &[u8], max_depth: usize) -> Result<()> {
+    let mut depth = 0usize;
+    let mut in_string = false;
+    let mut escaped = false;
+
+    for byte in bytes {
+        if in_string {
+            match byte {
+                _ if escaped => escaped = false,
+                b'\\' => escaped = true,
+                b'"' => in_string = false,
+                _ => {}
+            }
+            continue;
+        }
+
+        match byte {
+            b'"' => in_string = true,
+            b'[' | b'{' => {
+                depth += 1;
+                if depth > max_depth {
+                    return Err(NestingTooDeep { limit: max_depth }.into());
+                }
+            }
+            b']' | b'}' => depth = depth.saturating_sub(1),
+            _ => {}
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        action::Action,
+        document::Document,
+        layout::Layout,
+        messages::{Directive, ErrorCode},
+        node::{Node, ToastSeverity},
+        storage::{StateMap, StateValue, StorageScope},
+    };
+    use async_std::{net::TcpListener, task};
+    use std::collections::HashMap;
+
+    /// Both ends of a loopback connection
+    async fn connected() -> (TcpStream, TcpStream) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        let client = TcpStream::connect(address).await.unwrap();
+        let (server, _) = listener.accept().await.unwrap();
+        (client, server)
+    }
+
+    fn load(path: impl ToString) -> ClientToServerMessage {
+        ClientToServerMessage::Load {
+            path: path.to_string(),
+            storage: HashMap::new(),
+            version: None,
+            request_id: None,
+        }
+    }
+
+    /// A message big enough to be worth compressing
+    fn large_load() -> ClientToServerMessage {
+        load(format!("/{}", "compressible/".repeat(200)))
+    }
+
+    fn decompressed(length: u32, body: &[u8]) -> Vec<u8> {
+        assert!(length & COMPRESSED_FLAG != 0);
+        let mut bytes = Vec::new();
+        DeflateDecoder::new(body).read_to_end(&mut bytes).unwrap();
+        bytes
+    }
+
+    /// `depth` arrays, each holding the next
+    fn nested_cbor_arrays(depth: usize) -> Vec<u8> {
+        let mut bytes = vec![0x81; depth];
+        bytes.push(0x00);
+        bytes
+    }
+
+    fn nested_json_arrays(depth: usize) -> Vec<u8> {
+        format!("{}{}", "[".repeat(depth), "]".repeat(depth)).into_bytes()
+    }
+
+    fn nested_containers(depth: usize) -> ServerToClientMessage {
+        let mut node = Node::text("innermost");
+        for _ in 0..depth {
+            node = Node::Container {
+                layout: Layout::default(),
+                children: vec![node.boxed()],
+            };
+        }
+
+        ServerToClientMessage::Render {
+            document: Document(node),
+            meta: None,
+            request_id: None,
+        }
+    }
+
+    fn is_too_deep(result: Result<()>) -> bool {
+        result.is_err_and(|err| err.is::<NestingTooDeep>())
+    }
+
+    #[test]
+    fn check_depth_allows_exactly_the_limit() {
+        assert!(check_depth(&nested_cbor_arrays(8), 8).is_ok());
+        assert!(is_too_deep(check_depth(&nested_cbor_arrays(9), 8)));
+    }
+
+    #[test]
+    fn check_depth_counts_maps_tags_and_indefinite_containers() {
+        // {0: {0: 0}}
+        assert!(check_depth(&[0xa1, 0x00, 0xa1, 0x00, 0x00], 2).is_ok());
+        assert!(is_too_deep(check_depth(&[0xa1, 0x00, 0xa1, 0x00, 0x00], 1)));
+
+        // A tagged array of one element
+        assert!(is_too_deep(check_depth(&[0xc1, 0x81, 0x00], 1)));
+
+        // [_ [_ 0]] with break markers
+        assert!(check_depth(&[0x9f, 0x9f, 0x00, 0xff, 0xff], 2).is_ok());
+        assert!(is_too_deep(check_depth(&[0x9f, 0x9f, 0x00, 0xff, 0xff], 1)));
+    }
+
+    #[test]
+    fn check_depth_closes_containers_once_their_items_are_read() {
+        // [[0], [0], [0]] never goes deeper than two levels
+        let bytes = [0x83, 0x81, 0x00, 0x81, 0x00, 0x81, 0x00];
+        assert!(check_depth(&bytes, 2).is_ok());
+    }
+
+    #[test]
+    fn check_depth_skips_string_contents() {
+        // A byte string of 0x81s isn't nested arrays
+        let mut bytes = vec![0x44];
+        bytes.extend([0x81; 4]);
+        assert!(check_depth(&bytes, 1).is_ok());
+    }
+
+    #[test]
+    fn check_json_depth_allows_exactly_the_limit() {
+        assert!(check_json_depth(&nested_json_arrays(8), 8).is_ok());
+        assert!(is_too_deep(check_json_depth(&nested_json_arrays(9), 8)));
+        assert!(check_json_depth(br#"{"a":{"b":[1]}}"#, 3).is_ok());
+        assert!(is_too_deep(check_json_depth(br#"{"a":{"b":[1]}}"#, 2)));
+    }
+
+    #[test]
+    fn check_json_depth_ignores_brackets_in_strings() {
+        assert!(check_json_depth(br#"{"a":"[[[{{{"}"#, 1).is_ok());
+        assert!(check_json_depth(br#"["\"[[[", "\\"]"#, 1).is_ok());
+    }
+
+    #[test]
+    fn document_depth_limit_allows_what_the_decoders_do() {
+        let deepest = serde_cbor::to_vec(&nested_containers(41)).unwrap();
+        assert!(check_depth(&deepest, DEFAULT_MAX_DOCUMENT_DEPTH).is_ok());
+        assert!(serde_cbor::from_slice::<ServerToClientMessage>(&deepest).is_ok());
+
+        let too_deep = serde_cbor::to_vec(&nested_containers(42)).unwrap();
+        assert!(is_too_deep(check_depth(
+            &too_deep,
+            DEFAULT_MAX_DOCUMENT_DEPTH
+        )));
+
+        let deepest = serde_json::to_vec(&nested_containers(41)).unwrap();
+        assert!(check_json_depth(&deepest, DEFAULT_MAX_DOCUMENT_DEPTH).is_ok());
+        assert!(serde_json::from_slice::<ServerToClientMessage>(&deepest).is_ok());
+    }
+
+    #[test]
+    fn frames_are_only_compressed_when_asked_and_worthwhile() {
+        let small = load("/");
+        let (length, body) = frame(&small, Compression::Deflate, WireFormat::Cbor).unwrap();
+        assert_eq!(length & COMPRESSED_FLAG, 0);
+        assert_eq!(body, serde_cbor::to_vec(&small).unwrap());
+
+        let large = large_load();
+        let (length, body) = frame(&large, Compression::None, WireFormat::Cbor).unwrap();
+        assert_eq!(length & COMPRESSED_FLAG, 0);
+        assert_eq!(body_size(length), body.len());
+
+        let (length, body) = frame(&large, Compression::Deflate, WireFormat::Cbor).unwrap();
+        assert_eq!(body_size(length), body.len());
+        assert_eq!(
+            decompressed(length, &body),
+            serde_cbor::to_vec(&large).unwrap()
+        );
+    }
+
+    #[test]
+    fn incompressible_frames_are_sent_as_they_are() {
+        // Xorshift noise, which deflate can only make bigger
+        let mut state = 0x2545_f491_u32;
+        let bytes = (0..4096)
+            .map(|_| {
+                state ^= state << 13;
+                state ^= state >> 17;
+                state ^= state << 5;
+                state as u8
+            })
+            .collect();
+        let message = ServerToClientMessage::Download {
+            filename: "noise.bin".to_string(),
+            mime: "application/octet-stream".to_string(),
+            bytes,
+        };
+
+        let (length, body) = frame(&message, Compression::Deflate, WireFormat::Cbor).unwrap();
+        assert_eq!(length & COMPRESSED_FLAG, 0);
+        assert_eq!(body, serde_cbor::to_vec(&message).unwrap());
+    }
+
+    #[test]
+    fn compressed_messages_round_trip() {
+        task::block_on(async {
+            let (mut client, mut server) = connected().await;
+            let limits = NetworkLimits::default();
+
+            let large = large_load();
+            let sent = send_request(
+                &mut client,
+                large.clone(),
+                Compression::Deflate,
+                WireFormat::Cbor,
+            )
+            .await
+            .unwrap();
+            assert!(sent < serde_cbor::to_vec(&large).unwrap().len());
+
+            let (received, size) = receive_request(&mut server, &limits)
+                .await
+                .unwrap()
+                .unwrap();
+            assert_eq!(format!("{:?}", received), format!("{:?}", large));
+            assert_eq!(size, sent);
+
+            // Compressed messages are accepted whatever the receiver sends
+            send_request(
+                &mut client,
+                load("/next"),
+                Compression::None,
+                WireFormat::Cbor,
+            )
+            .await
+            .unwrap();
+            let (received, _) = receive_request(&mut server, &limits)
+                .await
+                .unwrap()
+                .unwrap();
+            assert!(
+                matches!(received, ClientToServerMessage::Load { path, .. } if path == "/next")
+            );
+        })
+    }
+
+    #[test]
+    fn compressed_messages_are_limited_by_their_decompressed_size() {
+        task::block_on(async {
+            let (mut client, mut server) = connected().await;
+            let large = large_load();
+            let (length, body) = frame(&large, Compression::Deflate, WireFormat::Cbor).unwrap();
+            let limits = NetworkLimits {
+                max_message_size: body.len() + 1,
+                ..NetworkLimits::default()
+            };
+
+            send_request(&mut client, large, Compression::Deflate, WireFormat::Cbor)
+                .await
+                .unwrap();
+            assert!(body_size(length) <= limits.max_message_size);
+
+            let err = receive_request(&mut server, &limits).await.unwrap_err();
+            assert!(err.is::<MessageTooLarge>());
+        })
+    }
+
+    /// A request exactly as `send_request` would put it on the wire
+    fn wire_bytes(request: &ClientToServerMessage) -> Vec<u8> {
+        let (length, body) = frame(request, Compression::None, WireFormat::Cbor).unwrap();
+        let mut bytes = length.to_le_bytes().to_vec();
+        bytes.extend(body);
+        bytes
+    }
+
+    #[test]
+    fn messages_arriving_a_byte_at_a_time_are_read_whole() {
+        task::block_on(async {
+            let (mut client, mut server) = connected().await;
+            client.set_nodelay(true).unwrap();
+            let bytes = wire_bytes(&load("/slowly"));
+
+            let writer = task::spawn(async move {
+                for byte in bytes {
+                    client.write_all(&[byte]).await.unwrap();
+                    task::sleep(Duration::from_millis(1)).await;
+                }
+                client
+            });
+
+            let (received, size) = receive_request(&mut server, &NetworkLimits::default())
+                .await
+                .unwrap()
+                .unwrap();
+            assert!(
+                matches!(received, ClientToServerMessage::Load { path, .. } if path == "/slowly")
+            );
+            assert_eq!(size, wire_bytes(&load("/slowly")).len());
+            writer.await;
+        })
+    }
+
+    #[test]
+    fn closing_between_messages_ends_cleanly() {
+        task::block_on(async {
+            let (client, mut server) = connected().await;
+            drop(client);
+
+            let received = receive_request(&mut server, &NetworkLimits::default()).await;
+            assert!(matches!(received, Ok(None)));
+        })
+    }
+
+    #[test]
+    fn closing_partway_through_a_message_is_an_error() {
+        let bytes = wire_bytes(&load("/cut-short"));
+
+        // Partway through the length prefix, and partway through the body
+        for cut in [2, bytes.len() - 3] {
+            task::block_on(async {
+                let (mut client, mut server) = connected().await;
+                client.write_all(&bytes[..cut]).await.unwrap();
+                drop(client);
+
+                let err = receive_request(&mut server, &NetworkLimits::default())
+                    .await
+                    .unwrap_err();
+                let err = err.downcast::<std::io::Error>().unwrap();
+                assert_eq!(
+                    err.kind(),
+                    std::io::ErrorKind::UnexpectedEof,
+                    "cut at {}",
+                    cut
+                );
+            })
+        }
+    }
+
+    #[test]
+    fn stalling_partway_through_a_message_times_out() {
+        let bytes = wire_bytes(&load("/stalled"));
+        let limits = NetworkLimits {
+            read_timeout: Duration::from_millis(50),
+            ..NetworkLimits::default()
+        };
+
+        for stall in [1, bytes.len() - 1] {
+            task::block_on(async {
+                let (mut client, mut server) = connected().await;
+                client.write_all(&bytes[..stall]).await.unwrap();
+
+                let err = receive_request(&mut server, &limits).await.unwrap_err();
+                assert!(err.is::<ReadTimeout>(), "stalled at {}: {}", stall, err);
+                drop(client);
+            })
+        }
+    }
+
+    #[test]
+    fn messages_may_be_exactly_the_size_limit() {
+        task::block_on(async {
+            let (mut client, mut server) = connected().await;
+            let (length, _) = frame(&load("/edge"), Compression::None, WireFormat::Cbor).unwrap();
+            let at_limit = NetworkLimits {
+                max_message_size: body_size(length),
+                ..NetworkLimits::default()
+            };
+
+            send_request(
+                &mut client,
+                load("/edge"),
+                Compression::None,
+                WireFormat::Cbor,
+            )
+            .await
+            .unwrap();
+            assert!(receive_request(&mut server, &at_limit).await.is_ok());
+
+            let over_limit = NetworkLimits {
+                max_message_size: body_size(length) - 1,
+                ..NetworkLimits::default()
+            };
+
+            send_request(
+                &mut client,
+                load("/edge"),
+                Compression::None,
+                WireFormat::Cbor,
+            )
+            .await
+            .unwrap();
+            let err = receive_request(&mut server, &over_limit).await.unwrap_err();
+            let err = err.downcast::<MessageTooLarge>().unwrap();
+            assert_eq!(err.limit, body_size(length) - 1);
+        })
+    }
+
+    #[test]
+    fn oversized_messages_are_refused_before_their_body_arrives() {
+        task::block_on(async {
+            let (mut client, mut server) = connected().await;
+            let limits = NetworkLimits {
+                max_message_size: 1024,
+                ..NetworkLimits::default()
+            };
+
+            // Only the length is sent, so reading the body would time out
+            client.write_all(&1025u32.to_le_bytes()).await.unwrap();
+
+            let err = receive_response(&mut server, &limits).await.unwrap_err();
+            assert!(err.is::<MessageTooLarge>(), "{}", err);
+        })
+    }
+
+    fn render_cases() -> Vec<(Option<RenderMeta>, Option<u64>)> {
+        let meta = RenderMeta {
+            canonical_path: Some("/todos".to_string()),
+            version: Some(42),
+            ..RenderMeta::default()
+        };
+        vec![
+            (None, None),
+            (Some(meta.clone()), None),
+            (Some(meta), Some(7)),
+        ]
+    }
+
+    #[test]
+    fn encoded_documents_are_framed_like_render_messages() {
+        let document = Document(Node::text("Hello"));
+        let encoded = document.clone().encode().unwrap();
+
+        for (meta, request_id) in render_cases() {
+            let message = ServerToClientMessage::Render {
+                document: document.clone(),
+                meta: meta.clone(),
+                request_id,
+            };
+            assert_eq!(
+                render_cbor(&encoded, &meta, request_id).unwrap(),
+                serde_cbor::to_vec(&message).unwrap()
+            );
+        }
+    }
+
+    #[test]
+    fn encoded_documents_are_framed_like_prefetched_messages() {
+        let document = Document(Node::text("Hello"));
+        let encoded = document.clone().encode().unwrap();
+
+        for (meta, _) in render_cases() {
+            let message = ServerToClientMessage::Prefetched {
+                path: "/todos".to_string(),
+                document: document.clone(),
+                meta: meta.clone(),
+            };
+            assert_eq!(
+                prefetched_cbor("/todos", &encoded, &meta).unwrap(),
+                serde_cbor::to_vec(&message).unwrap()
+            );
+        }
+    }
+
+    #[test]
+    fn encoded_documents_are_received_as_prefetched() {
+        task::block_on(async {
+            let (mut server, mut client) = connected().await;
+            let document = Document(Node::text("Hello"));
+            let encoded = document.clone().encode().unwrap();
+
+            for format in [WireFormat::Cbor, WireFormat::Json] {
+                let sent = send_encoded_prefetched(
+                    &mut server,
+                    "/todos",
+                    &encoded,
+                    None,
+                    Compression::None,
+                    format,
+                )
+                .await
+                .unwrap();
+
+                let (received, size) = receive_response(&mut client, &NetworkLimits::default())
+                    .await
+                    .unwrap()
+                    .unwrap();
+                assert_eq!(size, sent);
+                match received {
+                    ServerToClientMessage::Prefetched {
+                        path,
+                        document: received_document,
+                        meta,
+                    } => {
+                        assert_eq!(path, "/todos");
+                        assert_eq!(received_document, document);
+                        assert_eq!(meta, None);
+                    }
+                    other => panic!("Expected a prefetched document, got {:?}", other),
+                }
+            }
+        })
+    }
+
+    #[test]
+    fn encoded_documents_are_received_as_renders() {
+        task::block_on(async {
+            let (mut server, mut client) = connected().await;
+            let document = Document(Node::text("Hello"));
+            let encoded = document.clone().encode().unwrap();
+
+            for format in [WireFormat::Cbor, WireFormat::Json] {
+                for (meta, request_id) in render_cases() {
+                    let sent = send_encoded_document(
+                        &mut server,
+                        &encoded,
+                        meta.clone(),
+                        request_id,
+                        Compression::Deflate,
+                        format,
+                    )
+                    .await
+                    .unwrap();
+
+                    let (received, size) = receive_response(&mut client, &NetworkLimits::default())
+                        .await
+                        .unwrap()
+                        .unwrap();
+                    assert_eq!(size, sent);
+                    match received {
+                        ServerToClientMessage::Render {
+                            document: received_document,
+                            meta: received_meta,
+                            request_id: received_id,
+                        } => {
+                            assert_eq!(received_document, document);
+                            assert_eq!(received_meta, meta);
+                            assert_eq!(received_id, request_id);
+                        }
+                        other => panic!("Expected a render, got {:?}", other),
+                    }
+                }
+            }
+        })
+    }
+
+    /// One of each request. Maps hold at most one entry, so they print the
+    /// same way after a round trip.
+    fn every_request() -> Vec<ClientToServerMessage> {
+        vec![
+            ClientToServerMessage::Load {
+                path: "/todos".to_string(),
+                storage: HashMap::from([("token".to_string(), "abc".to_string())]),
+                version: Some(42),
+                request_id: Some(1),
+            },
+            ClientToServerMessage::Action {
+                path: "/todos".to_string(),
+                action: Action::named("add", vec!["title".to_string()]),
+                state_map: StateMap::from([(
+                    "title".to_string(),
+                    StateValue::String("Milk".to_string()),
+                )]),
+                storage: HashMap::new(),
+                request_id: Some(2),
+            },
+            ClientToServerMessage::Viewport {
+                width: 800,
+                height: 600,
+            },
+            ClientToServerMessage::StoreAck { id: 3 },
+            ClientToServerMessage::Locale {
+                locale: "fr-CA".to_string(),
+            },
+            ClientToServerMessage::Capabilities {
+                capabilities: vec![JSON.to_string(), DEFLATE.to_string()],
+            },
+            ClientToServerMessage::Ping,
+            ClientToServerMessage::Prefetch {
+                path: "/about".to_string(),
+                storage: HashMap::new(),
+                request_id: None,
+            },
+        ]
+    }
+
+    /// One of each response, with the same caveat as `every_request`.
+    fn every_response() -> Vec<ServerToClientMessage> {
+        let meta = RenderMeta {
+            canonical_path: Some("/todos".to_string()),
+            version: Some(42),
+            ..RenderMeta::default()
+        };
+
+        vec![
+            ServerToClientMessage::Render {
+                document: Document(Node::text("Hello")),
+                meta: Some(meta.clone()),
+                request_id: Some(1),
+            },
+            ServerToClientMessage::RedirectTo {
+                path: "/login".to_string(),
+                request_id: None,
+            },
+            ServerToClientMessage::NotModified,
+            ServerToClientMessage::Store {
+                scope: StorageScope::Session,
+                key: "token".to_string(),
+                value: "abc".to_string(),
+                ack: Some(3),
+            },
+            ServerToClientMessage::StoreBatch {
+                items: vec![
+                    (
+                        StorageScope::Persistent,
+                        "name".to_string(),
+                        StateValue::String("Ada".to_string()),
+                    ),
+                    (
+                        StorageScope::Session,
+                        "admin".to_string(),
+                        StateValue::Boolean(true),
+                    ),
+                ],
+            },
+            ServerToClientMessage::ClearStorage {
+                scope: StorageScope::Local,
+            },
+            ServerToClientMessage::Error {
+                code: ErrorCode::Forbidden,
+                message: "No".to_string(),
+                request_id: Some(2),
+            },
+            ServerToClientMessage::ScheduleReload { after_ms: 5000 },
+            ServerToClientMessage::Download {
+                filename: "report.bin".to_string(),
+                mime: "application/octet-stream".to_string(),
+                bytes: vec![0, 1, 254, 255],
+            },
+            ServerToClientMessage::Capabilities {
+                capabilities: vec![JSON.to_string()],
+            },
+            ServerToClientMessage::Pong,
+            ServerToClientMessage::ValidationErrors {
+                errors: HashMap::from([("title".to_string(), "Required".to_string())]),
+            },
+            ServerToClientMessage::Notify {
+                severity: ToastSeverity::Success,
+                text: "Saved".to_string(),
+            },
+            ServerToClientMessage::ActionData {
+                value: StateValue::Array(vec![
+                    StateValue::String("Milk".to_string()),
+                    StateValue::Boolean(false),
+                ]),
+            },
+            ServerToClientMessage::Prefetch {
+                paths: vec!["/about".to_string()],
+            },
+            ServerToClientMessage::Prefetched {
+                path: "/about".to_string(),
+                document: Document(Node::text("About")),
+                meta: Some(meta.clone()),
+            },
+            ServerToClientMessage::Patch {
+                id: "slot".to_string(),
+                node: Node::text("Filled in"),
+            },
+            ServerToClientMessage::RenderBegin {
+                layout: Layout::default(),
+                meta: Some(meta),
+            },
+            ServerToClientMessage::RenderAppend {
+                node: Node::text("Part"),
+            },
+            ServerToClientMessage::RenderEnd,
+            ServerToClientMessage::DownloadBegin {
+                filename: "report.csv".to_string(),
+                mime: "text/csv".to_string(),
+            },
+            ServerToClientMessage::DownloadChunk {
+                bytes: b"a,b\n".to_vec(),
+            },
+            ServerToClientMessage::DownloadEnd,
+            ServerToClientMessage::ClientDirective {
+                kind: Directive::CopyToClipboard("Copied".to_string()),
+            },
+        ]
+    }
+
+    #[test]
+    fn json_frames_are_flagged_as_json() {
+        let request = load("/");
+        let (length, body) = frame(&request, Compression::None, WireFormat::Json).unwrap();
+        assert!(length & JSON_FLAG != 0);
+        assert_eq!(length & COMPRESSED_FLAG, 0);
+        assert_eq!(body_size(length), body.len());
+        assert_eq!(body, serde_json::to_vec(&request).unwrap());
+
+        let (length, _) = frame(&request, Compression::None, WireFormat::Cbor).unwrap();
+        assert_eq!(length & JSON_FLAG, 0);
+    }
+
+    #[test]
+    fn every_message_round_trips_over_json() {
+        task::block_on(async {
+            let (mut client, mut server) = connected().await;
+            let limits = NetworkLimits::default();
+
+            for request in every_request() {
+                let sent = send_request(
+                    &mut client,
+                    request.clone(),
+                    Compression::None,
+                    WireFormat::Json,
+                )
+                .await
+                .unwrap();
+                let (received, size) = receive_request(&mut server, &limits)
+                    .await
+                    .unwrap()
+                    .unwrap();
+                assert_eq!(format!("{:?}", received), format!("{:?}", request));
+                assert_eq!(size, sent);
+            }
+
+            for response in every_response() {
+                let sent = send_response(
+                    &mut server,
+                    response.clone(),
+                    Compression::None,
+                    WireFormat::Json,
+                )
+                .await
+                .unwrap();
+                let (received, size) = receive_response(&mut client, &limits)
+                    .await
+                    .unwrap()
+                    .unwrap();
+                assert_eq!(format!("{:?}", received), format!("{:?}", response));
+                assert_eq!(size, sent);
+            }
+        })
+    }
+
+    #[test]
+    fn compressed_json_round_trips() {
+        task::block_on(async {
+            let (mut client, mut server) = connected().await;
+            let large = large_load();
+
+            let (length, body) = frame(&large, Compression::Deflate, WireFormat::Json).unwrap();
+            assert!(length & JSON_FLAG != 0);
+            assert_eq!(
+                decompressed(length, &body),
+                serde_json::to_vec(&large).unwrap()
+            );
+
+            let sent = send_request(
+                &mut client,
+                large.clone(),
+                Compression::Deflate,
+                WireFormat::Json,
+            )
+            .await
+            .unwrap();
+            let (received, size) = receive_request(&mut server, &NetworkLimits::default())
+                .await
+                .unwrap()
+                .unwrap();
+            assert_eq!(format!("{:?}", received), format!("{:?}", large));
+            assert_eq!(size, sent);
+        })
+    }
+
+    #[test]
+    fn bodies_in_the_wrong_format_fail_cleanly() {
+        let request = load("/mismatched");
+        let json = serde_json::to_vec(&request).unwrap();
+        let cbor = serde_cbor::to_vec(&request).unwrap();
+        let garbage = vec![0xff, 0x00, 0x7b, 0x5b];
+
+        // Each body under a prefix announcing the other format, or nonsense
+        // under either
+        let cases = [
+            (json.len() as u32, json.clone()),
+            (cbor.len() as u32 | JSON_FLAG, cbor),
+            (garbage.len() as u32, garbage.clone()),
+            (garbage.len() as u32 | JSON_FLAG, garbage),
+        ];
+
+        for (length, body) in cases {
+            task::block_on(async {
+                let (mut client, mut server) = connected().await;
+                client.write_all(&length.to_le_bytes()).await.unwrap();
+                client.write_all(&body).await.unwrap();
+
+                let received = receive_request(&mut server, &NetworkLimits::default()).await;
+                assert!(received.is_err(), "decoded {:?}", received);
+            })
+        }
+    }
+
+    #[test]
+    fn frames_too_large_to_announce_are_refused() {
+        let (length, bytes) =
+            frame_encoded(vec![0; MAX_FRAME_SIZE], Compression::None, WireFormat::Cbor).unwrap();
+        assert_eq!(body_size(length), bytes.len());
+        assert_eq!(length & (COMPRESSED_FLAG | JSON_FLAG), 0);
+
+        let err = frame_encoded(
+            vec![0; MAX_FRAME_SIZE + 1],
+            Compression::None,
+            WireFormat::Cbor,
+        )
+        .unwrap_err();
+        assert!(err.is::<MessageTooLarge>(), "{}", err);
+    }
+}
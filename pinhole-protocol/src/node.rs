@@ -1,35 +1,396 @@
-use serde::{Deserialize, Serialize};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
 
-use crate::{action::Action, layout::Layout};
+use crate::{
+    action::Action,
+    layout::{Layout, Padding, Position},
+    lenient::Lenient,
+};
 
-#[derive(Clone, Debug, Serialize, Deserialize)]
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
 pub struct TextProps {
     pub text: String,
+
+    /// Which side of the available width lines are aligned to. With
+    /// anything but `Start` the text fills its container's width.
+    #[serde(default)]
+    pub align: Position,
 }
 
-#[derive(Clone, Debug, Serialize, Deserialize)]
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
 pub struct ButtonProps {
     pub label: String,
     pub on_click: Action,
+
+    /// Space around the label. The client's default is used when `None`.
+    #[serde(default)]
+    pub padding: Option<Padding>,
+
+    /// Shown greyed out, and clicking does nothing
+    #[serde(default)]
+    pub disabled: bool,
 }
 
-#[derive(Clone, Debug, Serialize, Deserialize)]
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
 pub struct CheckboxProps {
     pub id: String,
     pub label: String,
     pub checked: bool,
     pub on_change: Action,
+
+    /// Shown greyed out, and clicking doesn't change it
+    #[serde(default)]
+    pub disabled: bool,
+
+    /// Shown as neither checked nor unchecked, e.g. for a "select all" box
+    /// when only some items are selected. Its value is `StateValue::Null`
+    /// until the user clicks it, which checks it.
+    #[serde(default)]
+    pub indeterminate: bool,
 }
 
-#[derive(Clone, Debug, Serialize, Deserialize)]
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
 pub struct InputProps {
     pub id: String,
     pub label: String,
     pub password: bool,
     pub placeholder: Option<String>,
+
+    /// Value to show in the input. When `None` the client keeps whatever
+    /// the user has typed so far; when set, it replaces it on render.
+    #[serde(default)]
+    pub value: Option<String>,
+
+    /// Position in the Tab order. Positive values come first in ascending
+    /// order, then fields with `None` or `0` in document order. Negative
+    /// values are skipped when tabbing.
+    #[serde(default)]
+    pub tab_index: Option<i32>,
+
+    /// Focus this field when the document is rendered. If several fields
+    /// ask for it, the first one in document order wins.
+    #[serde(default)]
+    pub autofocus: bool,
+
+    /// Fired as the user types, e.g. for a live search
+    #[serde(default)]
+    pub on_change: Option<Action>,
+
+    /// Waits until the user has stopped typing for this long before firing
+    /// `on_change`, so one action is sent rather than one per keystroke
+    #[serde(default)]
+    pub debounce_ms: Option<u64>,
+}
+
+/// A text field for longer text, such as a comment. The text is stored
+/// under `id` as a string.
+///
+/// The client can't edit multi-line text yet: it shows a single-line field
+/// in a box `rows` lines tall, and newlines can't be typed.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct TextAreaProps {
+    pub id: String,
+    pub label: String,
+    pub placeholder: Option<String>,
+
+    /// Number of lines of text to make room for, though the text itself is
+    /// edited on one line for now
+    pub rows: u16,
+
+    /// Focus this field when the document is rendered, as for `InputProps`
+    #[serde(default)]
+    pub autofocus: bool,
+
+    /// Value to show in the field. When `None` the client keeps whatever
+    /// the user has typed so far; when set, it replaces it on render.
+    #[serde(default)]
+    pub value: Option<String>,
+}
+
+#[derive(Copy, Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub enum TooltipPosition {
+    #[default]
+    Top,
+    Bottom,
+    Left,
+    Right,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct TooltipProps {
+    pub text: String,
+    #[serde(default)]
+    pub position: TooltipPosition,
+    pub child: Box<Node>,
+}
+
+/// A user's picture, or their initials on a coloured circle when there is no
+/// picture to show.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct AvatarProps {
+    pub name: String,
+
+    /// Location of the picture. The client can't load images yet, so for
+    /// now it always falls back to the initials.
+    #[serde(default)]
+    pub source: Option<String>,
+
+    /// Diameter in pixels
+    pub size: f32,
+}
+
+impl AvatarProps {
+    /// Up to two initials: the first letters of the first and last words.
+    pub fn initials(&self) -> String {
+        let mut words = self.name.split_whitespace();
+        let first = words.next().and_then(|word| word.chars().next());
+        let last = words.last().and_then(|word| word.chars().next());
+
+        first
+            .into_iter()
+            .chain(last)
+            .flat_map(char::to_uppercase)
+            .collect()
+    }
+}
+
+/// A number field with buttons to step it up and down. The value is stored
+/// under `id` as a number, kept between `min` and `max`.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct NumberInputProps {
+    pub id: String,
+    pub label: String,
+
+    /// Shown until the user changes it
+    pub value: f64,
+    pub min: Option<f64>,
+    pub max: Option<f64>,
+
+    /// How much the buttons change the value by
+    pub step: f64,
+
+    /// Fired after the value changes
+    pub on_change: Action,
+}
+
+/// Text that navigates the client to `to` when clicked, without asking the
+/// server first. Linking to the current path loads it again.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct LinkProps {
+    pub label: String,
+    pub to: String,
+}
+
+/// A line of text made of differently styled runs, e.g. with a word in bold
+/// or a link in the middle.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct RichTextProps {
+    pub spans: Vec<Span>,
+}
+
+/// A run of text in a `RichText`, styled as a whole.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct Span {
+    pub text: String,
+
+    /// Clients without a bold face draw the text as usual
+    #[serde(default)]
+    pub bold: bool,
+
+    /// Clients without an italic face draw the text as usual
+    #[serde(default)]
+    pub italic: bool,
+
+    /// Replaces the theme's text colour
+    #[serde(default)]
+    pub colour: Option<Colour>,
+
+    /// Navigates to this path when clicked, like a `Link`
+    #[serde(default)]
+    pub link: Option<String>,
+}
+
+impl Span {
+    pub fn new(text: impl ToString) -> Span {
+        Span {
+            text: text.to_string(),
+            ..Span::default()
+        }
+    }
+
+    pub fn bold(self) -> Span {
+        Span { bold: true, ..self }
+    }
+
+    pub fn italic(self) -> Span {
+        Span {
+            italic: true,
+            ..self
+        }
+    }
+
+    pub fn colour(self, colour: Colour) -> Span {
+        Span {
+            colour: Some(colour),
+            ..self
+        }
+    }
+
+    pub fn link(self, to: impl ToString) -> Span {
+        Span {
+            link: Some(to.to_string()),
+            ..self
+        }
+    }
+}
+
+/// An sRGB colour.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Colour {
+    pub red: u8,
+    pub green: u8,
+    pub blue: u8,
+}
+
+/// A horizontal line across the container, for separating groups of nodes.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct DividerProps {
+    /// Height in pixels taken up by the divider, with the line centred in it
+    pub spacing: f32,
+}
+
+/// One of the client's built-in icons, drawn in the text colour. Names the
+/// client doesn't know leave a blank space of the same size.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct IconProps {
+    /// `check`, `chevron-down`, `close`, `plus`, `minus`, `more` or `dot`
+    pub name: String,
+
+    /// Height in pixels. The client's text size is used when `None`.
+    #[serde(default)]
+    pub size: Option<u16>,
+}
+
+/// How far along a long-running task is, as a bar across the container.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct ProgressProps {
+    /// The fraction done, from 0.0 to 1.0. When `None` the amount isn't
+    /// known, and the client shows a spinner instead.
+    #[serde(default)]
+    pub value: Option<f64>,
+}
+
+/// An animated activity indicator for content that is still loading.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct SpinnerProps {
+    /// Width in pixels
+    pub size: f32,
+}
+
+/// A dropdown for choosing one of several options. The chosen value is
+/// stored under `id` as a string.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct SelectProps {
+    pub id: String,
+
+    /// `(value, label)` for each option, in order
+    pub options: Vec<(String, String)>,
+
+    /// Value to select. When `None` the client keeps the user's choice;
+    /// when set, it replaces it on render.
+    #[serde(default)]
+    pub selected: Option<String>,
+
+    /// Fired after the choice changes
+    pub on_change: Action,
+}
+
+/// A column of radio buttons for choosing one of several options. The
+/// chosen value is stored under `id` as a string; until there is one, no
+/// option is chosen.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct RadioProps {
+    pub id: String,
+
+    /// `(value, label)` for each option, in order
+    pub options: Vec<(String, String)>,
+
+    /// Value to select. When `None` the client keeps the user's choice;
+    /// when set, it replaces it on render.
+    #[serde(default)]
+    pub selected: Option<String>,
+
+    /// Fired after the choice changes
+    pub on_change: Action,
+}
+
+/// A placeholder for part of a document that the server fills in later with
+/// `Patch`, e.g. a dashboard section whose data is still loading. Content
+/// patched into a slot inside a form isn't attached to the form.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct SlotProps {
+    pub id: String,
+
+    /// Shown until the slot is patched
+    pub child: Box<Node>,
+}
+
+/// A row of adjacent buttons of which one is selected, like radio buttons.
+/// The selected value is stored under `id` as a string.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct ButtonGroupProps {
+    pub id: String,
+
+    /// `(value, label)` for each button, in order
+    pub options: Vec<(String, String)>,
+
+    /// Value to select. When `None` the client keeps the user's selection;
+    /// when set, it replaces it on render.
+    #[serde(default)]
+    pub selected: Option<String>,
+
+    /// Fired after the selection changes
+    #[serde(default)]
+    pub on_change: Option<Action>,
+}
+
+/// Groups form fields. Pressing Enter in one of its inputs fires
+/// `on_submit`, and both that and any button click inside the form send the
+/// values of every input, text area, number input, checkbox, button group,
+/// select and radio group in the form, in addition to the keys the action
+/// lists itself.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct FormProps {
+    pub on_submit: Action,
+    pub children: Vec<Box<Node>>,
+}
+
+#[derive(Copy, Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub enum ToastSeverity {
+    #[default]
+    Info,
+    Success,
+    Warning,
+    Error,
 }
 
-#[derive(Clone, Debug, Serialize, Deserialize)]
+/// A transient message the client shows above the document and dismisses
+/// on its own after `duration_ms`.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct ToastProps {
+    /// Identifies the toast across re-renders. While successive documents
+    /// contain a toast with the same id it is shown once and its timer is
+    /// not restarted.
+    pub id: String,
+    pub text: String,
+    pub duration_ms: u64,
+    #[serde(default)]
+    pub severity: ToastSeverity,
+}
+
+/// Node types this version doesn't know, e.g. from a newer server, decode as
+/// `Empty` so the rest of the document still renders.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(remote = "Self")]
 pub enum Node {
     Empty,
     Container {
@@ -40,10 +401,397 @@ pub enum Node {
     Button(ButtonProps),
     Checkbox(CheckboxProps),
     Input(InputProps),
+    Tooltip(TooltipProps),
+    Toast(ToastProps),
+    Form(FormProps),
+    Avatar(AvatarProps),
+    Spinner(SpinnerProps),
+    ButtonGroup(ButtonGroupProps),
+    Slot(SlotProps),
+    Select(SelectProps),
+    Radio(RadioProps),
+    TextArea(TextAreaProps),
+    Link(LinkProps),
+    NumberInput(NumberInputProps),
+    Divider(DividerProps),
+    Icon(IconProps),
+    Progress(ProgressProps),
+    RichText(RichTextProps),
+}
+
+/// A difference between two node trees. Paths are the child indices leading
+/// from the root to the node; a tooltip's or slot's child is at index 0.
+#[derive(Clone, Debug, PartialEq)]
+pub enum NodeChange {
+    Added {
+        path: Vec<usize>,
+        node: Node,
+    },
+    Removed {
+        path: Vec<usize>,
+        node: Node,
+    },
+    Changed {
+        path: Vec<usize>,
+        from: Box<Node>,
+        to: Box<Node>,
+    },
+}
+
+impl Serialize for Node {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        Node::serialize(self, serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for Node {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        Node::deserialize(Lenient::new(deserializer, "Empty"))
+    }
+}
+
+/// A container or form being built up a child at a time, from
+/// `Node::container` or `Node::form`. Only nodes that hold children are built
+/// this way, so adding one can't fail.
+#[derive(Clone, Debug, PartialEq)]
+#[must_use]
+pub struct ContainerBuilder {
+    kind: ContainerKind,
+    children: Vec<Node>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum ContainerKind {
+    Container(Layout),
+    Form(Action),
+}
+
+impl ContainerBuilder {
+    fn new(kind: ContainerKind) -> ContainerBuilder {
+        ContainerBuilder {
+            kind,
+            children: Vec::new(),
+        }
+    }
+
+    /// Appends `child`, which may itself be a container being built.
+    pub fn child(mut self, child: impl Into<Node>) -> ContainerBuilder {
+        self.children.push(child.into());
+        self
+    }
+
+    /// Appends each of `children` in order.
+    pub fn children<N: Into<Node>>(
+        self,
+        children: impl IntoIterator<Item = N>,
+    ) -> ContainerBuilder {
+        children.into_iter().fold(self, ContainerBuilder::child)
+    }
+
+    pub fn build(self) -> Node {
+        let ContainerBuilder { kind, children } = self;
+        let children = children.into_iter().map(Node::boxed).collect();
+        match kind {
+            ContainerKind::Container(layout) => Node::Container { layout, children },
+            ContainerKind::Form(on_submit) => Node::Form(FormProps {
+                on_submit,
+                children,
+            }),
+        }
+    }
+
+    pub fn boxed(self) -> Box<Node> {
+        self.build().boxed()
+    }
+}
+
+impl From<ContainerBuilder> for Node {
+    fn from(builder: ContainerBuilder) -> Node {
+        builder.build()
+    }
 }
 
 impl Node {
+    /// A container with no children yet; add them with `child`.
+    pub fn container(layout: Layout) -> ContainerBuilder {
+        ContainerBuilder::new(ContainerKind::Container(layout))
+    }
+
+    /// A form with no children yet; add them with `child`. The ids of the
+    /// fields added are sent with `on_submit` without it listing them.
+    pub fn form(on_submit: Action) -> ContainerBuilder {
+        ContainerBuilder::new(ContainerKind::Form(on_submit))
+    }
+
+    pub fn text(text: impl ToString) -> Node {
+        Node::Text(TextProps {
+            text: text.to_string(),
+            align: Position::Start,
+        })
+    }
+
     pub fn boxed(self) -> Box<Self> {
         Box::new(self)
     }
+
+    /// Structural differences from `self` to `other`. Children of matching
+    /// containers, forms, tooltips and slots are compared by position; any other
+    /// mismatch is reported as a change of the whole node.
+    pub fn diff(&self, other: &Node) -> Vec<NodeChange> {
+        let mut changes = Vec::new();
+        self.diff_into(other, &mut Vec::new(), &mut changes);
+        changes
+    }
+
+    fn diff_into(&self, other: &Node, path: &mut Vec<usize>, changes: &mut Vec<NodeChange>) {
+        match (self, other) {
+            (
+                Node::Container { layout, children },
+                Node::Container {
+                    layout: other_layout,
+                    children: other_children,
+                },
+            ) if layout == other_layout => {
+                Self::diff_children(children, other_children, path, changes)
+            }
+
+            (
+                Node::Form(FormProps {
+                    on_submit,
+                    children,
+                }),
+                Node::Form(FormProps {
+                    on_submit: other_on_submit,
+                    children: other_children,
+                }),
+            ) if on_submit == other_on_submit => {
+                Self::diff_children(children, other_children, path, changes)
+            }
+
+            (
+                Node::Tooltip(TooltipProps {
+                    text,
+                    position,
+                    child,
+                }),
+                Node::Tooltip(TooltipProps {
+                    text: other_text,
+                    position: other_position,
+                    child: other_child,
+                }),
+            ) if text == other_text && position == other_position => {
+                path.push(0);
+                child.diff_into(other_child, path, changes);
+                path.pop();
+            }
+
+            (
+                Node::Slot(SlotProps { id, child }),
+                Node::Slot(SlotProps {
+                    id: other_id,
+                    child: other_child,
+                }),
+            ) if id == other_id => {
+                path.push(0);
+                child.diff_into(other_child, path, changes);
+                path.pop();
+            }
+
+            _ if self != other => changes.push(NodeChange::Changed {
+                path: path.clone(),
+                from: Box::new(self.clone()),
+                to: Box::new(other.clone()),
+            }),
+
+            _ => {}
+        }
+    }
+
+    fn diff_children(
+        children: &[Box<Node>],
+        other_children: &[Box<Node>],
+        path: &mut Vec<usize>,
+        changes: &mut Vec<NodeChange>,
+    ) {
+        for index in 0..children.len().max(other_children.len()) {
+            path.push(index);
+
+            match (children.get(index), other_children.get(index)) {
+                (Some(from), Some(to)) => from.diff_into(to, path, changes),
+                (Some(from), None) => changes.push(NodeChange::Removed {
+                    path: path.clone(),
+                    node: *from.clone(),
+                }),
+                (None, Some(to)) => changes.push(NodeChange::Added {
+                    path: path.clone(),
+                    node: *to.clone(),
+                }),
+                (None, None) => unreachable!(),
+            }
+
+            path.pop();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn container(children: Vec<Node>) -> Node {
+        Node::Container {
+            layout: Layout::default(),
+            children: children.into_iter().map(Node::boxed).collect(),
+        }
+    }
+
+    fn initials(name: &str) -> String {
+        AvatarProps {
+            name: name.to_string(),
+            source: None,
+            size: 32.0,
+        }
+        .initials()
+    }
+
+    #[test]
+    fn initials_are_the_first_letters_of_the_first_and_last_words() {
+        assert_eq!(initials("Ada Lovelace"), "AL");
+        assert_eq!(initials("mary ann evans"), "ME");
+        assert_eq!(initials("  Grace   Hopper "), "GH");
+    }
+
+    #[test]
+    fn initials_of_one_word_or_none() {
+        assert_eq!(initials("Plato"), "P");
+        assert_eq!(initials(""), "");
+        assert_eq!(initials("   "), "");
+    }
+
+    #[test]
+    fn initials_handle_non_ascii_letters() {
+        assert_eq!(initials("élodie ørsted"), "ÉØ");
+    }
+
+    #[test]
+    fn diff_of_equal_trees_is_empty() {
+        let tree = container(vec![Node::text("a"), container(vec![Node::text("b")])]);
+
+        assert_eq!(tree.diff(&tree.clone()), vec![]);
+    }
+
+    #[test]
+    fn diff_reports_changed_nodes_by_path() {
+        let from = container(vec![Node::text("a"), container(vec![Node::text("b")])]);
+        let to = container(vec![Node::text("a"), container(vec![Node::text("c")])]);
+
+        assert_eq!(
+            from.diff(&to),
+            vec![NodeChange::Changed {
+                path: vec![1, 0],
+                from: Box::new(Node::text("b")),
+                to: Box::new(Node::text("c")),
+            }]
+        );
+    }
+
+    #[test]
+    fn diff_reports_added_and_removed_children() {
+        let short = container(vec![Node::text("a")]);
+        let long = container(vec![Node::text("a"), Node::text("b")]);
+
+        assert_eq!(
+            short.diff(&long),
+            vec![NodeChange::Added {
+                path: vec![1],
+                node: Node::text("b"),
+            }]
+        );
+        assert_eq!(
+            long.diff(&short),
+            vec![NodeChange::Removed {
+                path: vec![1],
+                node: Node::text("b"),
+            }]
+        );
+    }
+
+    #[test]
+    fn diff_replaces_containers_whose_layout_changed() {
+        let from = container(vec![Node::text("a")]);
+        let to = Node::Container {
+            layout: Layout::default().margin(Padding::all(4.0)),
+            children: vec![Node::text("a").boxed()],
+        };
+
+        assert_eq!(
+            from.diff(&to),
+            vec![NodeChange::Changed {
+                path: vec![],
+                from: Box::new(from.clone()),
+                to: Box::new(to.clone()),
+            }]
+        );
+    }
+
+    #[test]
+    fn diff_looks_inside_slots() {
+        let slot = |text| {
+            Node::Slot(SlotProps {
+                id: "slot".to_string(),
+                child: Node::text(text).boxed(),
+            })
+        };
+
+        assert_eq!(
+            slot("a").diff(&slot("b")),
+            vec![NodeChange::Changed {
+                path: vec![0],
+                from: Box::new(Node::text("a")),
+                to: Box::new(Node::text("b")),
+            }]
+        );
+    }
+
+    #[test]
+    fn container_builder_appends_children_in_order() {
+        let built = Node::container(Layout::default())
+            .child(Node::text("a"))
+            .children([Node::text("b"), Node::text("c")])
+            .build();
+
+        assert_eq!(
+            built,
+            container(vec![Node::text("a"), Node::text("b"), Node::text("c")])
+        );
+    }
+
+    #[test]
+    fn container_builders_nest_without_building_each_level() {
+        let submit = Action::named("submit", vec![]);
+        let built: Node = Node::container(Layout::default())
+            .child(Node::form(submit.clone()).child(Node::text("field")))
+            .into();
+
+        let form = Node::Form(FormProps {
+            on_submit: submit,
+            children: vec![Node::text("field").boxed()],
+        });
+        assert_eq!(built, container(vec![form]));
+    }
+
+    #[test]
+    fn dividers_round_trip() {
+        let divider = container(vec![
+            Node::text("Above"),
+            Node::Divider(DividerProps { spacing: 12.5 }),
+            Node::text("Below"),
+        ]);
+
+        let cbor = serde_cbor::to_vec(&divider).unwrap();
+        assert_eq!(serde_cbor::from_slice::<Node>(&cbor).unwrap(), divider);
+
+        let json = serde_json::to_string(&divider).unwrap();
+        assert_eq!(serde_json::from_str::<Node>(&json).unwrap(), divider);
+    }
 }
@@ -1,6 +1,6 @@
 use std::collections::HashMap;
 
-use serde::{Deserialize, Serialize};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub enum StorageScope {
@@ -14,13 +14,31 @@ pub enum StorageScope {
     Local,
 }
 
-#[derive(Clone, Debug, Serialize, Deserialize)]
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
 pub enum StateValue {
     String(String),
     Boolean(bool),
+    Null,
+    Number(f64),
+    Array(Vec<StateValue>),
+    Object(HashMap<String, StateValue>),
+
+    /// Binary data, carried as a CBOR byte string. Only send it to peers
+    /// that advertise `network::BYTES`, since others can't decode it.
+    Bytes(#[serde(with = "crate::bytes")] Vec<u8>),
 }
 
 impl StateValue {
+    /// Converts any serializable value, going through its JSON representation.
+    pub fn from_serde<T: Serialize>(value: &T) -> Result<StateValue, serde_json::Error> {
+        Ok(serde_json::to_value(value)?.into())
+    }
+
+    /// Deserializes the value into `T`, going through its JSON representation.
+    pub fn to_serde<T: DeserializeOwned>(&self) -> Result<T, serde_json::Error> {
+        serde_json::from_value(self.clone().into())
+    }
+
     pub fn boolean(&self) -> bool {
         match self {
             StateValue::Boolean(b) => *b,
@@ -34,12 +52,323 @@ impl StateValue {
             _ => "",
         }
     }
+
+    pub fn bytes(&self) -> &[u8] {
+        match self {
+            StateValue::Bytes(bytes) => bytes,
+            _ => &[],
+        }
+    }
+
+    /// The value as storage holds it, which is as text: strings as they are
+    /// and anything else as JSON.
+    pub fn into_storage_string(self) -> String {
+        match self {
+            StateValue::String(string) => string,
+            value => serde_json::Value::from(value).to_string(),
+        }
+    }
+
+    /// This value with any `Bytes` in it turned into arrays of numbers, as
+    /// JSON would have them, for peers that don't advertise `network::BYTES`.
+    pub fn without_bytes(self) -> StateValue {
+        match self {
+            StateValue::Bytes(bytes) => StateValue::Array(
+                bytes
+                    .into_iter()
+                    .map(|byte| StateValue::Number(byte.into()))
+                    .collect(),
+            ),
+            StateValue::Array(values) => {
+                StateValue::Array(values.into_iter().map(StateValue::without_bytes).collect())
+            }
+            StateValue::Object(fields) => StateValue::Object(
+                fields
+                    .into_iter()
+                    .map(|(key, value)| (key, value.without_bytes()))
+                    .collect(),
+            ),
+            value => value,
+        }
+    }
+
+    /// The value at a dot-separated `path` such as `"user.emails.0"`, where
+    /// each segment is an object key or an array index. An empty path is the
+    /// value itself.
+    pub fn get_path(&self, path: &str) -> Option<&StateValue> {
+        if path.is_empty() {
+            return Some(self);
+        }
+
+        path.split('.')
+            .try_fold(self, |value, segment| match value {
+                StateValue::Object(fields) => fields.get(segment),
+                StateValue::Array(values) => values.get(segment.parse::<usize>().ok()?),
+                _ => None,
+            })
+    }
+
+    /// Merges `other` into this value. Where both are objects, keys only in
+    /// `other` are added and keys in both are merged in turn; anywhere else
+    /// `other` replaces what was here.
+    pub fn merge(&mut self, other: StateValue) {
+        match (self, other) {
+            (StateValue::Object(fields), StateValue::Object(other_fields)) => {
+                for (key, value) in other_fields {
+                    match fields.get_mut(&key) {
+                        Some(field) => field.merge(value),
+                        None => {
+                            fields.insert(key, value);
+                        }
+                    }
+                }
+            }
+            (this, other) => *this = other,
+        }
+    }
+}
+
+impl From<serde_json::Value> for StateValue {
+    fn from(value: serde_json::Value) -> Self {
+        match value {
+            serde_json::Value::Null => StateValue::Null,
+            serde_json::Value::Bool(b) => StateValue::Boolean(b),
+            serde_json::Value::Number(n) => StateValue::Number(n.as_f64().unwrap_or_default()),
+            serde_json::Value::String(s) => StateValue::String(s),
+            serde_json::Value::Array(values) => {
+                StateValue::Array(values.into_iter().map(StateValue::from).collect())
+            }
+            serde_json::Value::Object(fields) => StateValue::Object(
+                fields
+                    .into_iter()
+                    .map(|(key, value)| (key, value.into()))
+                    .collect(),
+            ),
+        }
+    }
+}
+
+impl From<StateValue> for serde_json::Value {
+    fn from(value: StateValue) -> Self {
+        match value {
+            StateValue::Null => serde_json::Value::Null,
+            StateValue::Boolean(b) => serde_json::Value::Bool(b),
+            // Integral numbers go back as integers so they deserialize into
+            // integer fields; NaN and infinities have no JSON form
+            StateValue::Number(n) if n.fract() == 0. && n.abs() < i64::MAX as f64 => {
+                serde_json::Value::from(n as i64)
+            }
+            StateValue::Number(n) => serde_json::Number::from_f64(n)
+                .map(serde_json::Value::Number)
+                .unwrap_or(serde_json::Value::Null),
+            StateValue::String(s) => serde_json::Value::String(s),
+            // JSON has no bytes, but an array of them deserializes into a
+            // `Vec<u8>` field
+            StateValue::Bytes(bytes) => {
+                serde_json::Value::Array(bytes.into_iter().map(serde_json::Value::from).collect())
+            }
+            StateValue::Array(values) => {
+                serde_json::Value::Array(values.into_iter().map(serde_json::Value::from).collect())
+            }
+            StateValue::Object(fields) => serde_json::Value::Object(
+                fields
+                    .into_iter()
+                    .map(|(key, value)| (key, value.into()))
+                    .collect(),
+            ),
+        }
+    }
 }
 
 impl log::kv::ToValue for StateValue {
-    fn to_value(&self) -> log::kv::Value {
+    fn to_value(&self) -> log::kv::Value<'_> {
         log::kv::Value::from_debug(self)
     }
 }
 
 pub type StateMap = HashMap<String, StateValue>;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn user() -> StateValue {
+        json!({
+            "name": "Ada",
+            "emails": ["ada@example.com", "countess@example.com"],
+            "address": { "city": "London" },
+        })
+        .into()
+    }
+
+    #[test]
+    fn get_path_follows_keys_and_indices() {
+        let user = user();
+        assert_eq!(
+            user.get_path("name"),
+            Some(&StateValue::String("Ada".into()))
+        );
+        assert_eq!(
+            user.get_path("emails.1"),
+            Some(&StateValue::String("countess@example.com".into()))
+        );
+        assert_eq!(
+            user.get_path("address.city"),
+            Some(&StateValue::String("London".into()))
+        );
+    }
+
+    #[test]
+    fn get_path_of_nothing_is_the_value_itself() {
+        let user = user();
+        assert_eq!(user.get_path(""), Some(&user));
+    }
+
+    #[test]
+    fn get_path_is_none_where_the_path_leads_nowhere() {
+        let user = user();
+        for path in [
+            "age",
+            "emails.2",
+            "emails.first",
+            "emails.-1",
+            "name.0",
+            "address.city.name",
+            "address..city",
+        ] {
+            assert_eq!(user.get_path(path), None, "{}", path);
+        }
+    }
+
+    #[test]
+    fn merge_combines_objects_key_by_key() {
+        let mut user = user();
+        user.merge(
+            json!({
+                "address": { "postcode": "W1" },
+                "verified": true,
+            })
+            .into(),
+        );
+
+        let expected: StateValue = json!({
+            "name": "Ada",
+            "emails": ["ada@example.com", "countess@example.com"],
+            "address": { "city": "London", "postcode": "W1" },
+            "verified": true,
+        })
+        .into();
+        assert_eq!(user, expected);
+    }
+
+    #[test]
+    fn merge_replaces_anything_but_objects() {
+        let mut user = user();
+        user.merge(
+            json!({
+                "name": "Augusta",
+                "emails": ["augusta@example.com"],
+                "address": null,
+            })
+            .into(),
+        );
+
+        assert_eq!(
+            user.get_path("name"),
+            Some(&StateValue::String("Augusta".into()))
+        );
+        assert_eq!(
+            user.get_path("emails"),
+            Some(&StateValue::Array(vec![StateValue::String(
+                "augusta@example.com".into()
+            )]))
+        );
+        assert_eq!(user.get_path("address"), Some(&StateValue::Null));
+
+        let mut value = StateValue::Number(1.);
+        value.merge(user.clone());
+        assert_eq!(value, user);
+    }
+
+    #[test]
+    fn without_bytes_turns_bytes_into_numbers_wherever_they_are() {
+        let value = StateValue::Object(HashMap::from([
+            ("avatar".to_string(), StateValue::Bytes(vec![0, 255])),
+            (
+                "files".to_string(),
+                StateValue::Array(vec![StateValue::Bytes(vec![7])]),
+            ),
+            ("name".to_string(), StateValue::String("Ada".to_string())),
+        ]));
+
+        let expected: StateValue = json!({
+            "avatar": [0, 255],
+            "files": [[7]],
+            "name": "Ada",
+        })
+        .into();
+        assert_eq!(value.without_bytes(), expected);
+    }
+
+    #[test]
+    fn storage_strings_are_strings_as_they_are_and_json_otherwise() {
+        assert_eq!(
+            StateValue::String("Ada".to_string()).into_storage_string(),
+            "Ada"
+        );
+        assert_eq!(StateValue::Number(3.).into_storage_string(), "3");
+        assert_eq!(
+            user().into_storage_string(),
+            serde_json::Value::from(user()).to_string()
+        );
+    }
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    struct Todo {
+        title: String,
+        done: bool,
+        tags: Vec<String>,
+        due: Option<u32>,
+    }
+
+    fn todo() -> Todo {
+        Todo {
+            title: "Milk".to_string(),
+            done: false,
+            tags: vec!["shopping".to_string()],
+            due: None,
+        }
+    }
+
+    #[test]
+    fn from_serde_goes_through_json() {
+        let expected: StateValue = json!({
+            "title": "Milk",
+            "done": false,
+            "tags": ["shopping"],
+            "due": null,
+        })
+        .into();
+        assert_eq!(StateValue::from_serde(&todo()).unwrap(), expected);
+    }
+
+    #[test]
+    fn to_serde_reverses_from_serde() {
+        let value = StateValue::from_serde(&todo()).unwrap();
+        assert_eq!(value.to_serde::<Todo>().unwrap(), todo());
+    }
+
+    #[test]
+    fn to_serde_fails_on_a_mismatched_shape() {
+        let mut value = StateValue::from_serde(&todo()).unwrap();
+        if let StateValue::Object(fields) = &mut value {
+            fields.insert("done".to_string(), StateValue::String("no".to_string()));
+        }
+        assert!(value.to_serde::<Todo>().is_err());
+
+        assert!(StateValue::String("Milk".to_string())
+            .to_serde::<Todo>()
+            .is_err());
+    }
+}
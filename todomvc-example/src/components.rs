@@ -0,0 +1,18 @@
+use pinhole::{Component, Node, Stack, Storage};
+
+/// The app title, with an optional subtitle for the current page.
+pub struct Header {
+    pub subtitle: Option<&'static str>,
+}
+
+impl Component for Header {
+    fn render(&self, storage: &Storage) -> Node {
+        let mut lines = vec![Node::text("TODO MVC")];
+
+        if let Some(subtitle) = self.subtitle {
+            lines.push(Node::text(subtitle));
+        }
+
+        Stack(lines).render(storage)
+    }
+}
@@ -1,7 +1,8 @@
+mod components;
 mod model;
 mod routes;
 
-use pinhole::{Application, Result, Route};
+use pinhole::{routes, Application, BoxedRoute, Render, Result, Storage};
 
 pub fn main() -> Result<()> {
     pinhole::run(TodoApplication, "0.0.0.0:8080")
@@ -11,7 +12,16 @@ pub fn main() -> Result<()> {
 struct TodoApplication;
 
 impl Application for TodoApplication {
-    fn routes(&self) -> Vec<Box<dyn Route>> {
-        vec![Box::new(routes::IndexRoute), Box::new(routes::ListRoute)]
+    fn routes(&self) -> Vec<BoxedRoute> {
+        routes![routes::IndexRoute, routes::ListRoute, routes::DetailRoute]
+    }
+
+    fn before_request(&self, path: &str, storage: &Storage) -> Option<Render> {
+        // Everything but the sign-in page needs a signed-in user
+        if path != "/" && storage.get("authenticated").is_none() {
+            return Some(Render::RedirectTo("/".to_string()));
+        }
+
+        None
     }
 }
@@ -3,3 +3,21 @@ pub struct Todo {
     pub text: String,
     pub done: bool,
 }
+
+impl Todo {
+    /// Stand-in for a real data store.
+    pub fn all() -> Vec<Todo> {
+        vec![
+            Todo {
+                id: "1".to_string(),
+                text: "Dishes".to_string(),
+                done: false,
+            },
+            Todo {
+                id: "2".to_string(),
+                text: "Put kid to bed".to_string(),
+                done: true,
+            },
+        ]
+    }
+}
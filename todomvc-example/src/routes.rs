@@ -1,3 +1,6 @@
+mod detail;
+pub use detail::DetailRoute;
+
 mod index;
 pub use index::IndexRoute;
 
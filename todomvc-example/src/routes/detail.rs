@@ -0,0 +1,64 @@
+use pinhole::{
+    Action, ActionResponse, Component, Context, Document, Layout, LinkProps, Node, Params, Render,
+    Result, Route, Size, Sizing, Storage,
+};
+
+use crate::{components::Header, model::Todo};
+
+pub struct DetailRoute;
+
+#[async_trait::async_trait]
+impl Route for DetailRoute {
+    fn path(&self) -> &'static str {
+        "/todos/:id"
+    }
+
+    async fn action<'a>(
+        &self,
+        action: &Action,
+        _params: &Params,
+        _context: &mut Context<'a>,
+    ) -> Result<ActionResponse> {
+        log::error!("Unknown action: {:?}", action);
+        Ok(ActionResponse::None)
+    }
+
+    async fn render<'a>(
+        &self,
+        params: &Params,
+        storage: &Storage,
+        _context: &mut Context<'a>,
+    ) -> Render {
+        let id = params.get("id").unwrap_or_default();
+
+        match Todo::all().into_iter().find(|todo| todo.id == id) {
+            Some(todo) => Render::Document(detail(storage, &todo)),
+            None => Render::RedirectTo("/todos".to_string()),
+        }
+    }
+}
+
+fn detail(storage: &Storage, todo: &Todo) -> Document {
+    let status = if todo.done { "Done" } else { "Not done" };
+
+    Document(
+        Node::container(
+            Layout::default()
+                .horizontal(Sizing::default().centred().size(Size::Fill))
+                .vertical(Sizing::default().centred().size(Size::Fill)),
+        )
+        .child(
+            Header {
+                subtitle: Some("Todo"),
+            }
+            .render(storage),
+        )
+        .child(Node::text(&todo.text))
+        .child(Node::text(status))
+        .child(Node::Link(LinkProps {
+            label: "Back".to_string(),
+            to: "/todos".to_string(),
+        }))
+        .build(),
+    )
+}
@@ -1,8 +1,10 @@
 use pinhole::{
-    Action, ButtonProps, Context, Document, InputProps, Layout, Node, Render, Result, Route, Size,
-    Sizing, Storage, StorageScope, TextProps,
+    Action, ActionResponse, ButtonProps, Component, Context, Document, InputProps, Layout, Node,
+    Params, Render, Result, Route, Size, Sizing, StateValue, Storage, StorageScope,
 };
 
+use crate::components::Header;
+
 pub struct IndexRoute;
 
 const SUBMIT_ACTION: &str = "submit";
@@ -13,65 +15,88 @@ impl Route for IndexRoute {
         "/"
     }
 
-    async fn action<'a>(&self, action: &Action, context: &mut Context<'a>) -> Result<()> {
+    fn sensitive_keys(&self) -> &'static [&'static str] {
+        &["password"]
+    }
+
+    async fn action<'a>(
+        &self,
+        action: &Action,
+        _params: &Params,
+        context: &mut Context<'a>,
+    ) -> Result<ActionResponse> {
         match action {
             Action { name, .. } if name == SUBMIT_ACTION => {
-                log::info!("Submit with state: {:?}", context.state_map);
+                let email = context.state_map.get("email").map(StateValue::string);
+                log::info!("Signing in {:?}", email);
 
                 context
                     .store(StorageScope::Session, "authenticated", "1")
                     .await?;
-                context.redirect("/todos").await?;
+                Ok(ActionResponse::Redirect("/todos".to_string()))
             }
 
-            _ => log::error!("Unknown action: {:?}", action),
+            _ => {
+                log::error!("Unknown action: {:?}", action);
+                Ok(ActionResponse::None)
+            }
         }
-
-        Ok(())
     }
 
-    async fn render(&self, storage: &Storage) -> Render {
+    async fn render<'a>(
+        &self,
+        _params: &Params,
+        storage: &Storage,
+        _context: &mut Context<'a>,
+    ) -> Render {
         if storage.get("authenticated").is_some() {
             return Render::RedirectTo("/todos".to_string());
         }
 
-        Render::Document(signin())
+        Render::Document(signin(storage))
     }
 }
 
-fn signin() -> Document {
+fn signin(storage: &Storage) -> Document {
     Document(Node::Container {
         layout: Layout::default()
             .horizontal(Sizing::default().centred().size(Size::Fixed(300.)))
             .vertical(Sizing::default().centred().size(Size::Fixed(200.))),
 
         children: vec![
-            Node::Text(TextProps {
-                text: "TODO MVC".to_string(),
-            })
-            .boxed(),
-            Node::Input(InputProps {
-                label: "Email".to_string(),
-                id: "email".to_string(),
-                password: false,
-                placeholder: Some("yourname@example.com".to_string()),
-            })
-            .boxed(),
-            Node::Input(InputProps {
-                label: "Password".to_string(),
-                id: "password".to_string(),
-                password: true,
-                placeholder: None,
-            })
-            .boxed(),
-            Node::Button(ButtonProps {
-                label: "Sign in".to_string(),
-                on_click: Action::named(
-                    SUBMIT_ACTION,
-                    vec!["email".to_string(), "password".to_string()],
-                ),
-            })
-            .boxed(),
+            Header { subtitle: None }.render(storage).boxed(),
+            Node::form(Action::named(SUBMIT_ACTION, vec![]))
+                .children([
+                    Node::Input(InputProps {
+                        label: "Email".to_string(),
+                        id: "email".to_string(),
+                        password: false,
+                        placeholder: Some("yourname@example.com".to_string()),
+                        value: None,
+                        tab_index: None,
+                        autofocus: true,
+                        on_change: None,
+                        debounce_ms: None,
+                    }),
+                    Node::Input(InputProps {
+                        label: "Password".to_string(),
+                        id: "password".to_string(),
+                        password: true,
+                        placeholder: None,
+                        value: None,
+                        tab_index: None,
+                        autofocus: false,
+                        on_change: None,
+                        debounce_ms: None,
+                    }),
+                    Node::Button(ButtonProps {
+                        label: "Sign in".to_string(),
+                        on_click: Action::named(SUBMIT_ACTION, vec![]),
+                        padding: None,
+                        disabled: false,
+                    }),
+                ])
+                .boxed(),
         ],
     })
 }
@@ -1,15 +1,14 @@
-use maplit::hashmap;
-
 use pinhole::{
-    Action, CheckboxProps, Context, Document, Layout, Node, Render, Result, Route, Size, Sizing,
-    Storage, TextProps,
+    Action, ActionResponse, ButtonProps, CheckboxProps, Component, Context, Document, Layout, Node,
+    Params, Render, Result, Route, Size, Sizing, Storage, StorageScope,
 };
 
-use crate::model::Todo;
+use crate::{components::Header, model::Todo};
 
 pub struct ListRoute;
 
 const TODO_CHECKED: &str = "checked";
+const SIGN_OUT: &str = "sign_out";
 const ID_KEY: &str = "id";
 
 #[async_trait::async_trait]
@@ -18,10 +17,15 @@ impl Route for ListRoute {
         "/todos"
     }
 
-    async fn action<'a>(&self, action: &Action, context: &mut Context<'a>) -> Result<()> {
+    async fn action<'a>(
+        &self,
+        action: &Action,
+        _params: &Params,
+        context: &mut Context<'a>,
+    ) -> Result<ActionResponse> {
         match action {
-            Action { name, args, .. } if name == TODO_CHECKED => {
-                if let Some(id) = args.get(ID_KEY) {
+            Action { name, .. } if name == TODO_CHECKED => {
+                if let Some(id) = action.arg_str(ID_KEY) {
                     if let Some(value) = context.state_map.get(id) {
                         if value.boolean() {
                             log::info!("Task {:?} checked", id);
@@ -32,62 +36,68 @@ impl Route for ListRoute {
                 }
             }
 
+            Action { name, .. } if name == SIGN_OUT => {
+                context.clear_storage(StorageScope::Session).await?;
+                return Ok(ActionResponse::Redirect("/".to_string()));
+            }
+
             _ => log::error!("Unknown action: {:?}", action),
         }
 
-        Ok(())
+        Ok(ActionResponse::None)
     }
 
-    async fn render(&self, _storage: &Storage) -> Render {
-        let todos = vec![
-            Todo {
-                id: "1".to_string(),
-                text: "Dishes".to_string(),
-                done: false,
-            },
-            Todo {
-                id: "2".to_string(),
-                text: "Put kid to bed".to_string(),
-                done: true,
-            },
-        ];
-
-        Render::Document(list(&todos))
+    async fn render<'a>(
+        &self,
+        _params: &Params,
+        storage: &Storage,
+        _context: &mut Context<'a>,
+    ) -> Render {
+        let todos = Todo::all();
+        Render::Document(list(storage, &todos))
     }
 }
 
-fn list(todos: &Vec<Todo>) -> Document {
+fn list(storage: &Storage, todos: &[Todo]) -> Document {
     Document(Node::Container {
         layout: Layout::default()
             .horizontal(Sizing::default().centred().size(Size::Fill))
             .vertical(Sizing::default().centred().size(Size::Fill)),
 
         children: vec![
-            Node::Text(TextProps {
-                text: "Your todos".to_string(),
-            })
+            Header {
+                subtitle: Some("Your todos"),
+            }
+            .render(storage)
             .boxed(),
             Node::Container {
-                layout: Layout::default(),
+                layout: Layout::default().scrollable(true),
                 children: todos
                     .iter()
                     .map(|t| {
-                        let action = Action::new(
-                            TODO_CHECKED,
-                            hashmap! { ID_KEY.to_string() => t.id.clone() },
-                            vec![t.id.clone()],
-                        );
+                        let action = Action::named(TODO_CHECKED, vec![])
+                            .arg(ID_KEY, &t.id)
+                            .capture(&t.id);
                         Node::Checkbox(CheckboxProps {
                             id: t.id.clone(),
                             label: t.text.clone(),
                             checked: t.done,
                             on_change: action,
+                            disabled: false,
+                            indeterminate: false,
                         })
                         .boxed()
                     })
                     .collect::<Vec<_>>(),
             }
             .boxed(),
+            Node::Button(ButtonProps {
+                label: "Sign out".to_string(),
+                on_click: Action::named(SIGN_OUT, vec![]),
+                padding: None,
+                disabled: false,
+            })
+            .boxed(),
         ],
     })
 }